@@ -0,0 +1,8 @@
+use clap::Parser;
+
+use client::loadtest::{run, LoadTestCli};
+
+fn main() {
+    let cli = LoadTestCli::parse();
+    run(cli);
+}
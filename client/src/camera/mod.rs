@@ -1,3 +1,4 @@
+use crate::render::killcam::KillCamTarget;
 use avian2d::position::Position;
 use bevy::core_pipeline::bloom::BloomSettings;
 use bevy::core_pipeline::tonemapping::Tonemapping;
@@ -5,7 +6,6 @@ use bevy::prelude::TransformSystem::TransformPropagate;
 use bevy::{prelude::*, window::PrimaryWindow};
 use lightyear::client::prediction::Predicted;
 use shared::player::bike::BikeMarker;
-use shared::player::death::Dead;
 
 pub const FOLLOW_CAMERA_Z: f32 = 2.0;
 pub const CAMERA_FOLLOW_SPEED: f32 = 5.0;
@@ -58,7 +58,8 @@ fn update_camera(
     time: Res<Time>,
     mut q_camera: Query<(&Camera, &mut Transform, &GlobalTransform), With<Camera2d>>,
     q_window: Query<&Window, With<PrimaryWindow>>,
-    q_player: Query<&Position, (With<BikeMarker>, With<Predicted>, Without<Dead>)>,
+    // while the kill cam is played, our bike is dead and we follow the killer instead
+    q_player: Query<&Position, (With<BikeMarker>, Or<(With<Predicted>, With<KillCamTarget>)>)>,
 ) {
     let window = q_window.single();
 
@@ -11,6 +11,7 @@ use lightyear::prelude::client::*;
 use lightyear::prelude::TickManager;
 use shared::network::inputs::PlayerMovement;
 use shared::player::bike::BikeMarker;
+use shared::player::PlayerMarker;
 
 pub struct InputPlugin;
 
@@ -33,12 +34,12 @@ impl Plugin for InputPlugin {
 
 fn add_input_map(
     mut commands: Commands,
-    // TODO: put the InputMap on the bike entity or the player entity?
+    // the InputMap is on the player entity, so that it persists across deaths
     predicted_players: Query<
         Entity,
         (
             With<Predicted>,
-            With<BikeMarker>,
+            With<PlayerMarker>,
             Without<InputMap<PlayerMovement>>,
         ),
     >,
@@ -59,9 +60,10 @@ fn capture_input(
     tick_manager: Res<TickManager>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     mut action_state_query: Query<
-        (&BikeMarker, &Position, &mut ActionState<PlayerMovement>),
-        With<Predicted>,
+        &mut ActionState<PlayerMovement>,
+        (With<PlayerMarker>, With<Predicted>),
     >,
+    bike_query: Query<&Position, (With<BikeMarker>, With<Predicted>)>,
     // query to get the window (so we can read the current cursor position)
     q_window: Query<&Window, With<PrimaryWindow>>,
     // query to get camera transform
@@ -78,7 +80,9 @@ fn capture_input(
         .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor))
         .map(|ray| ray.origin.truncate())
     {
-        if let Ok((bike, bike_pos, mut action_state)) = action_state_query.get_single_mut() {
+        if let (Ok(bike_pos), Ok(mut action_state)) =
+            (bike_query.get_single(), action_state_query.get_single_mut())
+        {
             let mouse_position_relative = world_position - bike_pos.0;
             action_state.press(&PlayerMovement::MousePositionRelative);
             action_state
@@ -1,10 +1,14 @@
+use crate::network::identity::PlayerIdentity;
 use bevy::app::{App, PluginGroup};
+use bevy::log::error;
 use clap::Parser;
 use rand::prelude::IteratorRandom;
 use rand::Rng;
 use shared::network::config::Transports;
+use shared::replay::Replay;
 use shared::SharedPlugin;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 pub mod audio;
@@ -16,7 +20,10 @@ mod ui;
 
 pub mod assets;
 mod inputs;
+#[cfg(not(target_family = "wasm"))]
+pub mod loadtest;
 mod network;
+pub mod replay;
 pub mod screen;
 
 // Use a port of 0 to automatically select a port
@@ -43,6 +50,20 @@ pub struct Cli {
 
     #[arg(short, long, value_enum, default_value_t = Transports::WebTransport)]
     transport: Transports,
+
+    /// Replay file recorded by the server, that can be watched from the title screen
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    /// Ask this matchmaker for a server to play on, instead of using the server address
+    #[cfg(not(target_family = "wasm"))]
+    #[arg(long)]
+    matchmaker: Option<SocketAddr>,
+
+    /// File that keeps the identity of the player across launches, created on the first launch
+    #[cfg(not(target_family = "wasm"))]
+    #[arg(long, default_value = "player_identity")]
+    identity_file: PathBuf,
 }
 
 pub fn app(cli: Cli) -> App {
@@ -53,16 +74,37 @@ pub fn app(cli: Cli) -> App {
     } else {
         cli.client_id
     };
+    #[cfg(not(target_family = "wasm"))]
+    app.insert_resource(PlayerIdentity::load_or_create(&cli.identity_file));
+    #[cfg(target_family = "wasm")]
+    app.insert_resource(PlayerIdentity::load_or_create());
     app.add_plugins(network::NetworkPlugin {
         client_id,
         client_port: cli.client_port,
         server_addr: SocketAddr::new(cli.server_addr.into(), cli.server_port),
         transport: cli.transport,
     });
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(addr) = cli.matchmaker {
+        app.insert_resource(network::matchmaker::Matchmaker {
+            addr,
+            client_port: cli.client_port,
+            transport: cli.transport,
+        });
+    }
     app.add_plugins(audio::plugin);
     app.add_plugins(camera::CameraPlugin);
     app.add_plugins(inputs::InputPlugin);
     app.add_plugins(screen::plugin);
     app.add_plugins(render::RenderPlugin);
+    app.add_plugins(replay::ReplayPlugin);
+    if let Some(path) = cli.replay {
+        match Replay::load(&path) {
+            Ok(replay) => {
+                app.insert_resource(replay::ReplayFile(replay));
+            }
+            Err(e) => error!("Could not load replay {:?}: {}", path, e),
+        }
+    }
     app
 }
@@ -0,0 +1,415 @@
+//! Load test: many headless clients connected to a server at the same time.
+//!
+//! Every client is a separate `App` with the lightyear client and the shared game logic, but no
+//! rendering, running in its own thread. Each one spawns a player and drives it with scripted or
+//! random inputs; at the end we report what the clients observed.
+use crate::network::config::build_lightyear_client;
+use bevy::diagnostic::{DiagnosticsPlugin, DiagnosticsStore};
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use clap::{Parser, ValueEnum};
+use leafwing_input_manager::axislike::DualAxisData;
+use leafwing_input_manager::prelude::*;
+use lightyear::client::input::leafwing::InputSystemSet;
+use lightyear::prelude::client::*;
+use lightyear::prelude::Tick;
+use lightyear::transport::io::IoDiagnosticsPlugin;
+use rand::Rng;
+use shared::network::config::{Transports, FIXED_TIMESTEP_HZ};
+use shared::network::inputs::PlayerMovement;
+use shared::network::message::{ProtocolVersionMessage, RoomChoice, SpawnPlayerMessage};
+use shared::network::protocol::{Channel1, ProtocolPlugin, ProtocolVersion};
+use shared::player::PlayerMarker;
+use shared::SharedPlugin;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Distance of the scripted mouse from the bike
+const MOUSE_DISTANCE: f32 = 100.0;
+/// Time for a scripted bike to drive a full circle
+const CIRCLE_PERIOD: f32 = 4.0;
+/// Random inputs change direction this often
+const RANDOM_TURN_INTERVAL: f32 = 0.5;
+/// The server tick is considered stalled if it doesn't advance for this long
+const STALL_THRESHOLD: Duration = Duration::from_millis(250);
+
+#[derive(Parser, PartialEq, Debug)]
+pub struct LoadTestCli {
+    /// Number of concurrent clients
+    #[arg(short, long, default_value_t = 10)]
+    pub clients: usize,
+
+    #[arg(long, default_value_t = Ipv4Addr::LOCALHOST)]
+    pub server_addr: Ipv4Addr,
+
+    #[arg(short, long, default_value_t = crate::SERVER_PORT)]
+    pub server_port: u16,
+
+    #[arg(short, long, value_enum, default_value_t = Transports::WebTransport)]
+    pub transport: Transports,
+
+    /// Duration of the test, in seconds
+    #[arg(short, long, default_value_t = 60)]
+    pub duration: u64,
+
+    /// Delay between two client connections, in milliseconds
+    #[arg(long, default_value_t = 100)]
+    pub ramp_up: u64,
+
+    #[arg(short, long, value_enum, default_value_t = Inputs::Random)]
+    pub inputs: Inputs,
+}
+
+/// How the load test clients drive their bike
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Inputs {
+    /// Drive in circles
+    Circle,
+    /// Turn in a random direction every now and then
+    Random,
+}
+
+/// What a client observed during the load test
+#[derive(Resource, Debug, Default, Clone)]
+pub struct ClientStats {
+    pub connected: bool,
+    /// Number of times the client was disconnected after connecting
+    pub disconnections: u32,
+    pub rollbacks: u32,
+    pub rollback_ticks: u32,
+    pub kb_in: f64,
+    pub kb_out: f64,
+    /// Latest server tick received, and when
+    last_server_tick: Option<(Tick, Instant)>,
+    first_server_tick_time: Option<Instant>,
+    /// Number of server ticks elapsed since the first one that we received
+    server_ticks: u64,
+    /// Longest time without a new server tick
+    pub longest_stall: Duration,
+    pub stalls: u32,
+}
+
+impl ClientStats {
+    /// Server ticks received per second
+    pub fn server_tick_rate(&self) -> Option<f64> {
+        let first_time = self.first_server_tick_time?;
+        let (_, last_time) = self.last_server_tick?;
+        let elapsed = (last_time - first_time).as_secs_f64();
+        (elapsed > 0.0).then(|| self.server_ticks as f64 / elapsed)
+    }
+}
+
+#[derive(Resource)]
+struct LoadTestInputs {
+    inputs: Inputs,
+    direction: Vec2,
+    next_turn: f32,
+}
+
+/// Run the load test and print a report
+pub fn run(cli: LoadTestCli) {
+    let server_addr = SocketAddr::new(cli.server_addr.into(), cli.server_port);
+    let duration = Duration::from_secs(cli.duration);
+    let ramp_up = Duration::from_millis(cli.ramp_up);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..cli.clients)
+        .map(|i| {
+            let transport = cli.transport;
+            let inputs = cli.inputs;
+            // every client runs for the same duration once connected
+            let delay = ramp_up * i as u32;
+            thread::spawn(move || {
+                thread::sleep(delay);
+                run_client(i, server_addr, transport, inputs, duration)
+            })
+        })
+        .collect();
+    let stats: Vec<ClientStats> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap_or_default())
+        .collect();
+    report(&stats, start.elapsed());
+}
+
+fn run_client(
+    index: usize,
+    server_addr: SocketAddr,
+    transport: Transports,
+    inputs: Inputs,
+    duration: Duration,
+) -> ClientStats {
+    let client_id = rand::thread_rng().gen::<u64>();
+    let mut app = client_app(client_id, server_addr, transport, inputs, index);
+    app.world_mut().commands().connect_client();
+    app.world_mut().flush();
+
+    let frame_duration = Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ);
+    let end = Instant::now() + duration;
+    while Instant::now() < end {
+        let frame_start = Instant::now();
+        app.update();
+        thread::sleep(frame_duration.saturating_sub(frame_start.elapsed()));
+    }
+    let stats = app.world().resource::<ClientStats>().clone();
+    app.world_mut().commands().disconnect_client();
+    app.update();
+    stats
+}
+
+fn client_app(
+    client_id: u64,
+    server_addr: SocketAddr,
+    transport: Transports,
+    inputs: Inputs,
+    index: usize,
+) -> App {
+    let mut app = App::new();
+    app.add_plugins(SharedPlugin { headless: true });
+    // the io diagnostics give the bandwidth used by the client
+    if !app.is_plugin_added::<DiagnosticsPlugin>() {
+        app.add_plugins(DiagnosticsPlugin);
+    }
+    // leafwing reads the keyboard/mouse resources
+    app.add_plugins(InputPlugin);
+    // the ClientPlugins must be added before the Protocol plugins
+    app.add_plugins(build_lightyear_client(client_id, 0, server_addr, transport));
+    app.add_plugins(ProtocolPlugin);
+
+    app.init_resource::<ClientStats>();
+    app.insert_resource(LoadTestInputs {
+        inputs,
+        direction: Vec2::X,
+        next_turn: 0.0,
+    });
+    app.insert_resource(LoadTestIndex(index));
+    app.add_systems(OnEnter(NetworkingState::Connected), spawn_player);
+    app.add_systems(OnExit(NetworkingState::Connected), count_disconnection);
+    app.add_systems(Update, (add_input_map, update_inputs, record_stats));
+    app.add_systems(
+        FixedPreUpdate,
+        apply_inputs
+            .before(InputSystemSet::BufferClientInputs)
+            .run_if(not(is_in_rollback)),
+    );
+    app.add_systems(
+        PreUpdate,
+        count_rollback
+            .after(PredictionSet::CheckRollback)
+            .before(PredictionSet::Rollback)
+            .run_if(is_in_rollback),
+    );
+    app.add_systems(FixedPreUpdate, count_rollback_tick.run_if(is_in_rollback));
+    app.finish();
+    app.cleanup();
+    app
+}
+
+#[derive(Resource)]
+struct LoadTestIndex(usize);
+
+fn spawn_player(
+    index: Res<LoadTestIndex>,
+    protocol_version: Res<ProtocolVersion>,
+    mut stats: ResMut<ClientStats>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    stats.connected = true;
+    let _ = manager.send_message::<Channel1, _>(&ProtocolVersionMessage {
+        version: protocol_version.0,
+    });
+    let _ = manager.send_message::<Channel1, _>(&SpawnPlayerMessage {
+        name: format!("Load {}", index.0),
+        room: RoomChoice::Any,
+        identity: index.0 as u128,
+    });
+}
+
+fn count_disconnection(mut stats: ResMut<ClientStats>) {
+    stats.disconnections += 1;
+}
+
+fn count_rollback(mut stats: ResMut<ClientStats>) {
+    stats.rollbacks += 1;
+}
+
+fn count_rollback_tick(mut stats: ResMut<ClientStats>) {
+    stats.rollback_ticks += 1;
+}
+
+/// Same as the client: the `InputMap` lives on the predicted player entity
+fn add_input_map(
+    mut commands: Commands,
+    predicted_players: Query<
+        Entity,
+        (
+            With<Predicted>,
+            With<PlayerMarker>,
+            Without<InputMap<PlayerMovement>>,
+        ),
+    >,
+) {
+    for entity in predicted_players.iter() {
+        commands
+            .entity(entity)
+            .insert(InputMap::<PlayerMovement>::default());
+    }
+}
+
+fn update_inputs(time: Res<Time>, mut inputs: ResMut<LoadTestInputs>) {
+    match inputs.inputs {
+        Inputs::Circle => {
+            let angle = time.elapsed_seconds() * std::f32::consts::TAU / CIRCLE_PERIOD;
+            inputs.direction = Vec2::from_angle(angle);
+        }
+        Inputs::Random => {
+            if time.elapsed_seconds() >= inputs.next_turn {
+                let angle = rand::thread_rng().gen_range(0.0..std::f32::consts::TAU);
+                inputs.direction = Vec2::from_angle(angle);
+                inputs.next_turn = time.elapsed_seconds() + RANDOM_TURN_INTERVAL;
+            }
+        }
+    }
+}
+
+fn apply_inputs(
+    inputs: Res<LoadTestInputs>,
+    mut action_states: Query<
+        &mut ActionState<PlayerMovement>,
+        (With<PlayerMarker>, With<Predicted>),
+    >,
+) {
+    for mut action_state in action_states.iter_mut() {
+        action_state.press(&PlayerMovement::MousePositionRelative);
+        action_state
+            .action_data_mut(&PlayerMovement::MousePositionRelative)
+            .unwrap()
+            .axis_pair = Some(DualAxisData::from_xy(inputs.direction * MOUSE_DISTANCE));
+    }
+}
+
+fn record_stats(
+    time: Res<Time>,
+    diagnostics: Res<DiagnosticsStore>,
+    confirmed: Query<&Confirmed>,
+    mut stats: ResMut<ClientStats>,
+) {
+    // the io diagnostics are in KB per second
+    let delta = time.delta_seconds_f64();
+    let rate = |path| {
+        diagnostics
+            .get(path)
+            .and_then(|diagnostic| diagnostic.value())
+            .unwrap_or_default()
+    };
+    stats.kb_in += rate(&IoDiagnosticsPlugin::BYTES_IN) * delta;
+    stats.kb_out += rate(&IoDiagnosticsPlugin::BYTES_OUT) * delta;
+
+    let now = Instant::now();
+    let Some(tick) = confirmed.iter().map(|confirmed| confirmed.tick).max() else {
+        return;
+    };
+    match stats.last_server_tick {
+        Some((last_tick, last_time)) if tick > last_tick => {
+            let gap = now - last_time;
+            if gap > STALL_THRESHOLD {
+                stats.stalls += 1;
+            }
+            stats.longest_stall = stats.longest_stall.max(gap);
+            stats.server_ticks += (tick - last_tick) as u64;
+            stats.last_server_tick = Some((tick, now));
+        }
+        Some(_) => {}
+        None => {
+            stats.first_server_tick_time = Some(now);
+            stats.last_server_tick = Some((tick, now));
+        }
+    }
+}
+
+fn report(stats: &[ClientStats], elapsed: Duration) {
+    println!(
+        "Load test: {} clients, {:.0}s",
+        stats.len(),
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "{:>6} {:>9} {:>8} {:>9} {:>10} {:>10} {:>10} {:>7} {:>10}",
+        "client",
+        "connected",
+        "disconn",
+        "rollbacks",
+        "rb ticks",
+        "KB in",
+        "KB out",
+        "tick/s",
+        "max stall"
+    );
+    for (i, client) in stats.iter().enumerate() {
+        println!(
+            "{:>6} {:>9} {:>8} {:>9} {:>10} {:>10.1} {:>10.1} {:>7} {:>8}ms",
+            i,
+            client.connected,
+            client.disconnections,
+            client.rollbacks,
+            client.rollback_ticks,
+            client.kb_in,
+            client.kb_out,
+            client
+                .server_tick_rate()
+                .map_or("-".to_string(), |rate| format!("{rate:.1}")),
+            client.longest_stall.as_millis(),
+        );
+    }
+
+    let connected: Vec<&ClientStats> = stats.iter().filter(|client| client.connected).collect();
+    let failures = stats.len() - connected.len();
+    println!();
+    println!("Connection failures: {failures}");
+    println!(
+        "Disconnections: {}",
+        connected
+            .iter()
+            .map(|client| client.disconnections)
+            .sum::<u32>()
+    );
+    if connected.is_empty() {
+        return;
+    }
+    let count = connected.len() as f64;
+    let average = |value: &dyn Fn(&ClientStats) -> f64| {
+        connected.iter().map(|client| value(client)).sum::<f64>() / count
+    };
+    println!(
+        "Average rollbacks per client: {:.1} ({:.1} ticks)",
+        average(&|client| client.rollbacks as f64),
+        average(&|client| client.rollback_ticks as f64)
+    );
+    println!(
+        "Average KB per client: {:.1} in, {:.1} out",
+        average(&|client| client.kb_in),
+        average(&|client| client.kb_out)
+    );
+    let tick_rates: Vec<f64> = connected
+        .iter()
+        .filter_map(|client| client.server_tick_rate())
+        .collect();
+    if !tick_rates.is_empty() {
+        println!(
+            "Server ticks per second: {:.1} on average, {:.1} at worst (expected {FIXED_TIMESTEP_HZ})",
+            tick_rates.iter().sum::<f64>() / tick_rates.len() as f64,
+            tick_rates.iter().copied().fold(f64::MAX, f64::min)
+        );
+    }
+    println!(
+        "Server stalls over {}ms: {}, the longest {}ms",
+        STALL_THRESHOLD.as_millis(),
+        connected.iter().map(|client| client.stalls).sum::<u32>(),
+        connected
+            .iter()
+            .map(|client| client.longest_stall)
+            .max()
+            .unwrap_or_default()
+            .as_millis()
+    );
+}
@@ -1,7 +1,7 @@
 //! Module to handle the networking of bikes on the client side
 
 use crate::render::label::EntityLabel;
-use crate::render::trail::TrailRenderMarker;
+use crate::render::trail::{TrailHeadMarker, TrailRenderMarker};
 use crate::render::zones::ZoneRenderMarker;
 use avian2d::prelude::{Position, RigidBody, Rotation};
 use bevy::prelude::*;
@@ -11,6 +11,7 @@ use lightyear::prelude::{client::*, MainSet};
 use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
 use shared::player::trail::Trail;
 use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
 
 use super::BikeSpawned;
 
@@ -35,31 +36,33 @@ impl Plugin for BikeNetworkPlugin {
     }
 }
 
-/// When a new trail is added, we go through all bikes to find the parent
+/// When a new trail is added, we go through all players to find the parent.
+/// Our predicted trail is attached to our predicted player.
 fn add_trail_hierarchy(
     mut commands: Commands,
-    bike: Query<(Entity, &ClientIdMarker), (With<BikeMarker>, With<Confirmed>)>,
-    trails: Query<(Entity, &ClientIdMarker), (With<Trail>, Without<Parent>)>,
+    players: Query<(Entity, &ClientIdMarker, Has<Predicted>), With<PlayerMarker>>,
+    trails: Query<(Entity, &ClientIdMarker, Has<Predicted>), (With<Trail>, Without<Parent>)>,
 ) {
-    for (trail, trail_client_id) in trails.iter() {
-        for (bike, bike_client_id) in bike.iter() {
-            if bike_client_id == trail_client_id {
-                commands.entity(bike).add_child(trail);
+    for (trail, trail_client_id, trail_predicted) in trails.iter() {
+        for (player, player_client_id, player_predicted) in players.iter() {
+            if player_client_id == trail_client_id && player_predicted == trail_predicted {
+                commands.entity(player).add_child(trail);
             }
         }
     }
 }
 
-/// When a new zones is added, we go through all bikes to find the parent
+/// When a new zones is added, we go through all players to find the parent.
+/// Our predicted zones are attached to our predicted player.
 fn add_zones_hierarchy(
     mut commands: Commands,
-    bike: Query<(Entity, &ClientIdMarker), (With<BikeMarker>, With<Confirmed>)>,
-    zones: Query<(Entity, &ClientIdMarker), (With<Zones>, Without<Parent>)>,
+    players: Query<(Entity, &ClientIdMarker, Has<Predicted>), With<PlayerMarker>>,
+    zones: Query<(Entity, &ClientIdMarker, Has<Predicted>), (With<Zones>, Without<Parent>)>,
 ) {
-    for (zones, zone_client_id) in zones.iter() {
-        for (bike, bike_client_id) in bike.iter() {
-            if bike_client_id == zone_client_id {
-                commands.entity(bike).add_child(zones);
+    for (zones, zone_client_id, zone_predicted) in zones.iter() {
+        for (player, player_client_id, player_predicted) in players.iter() {
+            if player_client_id == zone_client_id && player_predicted == zone_predicted {
+                commands.entity(player).add_child(zones);
             }
         }
     }
@@ -68,16 +71,25 @@ fn add_zones_hierarchy(
 /// When a interpolated bike gets created, we want to:
 /// - add a Player Label
 /// - trigger `BikeSpawned` to draw a mesh
+///
+/// The bike might be replicated before its player; in that case we retry on the next frame.
 fn handle_new_interpolated_bike(
     mut commands: Commands,
+    players: Query<(&ClientIdMarker, &PlayerMarker, &ColorComponent), With<Confirmed>>,
     interpolated_bikes: Query<
-        (Entity, &ColorComponent, &BikeMarker),
-        (With<Interpolated>, Without<EntityLabel>),
+        (Entity, &ClientIdMarker),
+        (With<BikeMarker>, With<Interpolated>, Without<EntityLabel>),
     >,
 ) {
-    for (entity, color_component, bike) in interpolated_bikes.iter() {
+    for (entity, client_id) in interpolated_bikes.iter() {
+        let Some((_, player, color_component)) = players
+            .iter()
+            .find(|(player_client_id, _, _)| *player_client_id == client_id)
+        else {
+            continue;
+        };
         commands.entity(entity).insert((EntityLabel {
-            text: bike.name.clone(),
+            text: player.name.clone(),
             sub_text: "".to_owned(),
             offset: Vec2::new(0.0, 60.0),
             color: color_component.overbright(4.0),
@@ -97,18 +109,25 @@ fn handle_new_interpolated_bike(
 /// - add a Player Label
 fn handle_new_predicted_bike(
     mut commands: Commands,
+    players: Query<(&ClientIdMarker, &PlayerMarker, &ColorComponent), With<Confirmed>>,
     predicted_bikes: Query<
-        (Entity, &ColorComponent, &BikeMarker),
-        (With<Predicted>, Without<EntityLabel>),
+        (Entity, &ClientIdMarker),
+        (With<BikeMarker>, With<Predicted>, Without<EntityLabel>),
     >,
 ) {
-    for (entity, color_component, bike) in predicted_bikes.iter() {
+    for (entity, client_id) in predicted_bikes.iter() {
+        let Some((_, player, color_component)) = players
+            .iter()
+            .find(|(player_client_id, _, _)| *player_client_id == client_id)
+        else {
+            continue;
+        };
         commands.entity(entity).insert((
             VisualInterpolateStatus::<Position>::default(),
             VisualInterpolateStatus::<Rotation>::default(),
             RigidBody::Kinematic,
             EntityLabel {
-                text: bike.name.clone(),
+                text: player.name.clone(),
                 sub_text: "".to_owned(),
                 offset: Vec2::new(0.0, 60.0),
                 color: color_component.overbright(4.0),
@@ -122,14 +141,21 @@ fn handle_new_predicted_bike(
     }
 }
 
-/// When a trail is replicated, add the render-related components
+/// When a trail is replicated, add the render-related components.
+/// If the trail is predicted, only the predicted trail is rendered.
 fn handle_new_trail(
     mut commands: Commands,
-    bike: Query<(&ClientIdMarker, &ColorComponent), With<BikeMarker>>,
-    new_trails: Query<(&Parent, Entity), (With<Trail>, Without<TrailRenderMarker>)>,
+    players: Query<(&ClientIdMarker, &ColorComponent), With<PlayerMarker>>,
+    new_trails: Query<
+        (&Parent, Entity, Option<&Confirmed>),
+        (With<Trail>, Without<TrailRenderMarker>),
+    >,
 ) {
-    for (parent, entity) in new_trails.iter() {
-        if let Ok((client_id, color)) = bike.get(parent.get()) {
+    for (parent, entity, confirmed) in new_trails.iter() {
+        if confirmed.is_some_and(|confirmed| confirmed.predicted.is_some()) {
+            continue;
+        }
+        if let Ok((client_id, color)) = players.get(parent.get()) {
             let trail_color: Color = color.overbright(10.0);
             let trail_z_order = ((client_id.to_bits() as f32) % 1000.0) / 10.0 + 100.0;
             commands
@@ -145,19 +171,42 @@ fn handle_new_trail(
                     0.0,
                     0.0,
                     trail_z_order,
-                )));
+                )))
+                .with_children(|parent| {
+                    parent
+                        .spawn((
+                            ShapeBundle::default(),
+                            TrailHeadMarker,
+                            NoFrustumCulling,
+                            Stroke::new(trail_color, 1.0),
+                            Name::from("TrailHead"),
+                        ))
+                        // the trail is not part of a transform hierarchy, so we also set the GlobalTransform manually
+                        .insert(GlobalTransform::from_translation(Vec3::new(
+                            0.0,
+                            0.0,
+                            trail_z_order,
+                        )));
+                });
         }
     }
 }
 
-/// When a zones entity is replicated, add the render-related components
+/// When a zones entity is replicated, add the render-related components.
+/// If the zones are predicted, only the predicted zones are rendered.
 fn handle_new_zones(
     mut commands: Commands,
-    bikes: Query<(&ClientIdMarker, &ColorComponent), With<BikeMarker>>,
-    new_zones: Query<(&Parent, Entity), (With<Zones>, Without<ZoneRenderMarker>)>,
+    players: Query<(&ClientIdMarker, &ColorComponent), With<PlayerMarker>>,
+    new_zones: Query<
+        (&Parent, Entity, Option<&Confirmed>),
+        (With<Zones>, Without<ZoneRenderMarker>),
+    >,
 ) {
-    for (parent, entity) in new_zones.iter() {
-        if let Ok((client_id, color)) = bikes.get(parent.get()) {
+    for (parent, entity, confirmed) in new_zones.iter() {
+        if confirmed.is_some_and(|confirmed| confirmed.predicted.is_some()) {
+            continue;
+        }
+        if let Ok((client_id, color)) = players.get(parent.get()) {
             // color values above 1.0 enable bloom
             let c = color.0.to_linear();
             let zone_fill_color: Color = Color::srgba(c.red, c.green, c.blue, 0.08);
@@ -5,7 +5,9 @@ use bevy::prelude::default;
 use lightyear::prelude::client::*;
 use lightyear::prelude::*;
 
-use shared::network::config::{shared_config, Transports, KEY, PROTOCOL_ID};
+use shared::network::config::{
+    shared_config, Transports, INTERPOLATION_SEND_INTERVAL_RATIO, KEY, PROTOCOL_ID,
+};
 
 pub(crate) fn build_lightyear_client(
     client_id: u64,
@@ -13,12 +15,46 @@ pub(crate) fn build_lightyear_client(
     server_addr: SocketAddr,
     transport: Transports,
 ) -> ClientPlugins {
+    let config = ClientConfig {
+        shared: shared_config(Mode::Separate),
+        net: build_net_config(client_id, client_port, server_addr, transport),
+        interpolation: InterpolationConfig {
+            delay: InterpolationDelay::default()
+                .with_send_interval_ratio(INTERPOLATION_SEND_INTERVAL_RATIO),
+            // do not do linear interpolation per component, instead we provide our own interpolation logic
+            // custom_interpolation_logic: true,
+        },
+        ..default()
+    };
+    ClientPlugins::new(config)
+}
+
+/// Connect to the server at `server_addr` as `client_id`
+pub(crate) fn build_net_config(
+    client_id: u64,
+    client_port: u16,
+    server_addr: SocketAddr,
+    transport: Transports,
+) -> NetConfig {
     let auth = Authentication::Manual {
         server_addr,
         client_id,
         private_key: KEY,
         protocol_id: PROTOCOL_ID,
     };
+    NetConfig::Netcode {
+        auth,
+        config: NetcodeConfig::default(),
+        io: build_io_config(client_port, server_addr, transport),
+    }
+}
+
+/// Transport used to reach the server at `server_addr`
+pub(crate) fn build_io_config(
+    client_port: u16,
+    server_addr: SocketAddr,
+    transport: Transports,
+) -> IoConfig {
     let client_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), client_port);
     let transport_config = match transport {
         #[cfg(not(target_family = "wasm"))]
@@ -41,19 +77,5 @@ pub(crate) fn build_lightyear_client(
         };
         io = io.with_conditioner(link_conditioner);
     }
-    let config = ClientConfig {
-        shared: shared_config(),
-        net: NetConfig::Netcode {
-            auth,
-            config: NetcodeConfig::default(),
-            io,
-        },
-        interpolation: InterpolationConfig {
-            delay: InterpolationDelay::default().with_send_interval_ratio(2.0),
-            // do not do linear interpolation per component, instead we provide our own interpolation logic
-            // custom_interpolation_logic: true,
-        },
-        ..default()
-    };
-    ClientPlugins::new(config)
+    io
 }
@@ -1,12 +1,74 @@
+use crate::network::identity::PlayerIdentity;
 use crate::screen::title::TitleScreenData;
+use crate::screen::Screen;
 use bevy::prelude::*;
 use lightyear::prelude::client::*;
-use shared::network::message::SpawnPlayerMessage;
-use shared::network::protocol::Channel1;
+use shared::network::message::{
+    ProtocolMismatchMessage, ProtocolVersionMessage, SpawnPlayerMessage, TrailSettingsMessage,
+};
+use shared::network::protocol::{Channel1, ProtocolVersion};
+use shared::player::trail::TrailSettings;
 
-/// Send message to server on connect with the player name
-pub fn on_connect(mut manager: ResMut<ConnectionManager>, name: Res<TitleScreenData>) {
+/// The server runs a different version of the game; shown on the title screen
+#[derive(Resource, Debug)]
+pub struct ProtocolMismatch;
+
+impl ProtocolMismatch {
+    #[cfg(target_family = "wasm")]
+    pub const MESSAGE: &'static str =
+        "The server runs a different version of the game: please refresh the page";
+    #[cfg(not(target_family = "wasm"))]
+    pub const MESSAGE: &'static str =
+        "The server runs a different version of the game: please update the game";
+}
+
+/// Send the protocol version first, so that the server can check that we can understand each other
+pub fn send_protocol_version(
+    mut manager: ResMut<ConnectionManager>,
+    protocol_version: Res<ProtocolVersion>,
+) {
+    let _ = manager.send_message::<Channel1, _>(&ProtocolVersionMessage {
+        version: protocol_version.0,
+    });
+}
+
+/// Send message to server on connect with the player name and the chosen room
+pub fn on_connect(
+    mut manager: ResMut<ConnectionManager>,
+    title_data: Res<TitleScreenData>,
+    identity: Res<PlayerIdentity>,
+) {
     let _ = manager.send_message::<Channel1, _>(&SpawnPlayerMessage {
-        name: name.name.clone(),
+        name: title_data.name.clone(),
+        room: title_data.room.clone(),
+        identity: identity.0,
     });
 }
+
+/// The server disconnects us right after telling us that our protocol is different
+pub fn receive_protocol_mismatch(
+    mut commands: Commands,
+    mut messages: ResMut<Events<MessageEvent<ProtocolMismatchMessage>>>,
+    protocol_version: Res<ProtocolVersion>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    for message in messages.drain() {
+        warn!(
+            client_version = protocol_version.0,
+            server_version = message.message.server_version,
+            "The server uses a different protocol version"
+        );
+        commands.insert_resource(ProtocolMismatch);
+        next_screen.set(Screen::Title);
+    }
+}
+
+/// Use the same trail settings as the server, otherwise our predicted trail diverges from it
+pub fn receive_trail_settings(
+    mut messages: ResMut<Events<MessageEvent<TrailSettingsMessage>>>,
+    mut settings: ResMut<TrailSettings>,
+) {
+    for message in messages.drain() {
+        settings.ticks_per_point = message.message.ticks_per_point;
+    }
+}
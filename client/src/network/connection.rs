@@ -0,0 +1,228 @@
+//! Connection to the server chosen on the title screen: its address and transport, the servers
+//! that we connected to recently, and the state of the connection shown to the player
+use crate::network::config::build_net_config;
+use crate::network::rooms::RoomList;
+use crate::screen::Screen;
+use crate::SERVER_PORT;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::prelude::client::*;
+use shared::network::config::Transports;
+use std::net::{IpAddr, SocketAddr};
+
+/// We give up on a connection that takes longer than this
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RECENT_SERVERS: usize = 5;
+
+pub(crate) struct ConnectionPlugin {
+    pub(crate) client_id: u64,
+    pub(crate) client_port: u16,
+    pub(crate) server_addr: SocketAddr,
+    pub(crate) transport: Transports,
+}
+
+impl Plugin for ConnectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ServerSettings {
+            address: self.server_addr.to_string(),
+            transport: self.transport,
+            recent: Vec::new(),
+            client_id: self.client_id,
+            client_port: self.client_port,
+        });
+        app.init_resource::<ConnectionStatus>();
+        app.observe(connect_to_server);
+        app.add_systems(OnEnter(NetworkingState::Connected), on_connected);
+        app.add_systems(OnEnter(NetworkingState::Disconnected), on_disconnected);
+        app.add_systems(Update, connection_timeout);
+    }
+}
+
+/// Server to connect to, edited on the title screen
+#[derive(Resource, Debug, Clone)]
+pub struct ServerSettings {
+    /// Address of the server, as typed by the player
+    pub address: String,
+    pub transport: Transports,
+    /// Servers that we connected to, the most recent first
+    pub recent: Vec<RecentServer>,
+    client_id: u64,
+    client_port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentServer {
+    pub address: String,
+    pub transport: Transports,
+}
+
+impl ServerSettings {
+    fn remember(&mut self) {
+        let server = RecentServer {
+            address: self.address.trim().to_string(),
+            transport: self.transport,
+        };
+        self.recent.retain(|recent| *recent != server);
+        self.recent.insert(0, server);
+        self.recent.truncate(MAX_RECENT_SERVERS);
+    }
+}
+
+/// State of the connection to the server, shown on the title screen
+#[derive(Resource, Debug, Default, Clone, PartialEq)]
+pub enum ConnectionStatus {
+    #[default]
+    Idle,
+    /// We start playing once connected if `play` is set; otherwise we only browse the rooms
+    Connecting {
+        addr: SocketAddr,
+        since: Duration,
+        play: bool,
+    },
+    Failed(String),
+    TimedOut(SocketAddr),
+    /// The connection dropped while we were playing
+    Lost,
+}
+
+/// Trigger this to connect to the server of the `ServerSettings`
+#[derive(Event, Debug)]
+pub struct ConnectToServer {
+    /// Start playing once connected
+    pub play: bool,
+}
+
+/// Address of the server, with the default port if there is none. Host names are only resolved
+/// natively
+fn resolve(address: &str) -> Result<SocketAddr, String> {
+    let address = address.trim();
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, SERVER_PORT));
+    }
+    #[cfg(not(target_family = "wasm"))]
+    {
+        use std::net::ToSocketAddrs;
+        let with_port = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{address}:{SERVER_PORT}")
+        };
+        if let Some(addr) = with_port
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+        {
+            return Ok(addr);
+        }
+    }
+    Err(format!("Invalid server address: {address}"))
+}
+
+fn connect_to_server(
+    trigger: Trigger<ConnectToServer>,
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<ServerSettings>,
+    state: Res<State<NetworkingState>>,
+    mut status: ResMut<ConnectionStatus>,
+    mut client_config: ResMut<ClientConfig>,
+    mut room_list: ResMut<RoomList>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let play = trigger.event().play;
+    // the state only changes once the connection started
+    if let ConnectionStatus::Connecting {
+        play: then_play, ..
+    } = status.as_mut()
+    {
+        *then_play |= play;
+        return;
+    }
+    if state.get() == &NetworkingState::Connected {
+        if play {
+            next_screen.set(Screen::Playing);
+        }
+        return;
+    }
+    let addr = match resolve(&settings.address) {
+        Ok(addr) => addr,
+        Err(error) => {
+            *status = ConnectionStatus::Failed(error);
+            room_list.refresh = false;
+            return;
+        }
+    };
+    info!("Connecting to {addr} with {:?}", settings.transport);
+    client_config.net = build_net_config(
+        settings.client_id,
+        settings.client_port,
+        addr,
+        settings.transport,
+    );
+    *status = ConnectionStatus::Connecting {
+        addr,
+        since: time.elapsed(),
+        play,
+    };
+    commands.connect_client();
+}
+
+fn on_connected(
+    mut status: ResMut<ConnectionStatus>,
+    mut settings: ResMut<ServerSettings>,
+    screen: Res<State<Screen>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let ConnectionStatus::Connecting { play, .. } = *status else {
+        return;
+    };
+    settings.remember();
+    if play && screen.get() == &Screen::Title {
+        next_screen.set(Screen::Playing);
+    }
+    *status = ConnectionStatus::Idle;
+}
+
+fn on_disconnected(
+    mut status: ResMut<ConnectionStatus>,
+    mut room_list: ResMut<RoomList>,
+    screen: Res<State<Screen>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    match *status {
+        ConnectionStatus::Connecting { addr, .. } => {
+            warn!("Could not connect to {addr}");
+            *status = ConnectionStatus::Failed(format!("Could not connect to {addr}"));
+            room_list.refresh = false;
+        }
+        // leaving the game also disconnects us
+        _ if screen.get() == &Screen::Playing
+            && !matches!(*next_screen, NextState::Pending(Screen::Title)) =>
+        {
+            warn!("Lost the connection to the server");
+            *status = ConnectionStatus::Lost;
+            next_screen.set(Screen::Title);
+        }
+        _ => {}
+    }
+}
+
+fn connection_timeout(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut status: ResMut<ConnectionStatus>,
+    mut room_list: ResMut<RoomList>,
+) {
+    let ConnectionStatus::Connecting { addr, since, .. } = *status else {
+        return;
+    };
+    if time.elapsed() - since > CONNECT_TIMEOUT {
+        warn!("The connection to {addr} timed out");
+        commands.disconnect_client();
+        *status = ConnectionStatus::TimedOut(addr);
+        room_list.refresh = false;
+    }
+}
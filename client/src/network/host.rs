@@ -0,0 +1,141 @@
+//! Host a game: the server plugins run inside the client app (lightyear's `HostServer` mode).
+//! Either other players can join on the server port, or we play offline against bots.
+use crate::screen::Screen;
+use bevy::prelude::*;
+use lightyear::prelude::client::*;
+use lightyear::prelude::server::NetConfig as ServerNetConfig;
+use lightyear::prelude::server::{ServerCommands, ServerConfig};
+use lightyear::prelude::Mode;
+use shared::network::config::Transports;
+
+pub(crate) struct HostPlugin {
+    pub(crate) client_id: u64,
+    /// Port and transport that the other players use to join a hosted game
+    pub(crate) port: u16,
+    pub(crate) transport: Transports,
+}
+
+impl Plugin for HostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(server::ServerGamePlugin);
+        app.insert_resource(LocalClientId(self.client_id));
+        app.insert_resource(HostSettings {
+            port: self.port,
+            transport: self.transport,
+        });
+        app.observe(prepare_hosting);
+        app.add_systems(
+            OnEnter(Screen::Playing),
+            start_hosting.run_if(resource_exists::<HostGame>),
+        );
+        app.add_systems(
+            OnExit(Screen::Playing),
+            stop_hosting.run_if(resource_exists::<HostGame>),
+        );
+    }
+}
+
+/// Inserted by [`StartHosting`] when we host the game instead of joining a server
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub enum HostGame {
+    /// Other players can join on the server port
+    Lan,
+    /// Fully offline game against bots: the server doesn't listen on the network
+    Practice { bots: usize },
+}
+
+/// Trigger this to host a game and enter it
+#[derive(Event, Debug)]
+pub struct StartHosting(pub HostGame);
+
+/// Why we could not host a game, shown on the title screen
+#[derive(Resource, Debug)]
+pub struct HostError(pub String);
+
+#[derive(Resource)]
+struct LocalClientId(u64);
+
+#[derive(Resource)]
+struct HostSettings {
+    port: u16,
+    transport: Transports,
+}
+
+/// Network configs of the hosted server, ready before we enter the game
+#[derive(Resource)]
+struct HostNetConfig(Vec<ServerNetConfig>);
+
+/// Network config used to join a remote server, saved while we are hosting
+#[derive(Resource)]
+struct JoinConfig {
+    client: NetConfig,
+}
+
+/// Create the transport of the hosted server (which can fail, for example if the WebTransport
+/// certificate is missing) before entering the game
+fn prepare_hosting(
+    trigger: Trigger<StartHosting>,
+    mut commands: Commands,
+    settings: Res<HostSettings>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let host_game = trigger.event().0;
+    let net = match host_game {
+        HostGame::Lan => match server::build_host_net_config(settings.port, settings.transport) {
+            Ok(net) => vec![net],
+            Err(error) => {
+                error!("Could not host a game: {error}");
+                commands.insert_resource(HostError(error));
+                return;
+            }
+        },
+        // the server doesn't listen on the network
+        HostGame::Practice { .. } => Vec::new(),
+    };
+    commands.remove_resource::<HostError>();
+    commands.insert_resource(HostNetConfig(net));
+    commands.insert_resource(host_game);
+    next_screen.set(Screen::Playing);
+}
+
+fn start_hosting(
+    mut commands: Commands,
+    host_game: Res<HostGame>,
+    client_id: Res<LocalClientId>,
+    mut host_net: ResMut<HostNetConfig>,
+    mut client_config: ResMut<ClientConfig>,
+    mut server_config: ResMut<ServerConfig>,
+) {
+    info!("Hosting a game: {:?}", *host_game);
+    // the local client talks to the server directly, without any transport
+    let client = std::mem::replace(&mut client_config.net, NetConfig::Local { id: client_id.0 });
+    let bots = match *host_game {
+        HostGame::Lan => 0,
+        HostGame::Practice { bots } => bots,
+    };
+    commands.insert_resource(server::Bots { count: bots });
+    server_config.net = std::mem::take(&mut host_net.0);
+    commands.insert_resource(JoinConfig { client });
+    client_config.shared.mode = Mode::HostServer;
+    server_config.shared.mode = Mode::HostServer;
+    // the client connects when entering the game
+    commands.start_server();
+}
+
+fn stop_hosting(
+    mut commands: Commands,
+    join_config: Option<Res<JoinConfig>>,
+    mut client_config: ResMut<ClientConfig>,
+    mut server_config: ResMut<ServerConfig>,
+) {
+    commands.stop_server();
+    commands.remove_resource::<HostGame>();
+    commands.remove_resource::<HostNetConfig>();
+    if let Some(join_config) = join_config {
+        client_config.net = join_config.client.clone();
+        client_config.shared.mode = Mode::Separate;
+        server_config.net.clear();
+        server_config.shared.mode = Mode::Separate;
+        commands.remove_resource::<JoinConfig>();
+    }
+}
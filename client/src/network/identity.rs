@@ -0,0 +1,60 @@
+//! Random token that identifies the player across launches, unlike the client id which is drawn at
+//! every launch. The server uses it to give the zones of a restored snapshot back to their owner
+use bevy::log::warn;
+use bevy::prelude::Resource;
+use rand::Rng;
+#[cfg(not(target_family = "wasm"))]
+use std::path::Path;
+
+#[cfg(target_family = "wasm")]
+const STORAGE_KEY: &str = "player_identity";
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PlayerIdentity(pub u128);
+
+impl PlayerIdentity {
+    fn parse(text: &str) -> Option<Self> {
+        u128::from_str_radix(text.trim(), 16).ok().map(Self)
+    }
+
+    fn to_hex(self) -> String {
+        format!("{:032x}", self.0)
+    }
+
+    /// Read the identity saved at `path` by a previous launch, or save a new one there
+    #[cfg(not(target_family = "wasm"))]
+    pub fn load_or_create(path: &Path) -> Self {
+        if let Some(identity) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| Self::parse(&text))
+        {
+            return identity;
+        }
+        let identity = Self(rand::thread_rng().gen());
+        if let Err(e) = std::fs::write(path, identity.to_hex()) {
+            warn!("Could not save the player identity to {path:?}: {e}");
+        }
+        identity
+    }
+
+    /// Read the identity saved in the local storage of the browser by a previous visit, or save a
+    /// new one there
+    #[cfg(target_family = "wasm")]
+    pub fn load_or_create() -> Self {
+        let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+        if let Some(identity) = storage
+            .as_ref()
+            .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten())
+            .and_then(|text| Self::parse(&text))
+        {
+            return identity;
+        }
+        let identity = Self(rand::thread_rng().gen());
+        let saved = storage
+            .is_some_and(|storage| storage.set_item(STORAGE_KEY, &identity.to_hex()).is_ok());
+        if !saved {
+            warn!("Could not save the player identity in the local storage");
+        }
+        identity
+    }
+}
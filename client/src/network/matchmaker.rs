@@ -0,0 +1,109 @@
+//! Find a server through the matchmaker instead of connecting to a fixed address
+use crate::network::config::build_io_config;
+use crate::screen::title::TitleScreenData;
+use crate::screen::Screen;
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{block_on, IoTaskPool, Task};
+use lightyear::connection::netcode::ConnectToken;
+use lightyear::prelude::client::*;
+use shared::network::config::Transports;
+use shared::network::matchmaker::{
+    send_request, transport_name, MatchmakerRequest, MatchmakerResponse,
+};
+use shared::network::message::RoomChoice;
+use shared::network::protocol::ProtocolVersion;
+use std::io;
+use std::net::SocketAddr;
+
+pub(crate) struct MatchmakerPlugin;
+
+impl Plugin for MatchmakerPlugin {
+    fn build(&self, app: &mut App) {
+        app.observe(find_server);
+        app.add_systems(
+            Update,
+            receive_server.run_if(resource_exists::<MatchmakerSearch>),
+        );
+    }
+}
+
+/// Inserted when the game was started with a matchmaker: the Play button asks it for a server
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct Matchmaker {
+    pub addr: SocketAddr,
+    pub client_port: u16,
+    pub transport: Transports,
+}
+
+/// Trigger this to ask the matchmaker for a server, and join it
+#[derive(Event, Debug)]
+pub struct FindServer;
+
+/// Request to the matchmaker in progress
+#[derive(Resource)]
+pub struct MatchmakerSearch(Task<io::Result<MatchmakerResponse>>);
+
+/// Why the matchmaker could not find a server; shown on the title screen
+#[derive(Resource, Debug)]
+pub struct MatchmakerError(pub String);
+
+fn find_server(
+    _trigger: Trigger<FindServer>,
+    mut commands: Commands,
+    matchmaker: Res<Matchmaker>,
+    protocol_version: Res<ProtocolVersion>,
+    search: Option<Res<MatchmakerSearch>>,
+) {
+    if search.is_some() {
+        return;
+    }
+    let addr = matchmaker.addr;
+    let request = MatchmakerRequest::FindServer {
+        protocol_version: protocol_version.0,
+        transport: transport_name(matchmaker.transport),
+    };
+    let task = IoTaskPool::get().spawn(async move { send_request(addr, &request) });
+    commands.insert_resource(MatchmakerSearch(task));
+    commands.remove_resource::<MatchmakerError>();
+}
+
+/// Once the matchmaker answers, connect to the server that it picked with its connect token
+fn receive_server(
+    mut commands: Commands,
+    mut search: ResMut<MatchmakerSearch>,
+    matchmaker: Res<Matchmaker>,
+    mut client_config: ResMut<ClientConfig>,
+    mut title_data: ResMut<TitleScreenData>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let Some(response) = block_on(future::poll_once(&mut search.0)) else {
+        return;
+    };
+    commands.remove_resource::<MatchmakerSearch>();
+    let error = match response {
+        Ok(MatchmakerResponse::Server {
+            addr,
+            client_id,
+            connect_token,
+        }) => match ConnectToken::try_from_bytes(&connect_token) {
+            Ok(token) => {
+                info!("The matchmaker sent us to {addr} as client {client_id}");
+                client_config.net = NetConfig::Netcode {
+                    auth: Authentication::Token(token),
+                    config: NetcodeConfig::default(),
+                    io: build_io_config(matchmaker.client_port, addr, matchmaker.transport),
+                };
+                title_data.room = RoomChoice::Any;
+                next_screen.set(Screen::Playing);
+                return;
+            }
+            Err(e) => format!("Invalid connect token: {e:?}"),
+        },
+        Ok(MatchmakerResponse::Error { reason }) => reason,
+        Ok(response) => format!("Unexpected response: {response:?}"),
+        Err(e) => format!("Could not reach the matchmaker: {e}"),
+    };
+    warn!("Matchmaking failed: {error}");
+    commands.insert_resource(MatchmakerError(error));
+}
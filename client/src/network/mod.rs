@@ -3,13 +3,23 @@ use bevy::prelude::*;
 use lightyear::prelude::client::*;
 use std::net::SocketAddr;
 
-use crate::network::connect::on_connect;
+use crate::network::connect::{
+    on_connect, receive_protocol_mismatch, receive_trail_settings, send_protocol_version,
+};
 use crate::screen::Screen::Playing;
 use shared::network::config::Transports;
 
 mod bike;
 pub(crate) mod config;
-mod connect;
+pub(crate) mod connect;
+pub mod connection;
+#[cfg(not(target_family = "wasm"))]
+pub mod host;
+pub mod identity;
+#[cfg(not(target_family = "wasm"))]
+pub mod matchmaker;
+pub mod rooms;
+mod sync;
 
 /// Plugin that handles networking
 pub(crate) struct NetworkPlugin {
@@ -29,13 +39,38 @@ impl Plugin for NetworkPlugin {
             self.server_addr,
             self.transport,
         ));
+        // the server plugins are always present, but the server only starts when hosting a game
+        #[cfg(not(target_family = "wasm"))]
+        app.add_plugins(server::build_host_server());
 
         app.add_plugins(shared::network::protocol::ProtocolPlugin);
+        #[cfg(not(target_family = "wasm"))]
+        app.add_plugins(host::HostPlugin {
+            client_id: self.client_id,
+            port: self.server_addr.port(),
+            transport: self.transport,
+        });
+        #[cfg(not(target_family = "wasm"))]
+        app.add_plugins(matchmaker::MatchmakerPlugin);
 
+        app.add_plugins(connection::ConnectionPlugin {
+            client_id: self.client_id,
+            client_port: self.client_port,
+            server_addr: self.server_addr,
+            transport: self.transport,
+        });
         app.add_plugins(bike::BikeNetworkPlugin);
+        app.add_plugins(sync::WorldSyncPlugin);
+        app.add_plugins(rooms::RoomsPlugin);
 
         app.add_systems(OnEnter(Playing), connect.run_if(not(is_connected)));
-        app.add_systems(OnEnter(NetworkingState::Connected), on_connect);
+        app.add_systems(
+            OnEnter(NetworkingState::Connected),
+            (send_protocol_version, on_connect.run_if(in_state(Playing))).chain(),
+        );
+        app.add_systems(Update, (receive_protocol_mismatch, receive_trail_settings));
+        // we can already be connected if we browsed the rooms from the title screen
+        app.add_systems(OnEnter(Playing), on_connect.run_if(is_connected));
 
         #[cfg(feature = "dev")]
         app.observe(debug_connect);
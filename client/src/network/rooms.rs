@@ -0,0 +1,102 @@
+//! Rooms of the server: the list shown on the title screen, and the room that we play in
+use crate::network::connection::ConnectToServer;
+use crate::screen::Screen;
+use bevy::prelude::*;
+use lightyear::prelude::client::*;
+use shared::network::message::{
+    RoomInfo, RoomJoinedMessage, RoomListMessage, RoomListRequest, RoomRejectedMessage,
+};
+use shared::network::protocol::Channel1;
+
+pub(crate) struct RoomsPlugin;
+
+impl Plugin for RoomsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoomList>();
+        app.add_systems(
+            Update,
+            (
+                refresh_room_list,
+                receive_room_list,
+                receive_room_joined,
+                receive_room_rejected,
+            ),
+        );
+        app.add_systems(OnExit(Screen::Playing), leave_room);
+    }
+}
+
+/// Rooms of the server, as last received
+#[derive(Resource, Default, Debug)]
+pub struct RoomList {
+    pub rooms: Vec<RoomInfo>,
+    /// Set to ask the server for the list of rooms; we connect first if needed
+    pub refresh: bool,
+}
+
+/// Room that we are playing in
+#[derive(Resource, Debug)]
+pub struct CurrentRoom {
+    pub code: String,
+    pub name: String,
+}
+
+/// Why the server refused to let us in the room we chose; shown on the title screen
+#[derive(Resource, Debug)]
+pub struct RoomError(pub String);
+
+fn refresh_room_list(
+    mut commands: Commands,
+    mut room_list: ResMut<RoomList>,
+    state: Res<State<NetworkingState>>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    if !room_list.refresh {
+        return;
+    }
+    match state.get() {
+        NetworkingState::Connected => {
+            let _ = manager.send_message::<Channel1, _>(&RoomListRequest);
+            room_list.refresh = false;
+        }
+        NetworkingState::Disconnected => commands.trigger(ConnectToServer { play: false }),
+        _ => {}
+    }
+}
+
+fn receive_room_list(
+    mut room_list: ResMut<RoomList>,
+    mut messages: ResMut<Events<MessageEvent<RoomListMessage>>>,
+) {
+    for message in messages.drain() {
+        room_list.rooms = message.message.rooms;
+    }
+}
+
+fn receive_room_joined(
+    mut commands: Commands,
+    mut messages: ResMut<Events<MessageEvent<RoomJoinedMessage>>>,
+) {
+    for message in messages.drain() {
+        let RoomJoinedMessage { code, name } = message.message;
+        info!("Joined room {name} ({code})");
+        commands.insert_resource(CurrentRoom { code, name });
+        commands.remove_resource::<RoomError>();
+    }
+}
+
+fn receive_room_rejected(
+    mut commands: Commands,
+    mut messages: ResMut<Events<MessageEvent<RoomRejectedMessage>>>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    for message in messages.drain() {
+        warn!("Could not join the room: {}", message.message.reason);
+        commands.insert_resource(RoomError(message.message.reason));
+        next_screen.set(Screen::Title);
+    }
+}
+
+fn leave_room(mut commands: Commands) {
+    commands.remove_resource::<CurrentRoom>();
+}
@@ -0,0 +1,128 @@
+//! Initial sync when joining a game in progress: the server first sends simplified outlines of the
+//! zones, which we draw until the full zones are replicated, and the progress of the sync.
+use crate::screen::Screen;
+use crate::ui::prelude::*;
+use bevy::prelude::*;
+use bevy::render::view::NoFrustumCulling;
+use bevy_prototype_lyon::prelude::*;
+use lightyear::prelude::client::*;
+use lightyear::prelude::ClientId;
+use shared::network::message::{SyncProgressMessage, ZoneOutlinesMessage};
+use shared::player::bike::ClientIdMarker;
+use shared::player::zone::Zones;
+
+const OUTLINE_COLOR: Color = Color::srgba(1.0, 1.0, 1.0, 0.3);
+
+pub(crate) struct WorldSyncPlugin;
+
+impl Plugin for WorldSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                spawn_zone_outlines,
+                despawn_zone_outlines,
+                update_sync_progress,
+            )
+                .run_if(in_state(Screen::Playing)),
+        );
+    }
+}
+
+/// Simplified outline of a zone, drawn until the zones of its owner are replicated
+#[derive(Component)]
+struct ZoneOutlineMarker(ClientId);
+
+/// Root of the label showing the progress of the initial sync
+#[derive(Component)]
+struct SyncProgressRoot;
+
+#[derive(Component)]
+struct SyncProgressText;
+
+fn spawn_zone_outlines(
+    mut commands: Commands,
+    mut messages: ResMut<Events<MessageEvent<ZoneOutlinesMessage>>>,
+) {
+    for message in messages.drain() {
+        for outline in message.message.outlines {
+            if outline.exterior.len() < 3 {
+                continue;
+            }
+            let mut path = PathBuilder::new();
+            path.move_to(outline.exterior[0]);
+            for point in outline.exterior.iter().skip(1) {
+                path.line_to(*point);
+            }
+            path.close();
+            commands.spawn((
+                ShapeBundle {
+                    path: path.build(),
+                    ..default()
+                },
+                Stroke::new(OUTLINE_COLOR, 2.0),
+                NoFrustumCulling,
+                ZoneOutlineMarker(outline.client_id),
+                StateScoped(Screen::Playing),
+                Name::from("ZoneOutline"),
+            ));
+        }
+    }
+}
+
+/// The full zones of a player replace its outlines
+fn despawn_zone_outlines(
+    mut commands: Commands,
+    new_zones: Query<&ClientIdMarker, Added<Zones>>,
+    outlines: Query<(Entity, &ZoneOutlineMarker)>,
+) {
+    for client_id in new_zones.iter() {
+        for (entity, outline) in outlines.iter() {
+            if outline.0 == client_id.0 {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
+
+fn update_sync_progress(
+    mut commands: Commands,
+    mut messages: ResMut<Events<MessageEvent<SyncProgressMessage>>>,
+    roots: Query<Entity, With<SyncProgressRoot>>,
+    mut texts: Query<&mut Text, With<SyncProgressText>>,
+) {
+    let Some(progress) = messages.drain().last().map(|message| message.message) else {
+        return;
+    };
+    if progress.synced >= progress.total {
+        for root in roots.iter() {
+            commands.entity(root).despawn_recursive();
+        }
+        return;
+    }
+    let text = format!("Loading world: {}/{}", progress.synced, progress.total);
+    if let Ok(mut label) = texts.get_single_mut() {
+        label.sections[0].value = text;
+        return;
+    }
+    commands
+        .ui_root()
+        .insert((
+            SyncProgressRoot,
+            StateScoped(Screen::Playing),
+            Name::from("SyncProgress"),
+        ))
+        .with_children(|children| {
+            children.spawn((
+                TextBundle::from_section(
+                    text,
+                    TextStyle {
+                        font_size: 24.0,
+                        color: ui_palette::LABEL_TEXT,
+                        ..default()
+                    },
+                ),
+                SyncProgressText,
+            ));
+        });
+}
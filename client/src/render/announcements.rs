@@ -0,0 +1,54 @@
+use crate::screen::Screen;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::client::events::MessageEvent;
+use shared::network::message::{AnnouncementKind, AnnouncementMessage};
+
+/// The message of the day stays longer than the other announcements
+const MOTD_DURATION: Duration = Duration::from_secs(15);
+const ANNOUNCEMENT_DURATION: Duration = Duration::from_secs(8);
+
+pub struct AnnouncementPlugin;
+
+impl Plugin for AnnouncementPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Announcements>();
+        app.add_systems(
+            Update,
+            handle_announcements.run_if(in_state(Screen::Playing)),
+        );
+        app.add_systems(OnExit(Screen::Playing), clear_announcements);
+    }
+}
+
+/// Announcements from the server that are currently displayed
+#[derive(Resource, Default)]
+pub struct Announcements {
+    pub(crate) messages: Vec<(AnnouncementMessage, Timer)>,
+}
+
+fn handle_announcements(
+    time: Res<Time>,
+    mut announcements: ResMut<Announcements>,
+    mut events: ResMut<Events<MessageEvent<AnnouncementMessage>>>,
+) {
+    for message in events.drain() {
+        let duration = match message.message.kind {
+            AnnouncementKind::Motd => MOTD_DURATION,
+            _ => ANNOUNCEMENT_DURATION,
+        };
+        announcements
+            .messages
+            .push((message.message, Timer::new(duration, TimerMode::Once)));
+    }
+    for (_, timer) in &mut announcements.messages {
+        timer.tick(time.delta());
+    }
+    announcements
+        .messages
+        .retain(|(_, timer)| !timer.finished());
+}
+
+fn clear_announcements(mut announcements: ResMut<Announcements>) {
+    announcements.messages.clear();
+}
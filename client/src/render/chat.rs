@@ -6,7 +6,8 @@ use lightyear::prelude::client::Predicted;
 use lightyear::prelude::{ClientConnectionManager, NetworkTarget};
 use shared::network::message::ChatMessage;
 use shared::network::protocol::Channel1;
-use shared::player::bike::{BikeMarker, ColorComponent};
+use shared::player::bike::ColorComponent;
+use shared::player::PlayerMarker;
 
 pub struct ChatPlugin;
 
@@ -23,6 +24,7 @@ impl Plugin for ChatPlugin {
             Update,
             (send_chat_system, handle_chat_message).run_if(in_state(Screen::Playing)),
         );
+        app.add_systems(OnExit(Screen::Playing), clear_chat);
     }
 }
 
@@ -35,18 +37,18 @@ pub struct ChatMessages {
 }
 
 /// Handles sending chat messages
-fn send_chat_system(
+pub(crate) fn send_chat_system(
     keys: Res<ButtonInput<KeyCode>>,
-    player: Query<(&ColorComponent, &BikeMarker), With<Predicted>>,
+    player: Query<(&ColorComponent, &PlayerMarker), With<Predicted>>,
     mut manager: ResMut<ClientConnectionManager>,
     mut chat: ResMut<ChatMessages>,
 ) {
     if keys.just_pressed(KeyCode::Enter) {
         if chat.open && !chat.current_message.is_empty() {
-            if let Ok((color, bike)) = player.get_single() {
+            if let Ok((color, player)) = player.get_single() {
                 let message = ChatMessage {
                     color: color.0,
-                    sender: bike.name.clone(),
+                    sender: player.name.clone(),
                     message: chat.current_message.clone(),
                 };
                 manager.send_message_to_target::<Channel1, _>(&message, NetworkTarget::All);
@@ -61,6 +63,12 @@ fn send_chat_system(
     }
 }
 
+fn clear_chat(mut chat: ResMut<ChatMessages>) {
+    chat.open = false;
+    chat.current_message.clear();
+    chat.messages.clear();
+}
+
 /// Tick timer for chat messages
 fn handle_chat_message(
     time: Res<Time>,
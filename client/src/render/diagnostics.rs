@@ -3,6 +3,8 @@ use bevy_screen_diagnostics::{Aggregate, ScreenDiagnostics, ScreenDiagnosticsPlu
 use lightyear::client::prediction::diagnostics::PredictionDiagnosticsPlugin;
 use lightyear::shared::ping::diagnostics::PingDiagnosticsPlugin;
 use lightyear::transport::io::IoDiagnosticsPlugin;
+#[cfg(feature = "compact_protocol")]
+use shared::network::compact::CompactDiagnosticsPlugin;
 
 pub struct DiagnosticsPlugin;
 
@@ -10,6 +12,8 @@ impl Plugin for DiagnosticsPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_diagnostic);
         app.add_plugins(ScreenDiagnosticsPlugin::default());
+        #[cfg(feature = "compact_protocol")]
+        app.add_plugins(CompactDiagnosticsPlugin);
     }
 }
 
@@ -54,4 +58,18 @@ fn setup_diagnostic(mut onscreen: ResMut<ScreenDiagnostics>) {
         .add("KB_out".to_string(), IoDiagnosticsPlugin::BYTES_OUT)
         .aggregate(Aggregate::Average)
         .format(|v| format!("{v:0>3.0}"));
+    // bandwidth used by the bike state and the trails, without and with the compact encoding
+    #[cfg(feature = "compact_protocol")]
+    onscreen
+        .add("KB_raw".to_string(), CompactDiagnosticsPlugin::RAW_BYTES)
+        .aggregate(Aggregate::Average)
+        .format(|v| format!("{:0>3.1}", v / 1000.0));
+    #[cfg(feature = "compact_protocol")]
+    onscreen
+        .add(
+            "KB_cmp".to_string(),
+            CompactDiagnosticsPlugin::COMPACT_BYTES,
+        )
+        .aggregate(Aggregate::Average)
+        .format(|v| format!("{:0>3.1}", v / 1000.0));
 }
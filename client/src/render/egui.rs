@@ -1,5 +1,7 @@
 //! Display UI via egui. All windows displayed must be in a single system.
 
+use crate::network::rooms::CurrentRoom;
+use crate::render::announcements::Announcements;
 use crate::render::chat::ChatMessages;
 use crate::render::kills::{KillMessages, KilledByMessageRes};
 use crate::screen::Screen::Playing;
@@ -12,10 +14,12 @@ use egui_extras::{Column, TableBuilder};
 use lightyear::client::prediction::Predicted;
 use lightyear::shared::replication::components::Controlled;
 use shared::map::MAP_SIZE;
+use shared::network::message::AnnouncementKind;
 use shared::physics::movement::{MAP_EDGE_SLOW_ZONE, TRAIL_SIZE_SLOW_START};
 use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
 use shared::player::scores::Score;
 use shared::player::trail::Trail;
+use shared::player::PlayerMarker;
 
 pub struct MyEguiPlugin;
 
@@ -23,6 +27,8 @@ const TEXT_COLOR: egui::Color32 = egui::Color32::from_rgba_premultiplied(11, 170
 
 const TITLE_COLOR: egui::Color32 = egui::Color32::from_rgba_premultiplied(22, 255, 255, 50);
 
+const WARNING_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 140, 60);
+
 pub const BG_COLOR: egui::Color32 = egui::Color32::from_rgba_premultiplied(0, 36, 42, 50);
 
 impl Plugin for MyEguiPlugin {
@@ -90,10 +96,48 @@ fn leaderboard_ui(
     killed_by: Res<KilledByMessageRes>,
     kills: Res<KillMessages>,
     mut chat: ResMut<ChatMessages>,
-    scores: Query<(&Score, &BikeMarker, &ColorComponent), With<BikeMarker>>,
+    scores: Query<(&Score, &PlayerMarker, &ColorComponent)>,
     predicted_bike: Query<&Position, (With<Predicted>, With<BikeMarker>)>,
-    controlled_trail: Query<&Trail, With<Controlled>>,
+    predicted_trail: Query<&Trail, With<Predicted>>,
+    current_room: Option<Res<CurrentRoom>>,
+    announcements: Res<Announcements>,
 ) {
+    // Room window, with the code that other players can use to join us
+    if let Some(room) = current_room {
+        egui::Window::new("Room")
+            .anchor(egui::Align2::LEFT_TOP, [30.0, 30.0])
+            .title_bar(false)
+            .resizable(false)
+            .show(egui_contexts.ctx_mut(), |ui| {
+                ui.label(
+                    RichText::new(format!("{} - code {}", room.name, room.code)).color(TEXT_COLOR),
+                );
+            });
+    }
+
+    // Announcements from the server, above everything else and distinct from the chat
+    if !announcements.messages.is_empty() {
+        egui::Window::new("Announcements")
+            .title_bar(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 30.0])
+            .max_width(500.0)
+            .show(egui_contexts.ctx_mut(), |ui| {
+                for (message, _) in &announcements.messages {
+                    let (prefix, color) = match message.kind {
+                        AnnouncementKind::Motd => ("", TITLE_COLOR),
+                        AnnouncementKind::Info => ("Server: ", egui::Color32::WHITE),
+                        AnnouncementKind::Warning => ("Warning: ", WARNING_COLOR),
+                    };
+                    ui.label(
+                        RichText::new(format!("{prefix}{}", message.text))
+                            .color(color)
+                            .font(FontId::proportional(20.0)),
+                    );
+                }
+            });
+    }
+
     // Chat window
     if !chat.messages.is_empty() {
         egui::Window::new("Chat")
@@ -176,7 +220,7 @@ fn leaderboard_ui(
                 });
         }
     }
-    if let Ok(trail) = controlled_trail.get_single() {
+    if let Ok(trail) = predicted_trail.get_single() {
         if trail.len() > TRAIL_SIZE_SLOW_START {
             egui::Window::new("SlowTrail")
                 .title_bar(false)
@@ -190,7 +234,7 @@ fn leaderboard_ui(
     // leaderboard
     let scores = scores
         .iter()
-        .sort_by::<(&Score, &BikeMarker, &ColorComponent)>(|(a, _, _), (b, _, _)| {
+        .sort_by::<(&Score, &PlayerMarker, &ColorComponent)>(|(a, _, _), (b, _, _)| {
             (b.kill_score, b.zone_score).cmp(&(a.kill_score, a.zone_score))
         })
         // .map(|(score, bike, color)| (bike.name.clone(), score.clone()))
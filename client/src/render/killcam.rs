@@ -0,0 +1,295 @@
+//! Kill cam: when we die, replay the last moments before the kill from the killer's point of view.
+//!
+//! We constantly buffer the interpolated bike positions, and the trail/zones changes (as diffs,
+//! like the server replays), for the last `DEATH_TIMER`. When we die, the buffered frames are fed
+//! into replayed bikes, trails and zones (like the replay viewer does) and the game camera follows
+//! the killer's bike.
+
+use crate::replay::ReplayScene;
+use crate::screen::Screen;
+use avian2d::prelude::{LinearVelocity, Position, Rotation};
+use bevy::prelude::*;
+use bevy::utils::{Duration, HashMap, HashSet};
+use lightyear::prelude::client::*;
+use lightyear::prelude::ClientId;
+use lightyear::shared::replication::delta::Diffable;
+use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
+use shared::player::death::DEATH_TIMER;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
+use shared::replay::{BikeFrame, ReplayEvent, ReplayFrame};
+use std::collections::VecDeque;
+
+pub struct KillCamPlugin;
+
+impl Plugin for KillCamPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KillCamBuffer>();
+        // record after the interpolation/visual interpolation have been applied
+        app.add_systems(
+            PostUpdate,
+            record_kill_cam_frame.run_if(in_state(Screen::Playing)),
+        );
+        app.add_systems(Update, play_kill_cam.run_if(in_state(Screen::Playing)));
+        app.add_systems(OnExit(Screen::Playing), clear_kill_cam);
+        app.observe(start_kill_cam);
+    }
+}
+
+/// Trigger this event to start the kill cam
+#[derive(Event)]
+pub struct StartKillCam {
+    /// The player entity that killed us
+    pub killer: Entity,
+}
+
+#[derive(Default, Clone)]
+struct KillCamState {
+    trail: Trail,
+    zones: Zones,
+}
+
+/// Rolling buffer of the last `DEATH_TIMER` of the game
+#[derive(Resource, Default)]
+pub struct KillCamBuffer {
+    /// State of the trails and zones at the time of the oldest buffered frame
+    base: HashMap<ClientId, KillCamState>,
+    /// Last recorded state, used to compute the diffs
+    last: HashMap<ClientId, KillCamState>,
+    frames: VecDeque<(Duration, ReplayFrame)>,
+}
+
+/// Marks the bike of the killer while the kill cam is played, so that the camera follows it
+#[derive(Component)]
+pub struct KillCamTarget;
+
+/// Kill cam that is currently being played
+#[derive(Resource)]
+struct KillCam {
+    killer: ClientId,
+    frames: Vec<(Duration, ReplayFrame)>,
+    /// Replayed entities, drawn by the usual render plugins
+    scene: ReplayScene,
+    /// Index of the next frame to apply
+    next_frame: usize,
+    /// Live entities that we hid while the kill cam is played
+    hidden: Vec<Entity>,
+    timer: Timer,
+}
+
+fn apply_events(state: &mut HashMap<ClientId, KillCamState>, frame: &ReplayFrame) {
+    for event in &frame.events {
+        match event {
+            ReplayEvent::Trail { client_id, diff } => {
+                state.entry(*client_id).or_default().trail.apply_diff(diff);
+            }
+            ReplayEvent::Zones { client_id, diff } => {
+                state.entry(*client_id).or_default().zones.apply_diff(diff);
+            }
+            ReplayEvent::PlayerLeft { client_id } => {
+                state.remove(client_id);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn record_kill_cam_frame(
+    time: Res<Time>,
+    mut buffer: ResMut<KillCamBuffer>,
+    bikes: Query<
+        (&ClientIdMarker, &Position, &Rotation),
+        (With<BikeMarker>, Or<(With<Interpolated>, With<Predicted>)>),
+    >,
+    trails: Query<(&ClientIdMarker, &Trail), (Changed<Trail>, With<Confirmed>)>,
+    zones: Query<(&ClientIdMarker, &Zones), (Changed<Zones>, With<Confirmed>)>,
+) {
+    let buffer = buffer.as_mut();
+    let mut events = Vec::new();
+    for (client_id, trail) in trails.iter() {
+        let last = buffer.last.entry(client_id.0).or_default();
+        events.push(ReplayEvent::Trail {
+            client_id: client_id.0,
+            diff: last.trail.diff(trail),
+        });
+        last.trail.clone_from(trail);
+    }
+    for (client_id, new_zones) in zones.iter() {
+        let last = buffer.last.entry(client_id.0).or_default();
+        events.push(ReplayEvent::Zones {
+            client_id: client_id.0,
+            diff: last.zones.diff(new_zones),
+        });
+        last.zones.clone_from(new_zones);
+    }
+    let bikes = bikes
+        .iter()
+        .map(|(client_id, position, rotation)| BikeFrame {
+            client_id: client_id.0,
+            position: position.0,
+            rotation: rotation.as_radians(),
+        })
+        .collect();
+    let now = time.elapsed();
+    buffer
+        .frames
+        .push_back((now, ReplayFrame { bikes, events }));
+
+    // drop the frames that are too old, and fold them into the base state
+    while buffer
+        .frames
+        .front()
+        .is_some_and(|(frame_time, _)| now.saturating_sub(*frame_time) > DEATH_TIMER)
+    {
+        let (_, frame) = buffer.frames.pop_front().unwrap();
+        apply_events(&mut buffer.base, &frame);
+    }
+}
+
+fn start_kill_cam(
+    trigger: Trigger<StartKillCam>,
+    mut commands: Commands,
+    buffer: Res<KillCamBuffer>,
+    killers: Query<&ClientIdMarker, With<PlayerMarker>>,
+    players: Query<(&ClientIdMarker, &PlayerMarker, &ColorComponent), With<Confirmed>>,
+) {
+    let Ok(killer) = killers.get(trigger.event().killer) else {
+        return;
+    };
+    // the buffer doesn't record who joined: spawn every player that appears in it, using the
+    // name and color of the live player, with the trails and zones of the oldest buffered frame
+    let mut client_ids = buffer.base.keys().copied().collect::<HashSet<_>>();
+    for (_, frame) in buffer.frames.iter() {
+        client_ids.extend(frame.bikes.iter().map(|bike| bike.client_id));
+        client_ids.extend(frame.events.iter().filter_map(|event| match event {
+            ReplayEvent::Trail { client_id, .. } | ReplayEvent::Zones { client_id, .. } => {
+                Some(*client_id)
+            }
+            _ => None,
+        }));
+    }
+    let mut events = Vec::new();
+    for client_id in client_ids {
+        let (name, color) = players
+            .iter()
+            .find(|(player_client_id, ..)| player_client_id.0 == client_id)
+            .map_or(
+                ("Someone".to_string(), Color::WHITE),
+                |(_, marker, color)| (marker.name.clone(), color.0),
+            );
+        events.push(ReplayEvent::PlayerJoined {
+            client_id,
+            name,
+            color,
+        });
+        if let Some(base) = buffer.base.get(&client_id) {
+            events.push(ReplayEvent::Trail {
+                client_id,
+                diff: Trail::default().diff(&base.trail),
+            });
+            events.push(ReplayEvent::Zones {
+                client_id,
+                diff: Zones::default().diff(&base.zones),
+            });
+        }
+    }
+    let mut frames: Vec<_> = buffer.frames.iter().cloned().collect();
+    if let Some((_, first)) = frames.first_mut() {
+        events.append(&mut first.events);
+        first.events = events;
+    }
+    commands.insert_resource(KillCam {
+        killer: killer.0,
+        frames,
+        scene: ReplayScene::default(),
+        next_frame: 0,
+        hidden: Vec::new(),
+        timer: Timer::new(DEATH_TIMER, TimerMode::Once),
+    });
+}
+
+fn clear_kill_cam(mut commands: Commands, mut buffer: ResMut<KillCamBuffer>) {
+    // the replayed entities are scoped to the `Playing` screen
+    commands.remove_resource::<KillCam>();
+    *buffer = KillCamBuffer::default();
+}
+
+/// Play the kill cam: feed the buffered frames into replayed bikes, trails and zones, and hide the
+/// live ones until it is over. The camera follows the killer's bike
+fn play_kill_cam(
+    mut commands: Commands,
+    time: Res<Time>,
+    kill_cam: Option<ResMut<KillCam>>,
+    mut trails: Query<&mut Trail>,
+    mut zones: Query<&mut Zones>,
+    mut bikes: Query<(&mut Position, &mut Rotation, &mut LinearVelocity)>,
+    mut live: Query<
+        (Entity, &mut Visibility),
+        Or<(With<Confirmed>, With<Predicted>, With<Interpolated>)>,
+    >,
+) {
+    let Some(mut kill_cam) = kill_cam else {
+        return;
+    };
+    let kill_cam = kill_cam.as_mut();
+    kill_cam.timer.tick(time.delta());
+    if kill_cam.timer.finished() || kill_cam.frames.is_empty() {
+        kill_cam.scene.despawn(&mut commands);
+        for entity in kill_cam.hidden.drain(..) {
+            if let Ok((_, mut visibility)) = live.get_mut(entity) {
+                *visibility = Visibility::Inherited;
+            }
+        }
+        commands.remove_resource::<KillCam>();
+        return;
+    }
+    for (entity, mut visibility) in live.iter_mut() {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+            kill_cam.hidden.push(entity);
+        }
+    }
+
+    // apply all the frames up to the current playback time
+    let playback_time = kill_cam.frames[0].0 + kill_cam.timer.elapsed();
+    let start = kill_cam.next_frame;
+    while kill_cam.next_frame < kill_cam.frames.len()
+        && kill_cam.frames[kill_cam.next_frame].0 <= playback_time
+    {
+        kill_cam.next_frame += 1;
+    }
+    let Some(current) = kill_cam.next_frame.checked_sub(1) else {
+        return;
+    };
+    let scope = StateScoped(Screen::Playing);
+    kill_cam.scene.apply_events(
+        &mut commands,
+        kill_cam.frames[start..kill_cam.next_frame]
+            .iter()
+            .enumerate()
+            .map(|(index, (_, frame))| (start + index, frame)),
+        scope.clone(),
+        &mut trails,
+        &mut zones,
+    );
+    let (frame_time, frame) = &kill_cam.frames[current];
+    let previous = current.checked_sub(1).map(|index| &kill_cam.frames[index]);
+    let frame_duration = previous.map_or(1.0, |(previous_time, _)| {
+        frame_time
+            .saturating_sub(*previous_time)
+            .as_secs_f32()
+            .max(f32::EPSILON)
+    });
+    kill_cam.scene.place_bikes(
+        &mut commands,
+        frame,
+        previous.map(|(_, previous)| previous),
+        frame_duration,
+        scope,
+        &mut bikes,
+    );
+    if let Some(bike) = kill_cam.scene.bike(kill_cam.killer) {
+        commands.entity(bike).insert(KillCamTarget);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::assets::HandleMap;
 use crate::audio::sfx::SfxKey;
+use crate::render::killcam::StartKillCam;
+use crate::screen::Screen;
 use bevy::prelude::*;
 use bevy::tasks::futures_lite::StreamExt;
 use bevy_particle_systems::{
@@ -12,6 +14,7 @@ use shared::network::message::{BikeDeathMessage, KillMessage, KilledByMessage};
 use shared::player::bike::BikeMarker;
 use shared::player::death::DEATH_TIMER;
 use shared::player::scores::Stats;
+use shared::player::PlayerMarker;
 use std::time::Duration;
 
 const KILL_MESSAGE_DURATION: Duration = Duration::from_secs(3);
@@ -34,6 +37,7 @@ impl Plugin for KillPlugin {
                 handle_death_message,
             ),
         );
+        app.add_systems(OnExit(Screen::Playing), clear_kill_messages);
     }
 }
 
@@ -49,6 +53,14 @@ pub struct KilledByMessageRes {
     pub(crate) timer: Option<Timer>,
 }
 
+fn clear_kill_messages(
+    mut kill_messages: ResMut<KillMessages>,
+    mut killed_by: ResMut<KilledByMessageRes>,
+) {
+    kill_messages.messages.clear();
+    killed_by.timer = None;
+}
+
 fn handle_death_message(
     mut commands: Commands,
     sfx_handles: Res<HandleMap<SfxKey>>,
@@ -115,18 +127,22 @@ fn handle_death_message(
 }
 
 fn handle_killed_by_message(
+    mut commands: Commands,
     time: Res<Time>,
-    players: Query<&BikeMarker, With<Confirmed>>,
+    players: Query<&PlayerMarker, With<Confirmed>>,
     mut res: ResMut<KilledByMessageRes>,
     mut messages: ResMut<Events<MessageEvent<KilledByMessage>>>,
 ) {
     for message in messages.drain() {
         let name = players
             .get(message.message.killer)
-            .map_or("Someone".to_string(), |bike| bike.name.clone());
+            .map_or("Someone".to_string(), |player| player.name.clone());
         res.message = format!("Killed by {}", name);
         res.stats = message.message.stats;
         res.timer = Some(Timer::new(DEATH_TIMER, TimerMode::Once));
+        commands.trigger(StartKillCam {
+            killer: message.message.killer,
+        });
     }
     if let Some(timer) = &mut res.timer {
         timer.tick(time.delta());
@@ -138,13 +154,13 @@ fn handle_killed_by_message(
 
 fn handle_kill_message(
     time: Res<Time>,
-    players: Query<&BikeMarker, With<Confirmed>>,
+    players: Query<&PlayerMarker, With<Confirmed>>,
     mut kills: ResMut<KillMessages>,
     mut kill_messages: ResMut<Events<MessageEvent<KillMessage>>>,
 ) {
     for message in kill_messages.drain() {
-        if let Ok(bike) = players.get(message.message.killed) {
-            let name = bike.name.clone();
+        if let Ok(player) = players.get(message.message.killed) {
+            let name = player.name.clone();
 
             kills.messages.push((
                 generate_kill_message(&name),
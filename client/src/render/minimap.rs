@@ -7,7 +7,8 @@ use bevy_egui::egui::{Pos2, Sense};
 use bevy_egui::{egui, EguiContexts};
 use lightyear::prelude::client::{Confirmed, Interpolated, Predicted};
 use shared::map::MAP_SIZE;
-use shared::player::bike::{BikeMarker, ColorComponent};
+use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
+use shared::player::PlayerMarker;
 
 pub struct MinimapPlugin;
 
@@ -23,8 +24,9 @@ impl Plugin for MinimapPlugin {
 
 fn draw_map_egui(
     mut egui_ctx: EguiContexts,
-    players: Query<
-        (&ColorComponent, &Position, &Rotation),
+    players: Query<(&ClientIdMarker, &ColorComponent), (With<PlayerMarker>, With<Confirmed>)>,
+    bikes: Query<
+        (&ClientIdMarker, &Position, &Rotation),
         (Or<(With<Interpolated>, With<Predicted>)>, With<BikeMarker>),
     >,
 ) {
@@ -50,7 +52,13 @@ fn draw_map_egui(
                 BG_COLOR,
             );
 
-            for (color, position, rotation) in players.iter() {
+            for (client_id, position, rotation) in bikes.iter() {
+                let Some((_, color)) = players
+                    .iter()
+                    .find(|(player_client_id, _)| *player_client_id == client_id)
+                else {
+                    continue;
+                };
                 // transform the position from world coordinates to the minimap size
                 let mut vec = position.0;
                 // The ui is flipped on the y axis
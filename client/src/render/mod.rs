@@ -6,9 +6,11 @@ use bevy_particle_systems::ParticleSystemPlugin;
 use bevy_prototype_lyon::prelude::ShapePlugin;
 use lightyear::prelude::client::*;
 
-mod chat;
+mod announcements;
+pub(crate) mod chat;
 mod diagnostics;
 mod egui;
+pub(crate) mod killcam;
 mod kills;
 pub mod label;
 pub mod map;
@@ -32,7 +34,9 @@ impl Plugin for RenderPlugin {
         app.add_plugins((
             diagnostics::DiagnosticsPlugin,
             chat::ChatPlugin,
+            announcements::AnnouncementPlugin,
             kills::KillPlugin,
+            killcam::KillCamPlugin,
             egui::MyEguiPlugin,
             label::EntityLabelPlugin,
             minimap::MinimapPlugin,
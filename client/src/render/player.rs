@@ -53,13 +53,14 @@ fn on_bike_spawned(
     mut commands: Commands,
     sfx_handles: Res<HandleMap<SfxKey>>,
     image_key: Res<HandleMap<ImageKey>>,
-    bike: Query<(&ColorComponent, Has<Predicted>), With<BikeMarker>>,
+    bike: Query<Has<Predicted>, With<BikeMarker>>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
 ) {
     let layout = TextureAtlasLayout::from_grid(UVec2::splat(256), 6, 6, None, None);
     let texture_atlas_handle = texture_atlas_layouts.add(layout);
     if let Some(texture) = image_key.get(&ImageKey::Moto) {
-        if let Ok((color, is_predicted)) = bike.get(trigger.event().entity) {
+        if let Ok(is_predicted) = bike.get(trigger.event().entity) {
+            let color = ColorComponent(trigger.event().color);
             let mut bike_graphics = commands.spawn((
                 BikeGraphics {
                     followed_entity: trigger.event().entity,
@@ -212,11 +213,16 @@ fn remove_bike_graphics(
 
 fn draw_bike_debug(
     mut gizmos: Gizmos,
-    query: Query<(&Position, &Rotation, &ColorComponent), (With<BikeMarker>, With<Predicted>)>,
+    query: Query<(&Position, &Rotation), (With<BikeMarker>, With<Predicted>)>,
 ) {
-    for (pos, rotation, color) in query.iter() {
+    for (pos, rotation) in query.iter() {
         trace!("Drawing bike at {:?}", pos.0);
-        gizmos.rounded_rect_2d(pos.0, rotation.as_radians(), Vec2::new(50.0, 10.0), color.0);
+        gizmos.rounded_rect_2d(
+            pos.0,
+            rotation.as_radians(),
+            Vec2::new(50.0, 10.0),
+            Color::WHITE,
+        );
     }
 }
 
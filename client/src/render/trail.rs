@@ -1,8 +1,11 @@
 //! How to draw trails
 
+use avian2d::prelude::Position;
 use bevy::prelude::TransformSystem::TransformPropagate;
 use bevy::prelude::*;
-use bevy_prototype_lyon::prelude::Path;
+use bevy_prototype_lyon::prelude::{Path, PathBuilder};
+use lightyear::prelude::client::Confirmed;
+use shared::player::bike::{BikeMarker, ClientIdMarker};
 use shared::player::trail::Trail;
 
 pub struct TrailRenderPlugin;
@@ -10,10 +13,14 @@ pub struct TrailRenderPlugin;
 #[derive(Component)]
 pub struct TrailRenderMarker;
 
+/// Child of a trail entity: live segment between the last point of the trail and the bike
+#[derive(Component)]
+pub struct TrailHeadMarker;
+
 impl Plugin for TrailRenderPlugin {
     fn build(&self, app: &mut App) {
         // update the trail path after Receive, but before rendering
-        app.add_systems(Update, update_trail_path);
+        app.add_systems(Update, (update_trail_path, update_trail_head));
     }
 }
 
@@ -26,3 +33,29 @@ fn update_trail_path(
         *path = trail.into();
     }
 }
+
+/// Points are only added to the trail every few ticks, and remote bikes are drawn at their
+/// interpolated position, so we draw the end of the trail up to the bike every frame.
+/// The segment starts from the new last point as soon as it is replicated.
+fn update_trail_head(
+    trails: Query<(&Trail, &ClientIdMarker, &Children), With<TrailRenderMarker>>,
+    // the bikes that are drawn: predicted, interpolated or replayed
+    bikes: Query<(&ClientIdMarker, &Position), (With<BikeMarker>, Without<Confirmed>)>,
+    mut heads: Query<&mut Path, With<TrailHeadMarker>>,
+) {
+    for (trail, client_id, children) in trails.iter() {
+        let Some(mut head) = children.iter().find_map(|child| heads.get_mut(*child).ok()) else {
+            continue;
+        };
+        let bike_position = bikes
+            .iter()
+            .find(|(bike_client_id, _)| *bike_client_id == client_id)
+            .map(|(_, position)| position.0);
+        let mut path = PathBuilder::new();
+        if let (Some(last_point), Some(bike_position)) = (trail.line.last(), bike_position) {
+            path.move_to(*last_point);
+            path.line_to(bike_position);
+        }
+        *head = path.build();
+    }
+}
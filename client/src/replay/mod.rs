@@ -0,0 +1,366 @@
+//! Play back a replay recorded by the server.
+//!
+//! The recorded state is fed into the same components that replication would fill
+//! (`BikeMarker`, `Position`, `Trail`, `Zones`...) so that the usual render plugins draw it.
+
+use crate::network::BikeSpawned;
+use crate::render::label::EntityLabel;
+use crate::screen::Screen;
+use avian2d::prelude::{LinearVelocity, Position, Rotation};
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use lightyear::prelude::ClientId;
+use lightyear::shared::replication::delta::Diffable;
+use shared::player::bike::{BikeBundle, ColorComponent};
+use shared::player::trail::{Trail, TrailBundle};
+use shared::player::zone::{Zones, ZonesBundle};
+use shared::player::PlayerBundle;
+use shared::replay::{Replay, ReplayEvent, ReplayFrame};
+
+pub const REPLAY_SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const FREE_CAMERA_SPEED: f32 = 1500.0;
+const FREE_CAMERA_ZOOM_SPEED: f32 = 0.1;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayPlayback>();
+        app.add_systems(OnExit(Screen::Replay), reset_playback);
+        app.add_systems(
+            Update,
+            (advance_playback, apply_playback, free_camera)
+                .chain()
+                .run_if(in_state(Screen::Replay)),
+        );
+    }
+}
+
+/// The replay that was loaded from disk, if any
+#[derive(Resource)]
+pub struct ReplayFile(pub Replay);
+
+/// Playback state of the replay
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    /// Frame that should be displayed
+    pub frame: usize,
+    pub paused: bool,
+    pub speed: f32,
+    /// Time accumulated towards the next frame
+    accumulator: f32,
+    /// Last frame whose events have been applied to the world
+    applied: Option<usize>,
+    scene: ReplayScene,
+    /// Kill events that have been played so far, for the kill feed
+    pub kills: Vec<(usize, String)>,
+}
+
+impl Default for ReplayPlayback {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            paused: false,
+            speed: 1.0,
+            accumulator: 0.0,
+            applied: None,
+            scene: ReplayScene::default(),
+            kills: Vec::new(),
+        }
+    }
+}
+
+/// Entities spawned for a recorded player
+struct ReplayPlayer {
+    name: String,
+    color: Color,
+    player: Entity,
+    trail: Entity,
+    zones: Entity,
+    bike: Option<Entity>,
+}
+
+/// Entities that show recorded frames, with the same components that replication would fill so
+/// that the usual render plugins draw them. Used by the replay viewer and by the kill cam
+#[derive(Default)]
+pub(crate) struct ReplayScene {
+    players: HashMap<ClientId, ReplayPlayer>,
+}
+
+impl ReplayScene {
+    /// Bike of a recorded player, if it is alive
+    pub(crate) fn bike(&self, client_id: ClientId) -> Option<Entity> {
+        self.players.get(&client_id).and_then(|player| player.bike)
+    }
+
+    /// Apply the events of the `frames`, given with their index. The spawned entities get the
+    /// `scope` bundle. Returns the kill messages, with the index of their frame
+    pub(crate) fn apply_events<'a>(
+        &mut self,
+        commands: &mut Commands,
+        frames: impl Iterator<Item = (usize, &'a ReplayFrame)>,
+        scope: impl Bundle + Clone,
+        trails: &mut Query<&mut Trail>,
+        zones: &mut Query<&mut Zones>,
+    ) -> Vec<(usize, String)> {
+        let mut kills = Vec::new();
+        // the entities spawned here only exist once the commands are applied,
+        // so we keep the trails/zones that haven't been inserted yet on the side
+        let mut pending_trails = HashMap::<Entity, Trail>::default();
+        let mut pending_zones = HashMap::<Entity, Zones>::default();
+        for (index, frame) in frames {
+            for event in &frame.events {
+                match event {
+                    ReplayEvent::PlayerJoined {
+                        client_id,
+                        name,
+                        color,
+                    } => {
+                        let player = commands
+                            .spawn((
+                                PlayerBundle::new(*client_id, name.clone(), *color),
+                                scope.clone(),
+                            ))
+                            .id();
+                        let trail = commands
+                            .spawn((TrailBundle::new_at(Vec2::ZERO, *client_id), scope.clone()))
+                            .id();
+                        let zones = commands
+                            .spawn((ZonesBundle::new(*client_id), scope.clone()))
+                            .id();
+                        pending_trails.insert(trail, Trail::default());
+                        pending_zones.insert(zones, Zones::default());
+                        // the render plugins use the hierarchy to find the color of trails and zones
+                        commands.entity(player).add_child(trail).add_child(zones);
+                        self.players.insert(
+                            *client_id,
+                            ReplayPlayer {
+                                name: name.clone(),
+                                color: *color,
+                                player,
+                                trail,
+                                zones,
+                                bike: None,
+                            },
+                        );
+                    }
+                    ReplayEvent::PlayerLeft { client_id } => {
+                        if let Some(player) = self.players.remove(client_id) {
+                            if let Some(bike) = player.bike {
+                                commands.entity(bike).despawn_recursive();
+                            }
+                            commands.entity(player.player).despawn_recursive();
+                        }
+                    }
+                    ReplayEvent::Trail { client_id, diff } => {
+                        if let Some(player) = self.players.get(client_id) {
+                            if let Some(trail) = pending_trails.get_mut(&player.trail) {
+                                trail.apply_diff(diff);
+                            } else if let Ok(mut trail) = trails.get_mut(player.trail) {
+                                trail.apply_diff(diff);
+                            }
+                        }
+                    }
+                    ReplayEvent::Zones { client_id, diff } => {
+                        if let Some(player) = self.players.get(client_id) {
+                            if let Some(zones) = pending_zones.get_mut(&player.zones) {
+                                zones.apply_diff(diff);
+                            } else if let Ok(mut zones) = zones.get_mut(player.zones) {
+                                zones.apply_diff(diff);
+                            }
+                        }
+                    }
+                    ReplayEvent::Kill { killer, killed, .. } => {
+                        let name = |client_id| {
+                            self.players
+                                .get(client_id)
+                                .map_or("Someone".to_string(), |player| player.name.clone())
+                        };
+                        kills.push((index, format!("{} killed {}", name(killer), name(killed))));
+                    }
+                    ReplayEvent::Chat(_) => {}
+                }
+            }
+        }
+        for (entity, trail) in pending_trails {
+            commands.entity(entity).insert(trail);
+        }
+        for (entity, zones) in pending_zones {
+            commands.entity(entity).insert(zones);
+        }
+        kills
+    }
+
+    /// Place the bikes of `frame`; bikes that are not in the frame are dead. The velocity is
+    /// computed from the `previous` frame, `frame_duration` seconds earlier
+    pub(crate) fn place_bikes(
+        &mut self,
+        commands: &mut Commands,
+        frame: &ReplayFrame,
+        previous: Option<&ReplayFrame>,
+        frame_duration: f32,
+        scope: impl Bundle + Clone,
+        bikes: &mut Query<(&mut Position, &mut Rotation, &mut LinearVelocity)>,
+    ) {
+        for (client_id, player) in self.players.iter_mut() {
+            let Some(bike_frame) = frame.bikes.iter().find(|bike| bike.client_id == *client_id)
+            else {
+                if let Some(bike) = player.bike.take() {
+                    commands.entity(bike).despawn_recursive();
+                }
+                continue;
+            };
+            let velocity = previous
+                .and_then(|previous| {
+                    previous
+                        .bikes
+                        .iter()
+                        .find(|bike| bike.client_id == *client_id)
+                })
+                .map_or(Vec2::ZERO, |previous| {
+                    (bike_frame.position - previous.position) / frame_duration
+                });
+            let rotation = Rotation::radians(bike_frame.rotation);
+            match player.bike.and_then(|bike| bikes.get_mut(bike).ok()) {
+                Some((mut position, mut bike_rotation, mut linear_velocity)) => {
+                    position.0 = bike_frame.position;
+                    *bike_rotation = rotation;
+                    linear_velocity.0 = velocity;
+                }
+                None if player.bike.is_none() => {
+                    let mut bike = BikeBundle::new_at(*client_id, bike_frame.position, default());
+                    bike.rotation = rotation;
+                    bike.linear_velocity = LinearVelocity(velocity);
+                    let color = ColorComponent(player.color);
+                    let entity = commands
+                        .spawn((
+                            bike,
+                            EntityLabel {
+                                text: player.name.clone(),
+                                sub_text: "".to_owned(),
+                                offset: Vec2::new(0.0, 60.0),
+                                color: color.overbright(4.0),
+                                ..default()
+                            },
+                            scope.clone(),
+                        ))
+                        .id();
+                    commands.trigger(BikeSpawned {
+                        entity,
+                        color: player.color,
+                    });
+                    player.bike = Some(entity);
+                }
+                // the bike was spawned this frame and is not in the world yet
+                None => {}
+            }
+        }
+    }
+
+    pub(crate) fn despawn(&mut self, commands: &mut Commands) {
+        for (_, player) in self.players.drain() {
+            if let Some(bike) = player.bike {
+                commands.entity(bike).despawn_recursive();
+            }
+            commands.entity(player.player).despawn_recursive();
+        }
+    }
+}
+
+/// Advance the current frame according to the playback speed
+fn advance_playback(
+    time: Res<Time>,
+    replay: Res<ReplayFile>,
+    mut playback: ResMut<ReplayPlayback>,
+) {
+    let last_frame = replay.0.frames.len().saturating_sub(1);
+    if playback.paused || playback.frame >= last_frame {
+        return;
+    }
+    playback.accumulator += time.delta_seconds() * playback.speed;
+    let frames = (playback.accumulator / replay.0.frame_duration) as usize;
+    playback.accumulator -= frames as f32 * replay.0.frame_duration;
+    playback.frame = (playback.frame + frames).min(last_frame);
+}
+
+/// Apply the events of every frame up to the current frame, then place the bikes
+fn apply_playback(
+    mut commands: Commands,
+    replay: Res<ReplayFile>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut trails: Query<&mut Trail>,
+    mut zones: Query<&mut Zones>,
+    mut bikes: Query<(&mut Position, &mut Rotation, &mut LinearVelocity)>,
+) {
+    let frames = &replay.0.frames;
+    if frames.is_empty() {
+        return;
+    }
+    let playback = playback.as_mut();
+    let target = playback.frame.min(frames.len() - 1);
+    if playback.applied.is_some_and(|applied| applied == target) {
+        return;
+    }
+    // when scrubbing backwards, rebuild the state from the start
+    if playback.applied.is_some_and(|applied| applied > target) {
+        playback.scene.despawn(&mut commands);
+        playback.kills.clear();
+        playback.applied = None;
+    }
+    let start = playback.applied.map_or(0, |applied| applied + 1);
+    let scope = StateScoped(Screen::Replay);
+    let kills = playback.scene.apply_events(
+        &mut commands,
+        frames.iter().enumerate().take(target + 1).skip(start),
+        scope.clone(),
+        &mut trails,
+        &mut zones,
+    );
+    playback.kills.extend(kills);
+    playback.scene.place_bikes(
+        &mut commands,
+        &frames[target],
+        target.checked_sub(1).map(|index| &frames[index]),
+        replay.0.frame_duration,
+        scope,
+        &mut bikes,
+    );
+    playback.applied = Some(target);
+}
+
+fn reset_playback(mut playback: ResMut<ReplayPlayback>) {
+    *playback = ReplayPlayback::default();
+}
+
+/// Move the camera freely with the keyboard and zoom with the mouse wheel
+fn free_camera(
+    time: Res<Time>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut q_camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+) {
+    let Ok((mut transform, mut projection)) = q_camera.get_single_mut() else {
+        return;
+    };
+    let mut direction = Vec2::ZERO;
+    if keys.any_pressed([KeyCode::KeyW, KeyCode::ArrowUp]) {
+        direction.y += 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyS, KeyCode::ArrowDown]) {
+        direction.y -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyA, KeyCode::ArrowLeft]) {
+        direction.x -= 1.0;
+    }
+    if keys.any_pressed([KeyCode::KeyD, KeyCode::ArrowRight]) {
+        direction.x += 1.0;
+    }
+    let movement =
+        direction.normalize_or_zero() * FREE_CAMERA_SPEED * projection.scale * time.delta_seconds();
+    transform.translation += movement.extend(0.0);
+    for event in wheel.read() {
+        projection.scale =
+            (projection.scale * (1.0 - event.y * FREE_CAMERA_ZOOM_SPEED)).clamp(0.25, 8.0);
+    }
+}
@@ -3,6 +3,7 @@
 mod credits;
 mod loading;
 mod playing;
+mod replay;
 mod splash;
 pub mod title;
 
@@ -20,6 +21,7 @@ pub fn plugin(app: &mut App) {
         title::plugin,
         credits::plugin,
         playing::plugin,
+        replay::plugin,
     ));
 
     #[cfg(feature = "dev")]
@@ -35,4 +37,6 @@ pub enum Screen {
     Title,
     Credits,
     Playing,
+    /// Watching a replay recorded by the server
+    Replay,
 }
@@ -1,33 +1,143 @@
 //! The screen state for the main game loop.
 
 use super::Screen;
+use crate::audio::sfx::{PlaySfx, SfxKey};
 use crate::audio::soundtrack::{PlaySoundtrack, SoundtrackKey};
+use crate::render::chat::{send_chat_system, ChatMessages};
+use bevy::audio::Volume;
+use bevy::window::{PrimaryWindow, WindowMode};
 use bevy::{input::common_conditions::input_just_pressed, prelude::*};
-use lightyear::prelude::client::ClientCommands;
+use bevy_egui::{egui, EguiContexts};
+use lightyear::prelude::client::{ClientCommands, Confirmed, Interpolated, Predicted};
 use shared::map::SpawnMap;
 
 pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<GameMenu>();
     app.add_systems(OnEnter(Screen::Playing), enter_playing);
-    app.add_systems(OnExit(Screen::Playing), exit_playing);
+    app.add_systems(
+        OnExit(Screen::Playing),
+        (exit_playing, despawn_replicated_entities),
+    );
 
-    // app.add_systems(
-    //     Update,
-    //     return_to_title_screen
-    //         .run_if(in_state(Screen::Playing).and_then(input_just_pressed(KeyCode::Escape))),
-    // );
+    app.add_systems(
+        Update,
+        (
+            // Escape closes the chat first
+            toggle_game_menu
+                .run_if(input_just_pressed(KeyCode::Escape))
+                .before(send_chat_system),
+            game_menu.run_if(|menu: Res<GameMenu>| menu.open),
+        )
+            .run_if(in_state(Screen::Playing)),
+    );
+    app.observe(leave_game);
 }
 
+/// In-game menu, opened with Escape. The game keeps running behind it
+#[derive(Resource, Default, Debug)]
+pub struct GameMenu {
+    pub open: bool,
+}
+
+/// Trigger this to disconnect from the server and go back to the title screen
+#[derive(Event, Debug)]
+pub struct LeaveGame;
+
+/// The network plugin connects to the server if we are not connected yet
 fn enter_playing(mut commands: Commands) {
-    commands.connect_client();
     commands.trigger(SpawnMap);
     commands.trigger(PlaySoundtrack::Key(SoundtrackKey::Gameplay));
 }
 
-fn exit_playing(mut commands: Commands) {
+fn exit_playing(mut commands: Commands, mut menu: ResMut<GameMenu>) {
     // We could use [`StateScoped`] on the sound playing entities instead.
     commands.trigger(PlaySoundtrack::Disable);
+    menu.open = false;
+}
+
+/// Remove everything that the server replicated to us, so that the next game starts from scratch.
+/// The children (trails, zones, labels...) are removed with their parent
+fn despawn_replicated_entities(
+    mut commands: Commands,
+    replicated: Query<
+        Entity,
+        (
+            Or<(With<Confirmed>, With<Predicted>, With<Interpolated>)>,
+            Without<Parent>,
+        ),
+    >,
+) {
+    for entity in replicated.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn toggle_game_menu(mut menu: ResMut<GameMenu>, chat: Res<ChatMessages>) {
+    if !chat.open {
+        menu.open = !menu.open;
+    }
 }
 
-fn return_to_title_screen(mut next_screen: ResMut<NextState<Screen>>) {
+fn leave_game(
+    _trigger: Trigger<LeaveGame>,
+    mut commands: Commands,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    info!("Leaving the game");
+    commands.disconnect_client();
     next_screen.set(Screen::Title);
 }
+
+fn game_menu(
+    mut commands: Commands,
+    mut egui_contexts: EguiContexts,
+    mut menu: ResMut<GameMenu>,
+    mut global_volume: ResMut<GlobalVolume>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    #[cfg(not(target_family = "wasm"))] mut app_exit: EventWriter<AppExit>,
+) {
+    egui::Window::new("Menu")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
+                ui.style_mut().spacing.item_spacing = egui::Vec2::new(0.0, 20.0);
+                if ui.button("Resume").clicked() {
+                    commands.trigger(PlaySfx::Key(SfxKey::ButtonPress));
+                    menu.open = false;
+                }
+
+                ui.collapsing("Settings", |ui| {
+                    ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 10.0);
+                    // the volume applies to the sounds played from now on
+                    let mut volume = global_volume.volume.get();
+                    let slider = egui::Slider::new(&mut volume, 0.0..=1.0).text("Volume");
+                    if ui.add(slider).changed() {
+                        global_volume.volume = Volume::new(volume);
+                    }
+                    if let Ok(mut window) = windows.get_single_mut() {
+                        let mut fullscreen = window.mode != WindowMode::Windowed;
+                        if ui.checkbox(&mut fullscreen, "Fullscreen").changed() {
+                            window.mode = if fullscreen {
+                                WindowMode::BorderlessFullscreen
+                            } else {
+                                WindowMode::Windowed
+                            };
+                        }
+                    }
+                });
+
+                if ui.button("Leave game").clicked() {
+                    commands.trigger(PlaySfx::Key(SfxKey::ButtonPress));
+                    commands.trigger(LeaveGame);
+                }
+
+                // exit doesn't work well in embedded applications
+                #[cfg(not(target_family = "wasm"))]
+                if ui.button("Quit").clicked() {
+                    app_exit.send(AppExit::Success);
+                }
+            });
+        });
+}
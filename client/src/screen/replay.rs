@@ -0,0 +1,81 @@
+//! The screen state to watch a replay recorded by the server.
+
+use super::Screen;
+use crate::replay::{ReplayFile, ReplayPlayback, REPLAY_SPEEDS};
+use bevy::prelude::*;
+use bevy_egui::egui::{FontId, RichText};
+use bevy_egui::{egui, EguiContexts};
+
+/// How long kills stay in the replay kill feed, in seconds
+const KILL_FEED_DURATION: f32 = 5.0;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (replay_ui, toggle_pause).run_if(in_state(Screen::Replay)),
+    );
+}
+
+fn toggle_pause(keys: Res<ButtonInput<KeyCode>>, mut playback: ResMut<ReplayPlayback>) {
+    if keys.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+}
+
+/// Timeline with scrubbing and speed control
+fn replay_ui(
+    mut egui_contexts: EguiContexts,
+    replay: Res<ReplayFile>,
+    mut playback: ResMut<ReplayPlayback>,
+    mut next_screen: ResMut<NextState<Screen>>,
+) {
+    let frame_duration = replay.0.frame_duration;
+    let last_frame = replay.0.frames.len().saturating_sub(1);
+    egui::Window::new("Replay")
+        .title_bar(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -20.0])
+        .show(egui_contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                let label = if playback.paused { "Play" } else { "Pause" };
+                if ui.button(label).clicked() {
+                    playback.paused = !playback.paused;
+                }
+                ui.label(format!(
+                    "{:.1}s / {:.1}s",
+                    playback.frame as f32 * frame_duration,
+                    replay.0.duration()
+                ));
+                ui.style_mut().spacing.slider_width = 500.0;
+                ui.add(egui::Slider::new(&mut playback.frame, 0..=last_frame).show_value(false));
+                for speed in REPLAY_SPEEDS {
+                    let selected = playback.speed == speed;
+                    if ui.selectable_label(selected, format!("{speed}x")).clicked() {
+                        playback.speed = speed;
+                    }
+                }
+                if ui.button("Back").clicked() {
+                    next_screen.set(Screen::Title);
+                }
+            });
+            ui.label("WASD/arrows to move the camera, mouse wheel to zoom, space to pause");
+        });
+
+    let frame = playback.frame;
+    let kill_feed_frames = (KILL_FEED_DURATION / frame_duration) as usize;
+    let recent_kills = playback
+        .kills
+        .iter()
+        .filter(|(kill_frame, _)| *kill_frame <= frame && frame - kill_frame < kill_feed_frames)
+        .collect::<Vec<_>>();
+    if !recent_kills.is_empty() {
+        egui::Window::new("ReplayKills")
+            .title_bar(false)
+            .anchor(egui::Align2::RIGHT_TOP, [-30.0, 30.0])
+            .show(egui_contexts.ctx_mut(), |ui| {
+                for (_, message) in recent_kills {
+                    ui.label(RichText::new(message).font(FontId::proportional(16.0)));
+                }
+            });
+    }
+}
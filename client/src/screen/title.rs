@@ -2,16 +2,35 @@
 
 use super::Screen;
 use crate::audio::sfx::{PlaySfx, SfxKey};
+use crate::network::connect::ProtocolMismatch;
+use crate::network::connection::{ConnectToServer, ConnectionStatus, ServerSettings};
+#[cfg(not(target_family = "wasm"))]
+use crate::network::host::{HostError, HostGame, StartHosting};
+#[cfg(not(target_family = "wasm"))]
+use crate::network::matchmaker::{FindServer, Matchmaker, MatchmakerError, MatchmakerSearch};
+use crate::network::rooms::{RoomError, RoomList};
+use crate::replay::ReplayFile;
 use crate::ui::prelude::*;
 use bevy::prelude::*;
 use bevy_egui::egui::Margin;
 use bevy_egui::{egui, EguiContexts};
-use clap::Command;
+use clap::{Command, ValueEnum};
+use lightyear::prelude::client::{ClientCommands, NetworkingState};
+#[cfg(not(target_family = "wasm"))]
+use server::MAX_BOTS;
+use shared::network::config::Transports;
+use shared::network::message::RoomChoice;
+
+const DEFAULT_BOTS: usize = 3;
 
 pub(super) fn plugin(app: &mut App) {
     app.insert_resource(TitleScreenData {
         name: "".to_string(),
         hovered: false,
+        bots: DEFAULT_BOTS,
+        room: RoomChoice::Any,
+        room_code: "".to_string(),
+        room_password: "".to_string(),
     });
     app.add_systems(Update, title.run_if(in_state(Screen::Title)));
 }
@@ -20,6 +39,12 @@ pub(super) fn plugin(app: &mut App) {
 pub struct TitleScreenData {
     pub name: String,
     hovered: bool,
+    /// Number of bots in practice mode
+    bots: usize,
+    /// Room to join when the game starts
+    pub room: RoomChoice,
+    room_code: String,
+    room_password: String,
 }
 
 fn title(
@@ -27,6 +52,17 @@ fn title(
     mut egui_contexts: EguiContexts,
     mut title_data: ResMut<TitleScreenData>,
     mut next_screen: ResMut<NextState<Screen>>,
+    replay: Option<Res<ReplayFile>>,
+    mut room_list: ResMut<RoomList>,
+    room_error: Option<Res<RoomError>>,
+    protocol_mismatch: Option<Res<ProtocolMismatch>>,
+    network_state: Res<State<NetworkingState>>,
+    mut server_settings: ResMut<ServerSettings>,
+    connection_status: Res<ConnectionStatus>,
+    #[cfg(not(target_family = "wasm"))] matchmaker: Option<Res<Matchmaker>>,
+    #[cfg(not(target_family = "wasm"))] matchmaker_search: Option<Res<MatchmakerSearch>>,
+    #[cfg(not(target_family = "wasm"))] matchmaker_error: Option<Res<MatchmakerError>>,
+    #[cfg(not(target_family = "wasm"))] host_error: Option<Res<HostError>>,
     #[cfg(not(target_family = "wasm"))] mut app_exit: EventWriter<AppExit>,
 ) {
     let handle_button =
@@ -66,10 +102,234 @@ fn title(
                         .hint_text("Enter your name"),
                 );
 
-                let play = ui.button("Play");
+                if protocol_mismatch.is_some() {
+                    ui.colored_label(egui::Color32::LIGHT_RED, ProtocolMismatch::MESSAGE);
+                }
+                if let Some(error) = room_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error.0.as_str());
+                }
+
+                // with a matchmaker, the server is only known once it answers
+                #[cfg(not(target_family = "wasm"))]
+                let use_matchmaker = matchmaker.is_some();
+                #[cfg(target_family = "wasm")]
+                let use_matchmaker = false;
+                #[cfg(not(target_family = "wasm"))]
+                let searching = matchmaker_search.is_some();
+                #[cfg(target_family = "wasm")]
+                let searching = false;
+                #[cfg(not(target_family = "wasm"))]
+                if let Some(error) = matchmaker_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error.0.as_str());
+                }
+                #[cfg(not(target_family = "wasm"))]
+                if let Some(error) = host_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error.0.as_str());
+                }
+                if searching {
+                    ui.label("Finding a server...");
+                }
+
+                // the server to play on, unless the matchmaker picks it
+                if !use_matchmaker {
+                    let mut server_changed = false;
+                    ui.horizontal(|ui| {
+                        ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 0.0);
+                        server_changed |= ui
+                            .add(
+                                egui::TextEdit::singleline(&mut server_settings.address)
+                                    .desired_width(200.0)
+                                    .hint_text("Server address"),
+                            )
+                            .changed();
+                        egui::ComboBox::from_id_source("transport")
+                            .selected_text(transport_label(server_settings.transport))
+                            .show_ui(ui, |ui| {
+                                for transport in Transports::value_variants() {
+                                    server_changed |= ui
+                                        .selectable_value(
+                                            &mut server_settings.transport,
+                                            *transport,
+                                            transport_label(*transport),
+                                        )
+                                        .changed();
+                                }
+                            });
+                    });
+                    if !server_settings.recent.is_empty() {
+                        ui.collapsing("Recent servers", |ui| {
+                            ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 10.0);
+                            for recent in server_settings.recent.clone() {
+                                let label = format!(
+                                    "{} ({})",
+                                    recent.address,
+                                    transport_label(recent.transport)
+                                );
+                                if ui.button(label).clicked() {
+                                    server_settings.address = recent.address;
+                                    server_settings.transport = recent.transport;
+                                    server_changed = true;
+                                }
+                            }
+                        });
+                    }
+                    // the rooms that we browsed belong to the previous server
+                    if server_changed && network_state.get() == &NetworkingState::Connected {
+                        commands.disconnect_client();
+                        room_list.rooms.clear();
+                    }
+
+                    match connection_status.as_ref() {
+                        ConnectionStatus::Idle => {}
+                        ConnectionStatus::Connecting { addr, .. } => {
+                            ui.label(format!("Connecting to {addr}..."));
+                        }
+                        status => {
+                            let error = match status {
+                                ConnectionStatus::Failed(error) => error.clone(),
+                                ConnectionStatus::TimedOut(addr) => {
+                                    format!("The connection to {addr} timed out")
+                                }
+                                _ => "The connection to the server was lost".to_string(),
+                            };
+                            ui.colored_label(egui::Color32::LIGHT_RED, error);
+                            if ui.button("Retry").clicked() {
+                                commands.trigger(ConnectToServer { play: true });
+                            }
+                        }
+                    }
+                }
+
+                let play = ui.add_enabled(!searching, egui::Button::new("Play"));
                 handle_button(&play, title_data.as_mut(), &mut commands);
                 if play.clicked() {
-                    next_screen.set(Screen::Playing);
+                    title_data.room = RoomChoice::Any;
+                    if use_matchmaker {
+                        #[cfg(not(target_family = "wasm"))]
+                        commands.trigger(FindServer);
+                    } else {
+                        commands.trigger(ConnectToServer { play: true });
+                    }
+                }
+
+                // join a room from the list or by its code, or create a private room.
+                // The rooms belong to a single server, so they cannot be browsed through the matchmaker
+                let mut join_room = None;
+                if !use_matchmaker {
+                    ui.collapsing("Rooms", |ui| {
+                        ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 10.0);
+                        if ui.button("Refresh").clicked() {
+                            room_list.refresh = true;
+                        }
+                        for room in room_list.rooms.iter() {
+                            ui.horizontal(|ui| {
+                                let private = if room.private { " (private)" } else { "" };
+                                ui.label(format!(
+                                    "{} [{}] {}/{}{}",
+                                    room.name, room.code, room.players, room.max_players, private
+                                ));
+                                if ui.button("Join").clicked() {
+                                    if room.private {
+                                        // the password must be entered first
+                                        title_data.room_code.clone_from(&room.code);
+                                    } else {
+                                        join_room = Some(RoomChoice::Join {
+                                            code: room.code.clone(),
+                                            password: None,
+                                        });
+                                    }
+                                }
+                            });
+                        }
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut title_data.room_code)
+                                    .char_limit(8)
+                                    .desired_width(120.0)
+                                    .hint_text("Room code"),
+                            );
+                            ui.add(
+                                egui::TextEdit::singleline(&mut title_data.room_password)
+                                    .password(true)
+                                    .desired_width(120.0)
+                                    .hint_text("Password"),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            let join = ui.add_enabled(
+                                !title_data.room_code.is_empty(),
+                                egui::Button::new("Join by code"),
+                            );
+                            if join.clicked() {
+                                join_room = Some(RoomChoice::Join {
+                                    code: title_data.room_code.trim().to_string(),
+                                    password: Some(title_data.room_password.clone())
+                                        .filter(|password| !password.is_empty()),
+                                });
+                            }
+                            let create = ui.add_enabled(
+                                !title_data.room_password.is_empty(),
+                                egui::Button::new("Create private room"),
+                            );
+                            if create.clicked() {
+                                join_room = Some(RoomChoice::CreatePrivate {
+                                    password: title_data.room_password.clone(),
+                                });
+                            }
+                        });
+                    });
+                }
+                if let Some(room) = join_room {
+                    commands.trigger(PlaySfx::Key(SfxKey::ButtonPress));
+                    commands.remove_resource::<RoomError>();
+                    title_data.room = room;
+                    commands.trigger(ConnectToServer { play: true });
+                }
+
+                // hosting uses a different network config, so we cannot host while connected
+                // to a server to browse its rooms
+                let connected = network_state.get() != &NetworkingState::Disconnected;
+
+                // run the server in the client, so that friends on the LAN can join us
+                #[cfg(not(target_family = "wasm"))]
+                let host = {
+                    let host = ui.add_enabled(!connected, egui::Button::new("Host game"));
+                    handle_button(&host, title_data.as_mut(), &mut commands);
+                    if host.clicked() {
+                        title_data.room = RoomChoice::Any;
+                        commands.trigger(StartHosting(HostGame::Lan));
+                    }
+                    host
+                };
+
+                // offline game against bots
+                #[cfg(not(target_family = "wasm"))]
+                let practice = ui
+                    .horizontal(|ui| {
+                        ui.style_mut().spacing.item_spacing = egui::Vec2::new(10.0, 0.0);
+                        let practice = ui.add_enabled(!connected, egui::Button::new("Practice"));
+                        ui.add(egui::Slider::new(&mut title_data.bots, 0..=MAX_BOTS).text("bots"));
+                        practice
+                    })
+                    .inner;
+                #[cfg(not(target_family = "wasm"))]
+                {
+                    handle_button(&practice, title_data.as_mut(), &mut commands);
+                    if practice.clicked() {
+                        title_data.room = RoomChoice::Any;
+                        commands.trigger(StartHosting(HostGame::Practice {
+                            bots: title_data.bots,
+                        }));
+                    }
+                }
+
+                // only offer to watch a replay if one was loaded from the command line
+                if replay.is_some() {
+                    let watch_replay = ui.button("Watch replay");
+                    handle_button(&watch_replay, title_data.as_mut(), &mut commands);
+                    if watch_replay.clicked() {
+                        next_screen.set(Screen::Replay);
+                    }
                 }
 
                 // ui.style_mut().spacing.item_spacing = egui::Vec2::new(0.0, 30.0);
@@ -89,7 +349,11 @@ fn title(
                     if exit.clicked() {
                         app_exit.send(AppExit::Success);
                     }
-                    title_data.hovered = exit.hovered() || play.hovered() || credits.hovered();
+                    title_data.hovered = exit.hovered()
+                        || play.hovered()
+                        || host.hovered()
+                        || practice.hovered()
+                        || credits.hovered();
                 }
                 #[cfg(target_family = "wasm")]
                 {
@@ -98,3 +362,10 @@ fn title(
             });
         });
 }
+
+fn transport_label(transport: Transports) -> String {
+    transport
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
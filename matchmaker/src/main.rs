@@ -0,0 +1,168 @@
+//! Matchmaker that spreads the players across several server processes.
+//!
+//! The game servers report their player count every few seconds; a client that wants to play
+//! gets the address of the least full server that uses its protocol and transport, with a connect
+//! token for it. The tokens are signed with a private key that the game servers share.
+use clap::Parser;
+use lightyear::connection::netcode::ConnectToken;
+use lightyear::prelude::Key;
+use rand::Rng;
+use shared::network::config::{load_key, PROTOCOL_ID};
+use shared::network::matchmaker::{
+    read_message, write_message, MatchmakerRequest, MatchmakerResponse, ServerReport,
+    MATCHMAKER_PORT, SERVER_TIMEOUT,
+};
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// A client or server that doesn't send its request or read the response in this time is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connections handled at the same time; the ones above are dropped right away
+const MAX_CONNECTIONS: usize = 256;
+
+#[derive(Parser, PartialEq, Debug)]
+struct Cli {
+    #[arg(short, long, default_value_t = MATCHMAKER_PORT)]
+    port: u16,
+
+    /// File with the private key of the connect tokens, as 64 hexadecimal digits. The game servers
+    /// must be started with the same key
+    #[arg(long = "key-file", value_parser = load_key)]
+    key: Key,
+}
+
+/// Last report of every game server
+#[derive(Default)]
+struct Servers {
+    servers: HashMap<SocketAddr, (ServerReport, Instant)>,
+}
+
+impl Servers {
+    fn report(&mut self, report: ServerReport) {
+        if !self.servers.contains_key(&report.addr) {
+            info!(addr = ?report.addr, transport = %report.transport, "Registered a game server");
+        }
+        self.servers.insert(report.addr, (report, Instant::now()));
+    }
+
+    /// Forget the servers that stopped reporting
+    fn remove_stale(&mut self) {
+        self.servers.retain(|addr, (_, last_report)| {
+            let alive = last_report.elapsed() < SERVER_TIMEOUT;
+            if !alive {
+                info!(?addr, "Game server timed out");
+            }
+            alive
+        });
+    }
+
+    /// Least full server that is compatible with the client
+    fn find(&mut self, protocol_version: u64, transport: &str) -> Option<&mut ServerReport> {
+        self.remove_stale();
+        self.servers
+            .values_mut()
+            .map(|(report, _)| report)
+            .filter(|report| {
+                report.protocol_version == protocol_version
+                    && report.transport == transport
+                    && report.players < report.max_players
+            })
+            .min_by_key(|report| report.players)
+    }
+}
+
+fn handle_request(
+    servers: &Mutex<Servers>,
+    key: Key,
+    request: MatchmakerRequest,
+) -> MatchmakerResponse {
+    match request {
+        MatchmakerRequest::Report(report) => {
+            servers.lock().unwrap().report(report);
+            MatchmakerResponse::Registered
+        }
+        MatchmakerRequest::FindServer {
+            protocol_version,
+            transport,
+        } => {
+            let mut servers = servers.lock().unwrap();
+            let Some(server) = servers.find(protocol_version, &transport) else {
+                return MatchmakerResponse::Error {
+                    reason: "No server available".to_string(),
+                };
+            };
+            let client_id = rand::thread_rng().gen::<u64>();
+            let token = ConnectToken::build(server.addr, PROTOCOL_ID, client_id, key)
+                .generate()
+                .map_err(|e| format!("{e:?}"))
+                .and_then(|token| token.try_into_bytes().map_err(|e| format!("{e:?}")));
+            match token {
+                Ok(token) => {
+                    // count the player right away, so that clients arriving before the next report
+                    // are spread across the servers too
+                    server.players += 1;
+                    MatchmakerResponse::Server {
+                        addr: server.addr,
+                        client_id,
+                        connect_token: token.to_vec(),
+                    }
+                }
+                Err(e) => {
+                    warn!("Could not generate a connect token: {e}");
+                    MatchmakerResponse::Error {
+                        reason: "Could not generate a connect token".to_string(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn handle_connection(servers: &Mutex<Servers>, key: Key, stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request: MatchmakerRequest = read_message(&mut reader)?;
+    debug!(?request, "Received a request");
+    let response = handle_request(servers, key, request);
+    write_message(&mut &stream, &response)
+}
+
+fn main() -> std::io::Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), cli.port))?;
+    info!("Matchmaker listening on port {}", cli.port);
+    let servers = Arc::new(Mutex::new(Servers::default()));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Could not accept a connection: {e}");
+                continue;
+            }
+        };
+        if connections.fetch_add(1, Ordering::Relaxed) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::Relaxed);
+            warn!("Too many connections, dropping one");
+            continue;
+        }
+        let servers = servers.clone();
+        let connections = connections.clone();
+        let key = cli.key;
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&servers, key, stream) {
+                debug!("Matchmaker request failed: {e}");
+            }
+            connections.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+    Ok(())
+}
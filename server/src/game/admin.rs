@@ -0,0 +1,471 @@
+//! Admin console: the operator of a server types commands on its standard input.
+//!
+//! Type `help` for the list of commands. Players are identified by their client id, as shown by
+//! `players`. The client id changes every time the game is launched, so a ban applies to the
+//! identity that the client keeps across launches and to its IP address, for as long as the
+//! server runs.
+use crate::game::announcements::announce;
+use crate::game::snapshot::{RestoreSnapshot, SaveSnapshot};
+use crate::network::connections::{AvailableColors, PlayerIdentity};
+use crate::network::rooms::{InRoom, Rooms};
+use crate::player::bot::{bot_client_id, spawn_bot, Bot, MAX_BOTS};
+use crate::player::lag_compensation::{LagCompensationSettings, PlayerLatency};
+use bevy::prelude::*;
+use bevy::utils::{Duration, HashSet};
+use crossbeam_channel::Receiver;
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::message::{AnnouncementKind, ChatMessage};
+use shared::network::protocol::Channel1;
+use shared::player::bike::ClientIdMarker;
+use shared::player::scores::Score;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Commands:
+  players                 list the players with their client id and ping
+  kick <client id>        disconnect a player
+  ban <client id>         disconnect a player and refuse its identity and IP address
+                          until the server stops
+  unban <client id>       lift the ban of the player that had this client id
+  bans                    list the banned players
+  say <message>           send a chat message to every player
+  announce <message>      send an announcement to every player
+  rule max_players <n>    maximum number of players per room
+  rule max_rewind <ticks> maximum rewind of the lag compensation
+  reset                   clear every trail and zone
+  snapshot save [file]    save the zones and scores of the players
+  snapshot restore [file] give back their zones and scores to the players of a snapshot
+  bots add|remove <n>     add or remove bots in the default room
+  shutdown [seconds]      stop the server, after warning the players
+  shutdown cancel
+  help";
+
+pub struct AdminConsolePlugin;
+
+impl Plugin for AdminConsolePlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        // reading the standard input blocks, so it happens in its own thread
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        app.insert_resource(ConsoleInput(receiver));
+        app.init_resource::<BanList>();
+        app.add_systems(
+            Update,
+            (
+                run_commands,
+                refuse_banned_clients,
+                shutdown_countdown.run_if(resource_exists::<ScheduledShutdown>),
+            ),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct ConsoleInput(Receiver<String>);
+
+/// Players that cannot play on the server anymore
+#[derive(Resource, Default, Debug)]
+pub struct BanList(Vec<Ban>);
+
+#[derive(Debug)]
+struct Ban {
+    /// Client id of the player when it was banned, to lift the ban
+    client_number: u64,
+    name: String,
+    identity: PlayerIdentity,
+    /// The clients hosted in the server process don't have one
+    address: Option<IpAddr>,
+}
+
+impl BanList {
+    fn is_banned_address(&self, address: IpAddr) -> bool {
+        self.0.iter().any(|ban| ban.address == Some(address))
+    }
+
+    fn is_banned_identity(&self, identity: PlayerIdentity) -> bool {
+        self.0.iter().any(|ban| ban.identity == identity)
+    }
+}
+
+/// Remaining time before the server shuts down; the players are warned regularly
+#[derive(Resource, Debug)]
+struct ScheduledShutdown {
+    remaining: Duration,
+}
+
+/// The players are warned when the remaining time before a shutdown crosses these values, in seconds
+const SHUTDOWN_WARNINGS: [u64; 8] = [300, 120, 60, 30, 10, 3, 2, 1];
+
+#[derive(Debug, PartialEq)]
+enum AdminCommand {
+    Players,
+    Kick(u64),
+    Ban(u64),
+    Unban(u64),
+    Bans,
+    Say(String),
+    Announce(String),
+    MaxPlayers(usize),
+    MaxRewind(u16),
+    Reset,
+    SaveSnapshot(Option<PathBuf>),
+    RestoreSnapshot(Option<PathBuf>),
+    AddBots(usize),
+    RemoveBots(usize),
+    Shutdown(u64),
+    CancelShutdown,
+    Help,
+}
+
+impl AdminCommand {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        match command {
+            "players" => Ok(Self::Players),
+            "kick" => Ok(Self::Kick(number(words.next(), "a client id")?)),
+            "ban" => Ok(Self::Ban(number(words.next(), "a client id")?)),
+            "unban" => Ok(Self::Unban(number(words.next(), "a client id")?)),
+            "bans" => Ok(Self::Bans),
+            "say" => Ok(Self::Say(message(line, command)?)),
+            "announce" => Ok(Self::Announce(message(line, command)?)),
+            "rule" => match words.next() {
+                Some("max_players") => Ok(Self::MaxPlayers(number(
+                    words.next(),
+                    "a number of players",
+                )? as usize)),
+                Some("max_rewind") => Ok(Self::MaxRewind(
+                    number(words.next(), "a number of ticks")? as u16,
+                )),
+                _ => Err("Unknown rule, expected max_players or max_rewind".to_string()),
+            },
+            "reset" => Ok(Self::Reset),
+            "snapshot" => {
+                let action = words.next();
+                let path = words.next().map(PathBuf::from);
+                match action {
+                    Some("save") => Ok(Self::SaveSnapshot(path)),
+                    Some("restore") => Ok(Self::RestoreSnapshot(path)),
+                    _ => Err("Expected snapshot save or snapshot restore".to_string()),
+                }
+            }
+            "bots" => match words.next() {
+                Some("add") => Ok(Self::AddBots(
+                    number(words.next(), "a number of bots")? as usize
+                )),
+                Some("remove") => Ok(Self::RemoveBots(
+                    number(words.next(), "a number of bots")? as usize
+                )),
+                _ => Err("Expected bots add <n> or bots remove <n>".to_string()),
+            },
+            "shutdown" => match words.next() {
+                None => Ok(Self::Shutdown(0)),
+                Some("cancel") => Ok(Self::CancelShutdown),
+                seconds => Ok(Self::Shutdown(number(seconds, "a number of seconds")?)),
+            },
+            "help" => Ok(Self::Help),
+            _ => Err(format!("Unknown command {command:?}, type help")),
+        }
+    }
+}
+
+fn number(word: Option<&str>, what: &str) -> Result<u64, String> {
+    word.and_then(|word| word.parse().ok())
+        .ok_or_else(|| format!("Expected {what}"))
+}
+
+/// Rest of the line after the command
+fn message(line: &str, command: &str) -> Result<String, String> {
+    let line = line.trim_start();
+    let message = line.strip_prefix(command).unwrap_or(line).trim();
+    if message.is_empty() {
+        return Err("Expected a message".to_string());
+    }
+    Ok(message.to_string())
+}
+
+/// Number identifying a client in the console
+fn client_number(client_id: ClientId) -> Option<u64> {
+    match client_id {
+        ClientId::Netcode(id) | ClientId::Local(id) => Some(id),
+        _ => None,
+    }
+}
+
+fn run_commands(
+    mut commands: Commands,
+    input: Res<ConsoleInput>,
+    time: Res<Time>,
+    mut ban_list: ResMut<BanList>,
+    mut rooms: ResMut<Rooms>,
+    mut lag_compensation: ResMut<LagCompensationSettings>,
+    mut colors: ResMut<AvailableColors>,
+    mut manager: ResMut<ConnectionManager>,
+    players: Query<(
+        Entity,
+        &PlayerMarker,
+        &ClientIdMarker,
+        &InRoom,
+        &Score,
+        Option<&PlayerLatency>,
+        Option<&PlayerIdentity>,
+        Has<Bot>,
+    )>,
+    mut trails: Query<&mut Trail>,
+    mut zones: Query<&mut Zones>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    for line in input.0.try_iter() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = match AdminCommand::parse(&line) {
+            Ok(command) => command,
+            Err(error) => {
+                println!("{error}");
+                continue;
+            }
+        };
+        info!(?command, "Admin command");
+        // connected client with this number
+        let find_client = |number: u64| {
+            players
+                .iter()
+                .map(|(_, _, client_id, ..)| client_id.0)
+                .find(|client_id| client_number(*client_id) == Some(number))
+        };
+        match command {
+            AdminCommand::Players => {
+                println!(
+                    "{:>20}  {:<20} {:<6} {:>6} {:>8}",
+                    "client id", "name", "room", "score", "ping"
+                );
+                for (_, player, client_id, room, score, latency, _, is_bot) in players.iter() {
+                    let id = client_number(client_id.0)
+                        .map_or_else(|| format!("{:?}", client_id.0), |id| id.to_string());
+                    let room = rooms.get(room.0).map_or("-", |room| room.code.as_str());
+                    let ping = match latency {
+                        Some(latency) => format!("{}ms", latency.rtt.as_millis()),
+                        None if is_bot => "bot".to_string(),
+                        None => "-".to_string(),
+                    };
+                    println!(
+                        "{id:>20}  {:<20} {room:<6} {:>6} {ping:>8}",
+                        player.name,
+                        score.total()
+                    );
+                }
+            }
+            AdminCommand::Kick(number) => match find_client(number) {
+                Some(client_id) => {
+                    commands.disconnect(client_id);
+                    println!("Kicked {number}");
+                }
+                None => println!("No player with the client id {number}"),
+            },
+            AdminCommand::Ban(number) => {
+                // the identity is only known once the client spawned its player
+                let Some((_, player, client_id, .., Some(identity), _)) = players
+                    .iter()
+                    .find(|(_, _, client_id, ..)| client_number(client_id.0) == Some(number))
+                else {
+                    println!("No player with the client id {number}");
+                    continue;
+                };
+                ban_list.0.push(Ban {
+                    client_number: number,
+                    name: player.name.clone(),
+                    identity: *identity,
+                    address: manager.client_addr(client_id.0).map(|addr| addr.ip()),
+                });
+                commands.disconnect(client_id.0);
+                println!("Banned {number} ({})", player.name);
+            }
+            AdminCommand::Unban(number) => {
+                let count = ban_list.0.len();
+                ban_list.0.retain(|ban| ban.client_number != number);
+                if ban_list.0.len() < count {
+                    println!("Unbanned {number}");
+                } else {
+                    println!("No ban of the client id {number}");
+                }
+            }
+            AdminCommand::Bans => {
+                for ban in ban_list.0.iter() {
+                    let address = ban.address.map_or("-".to_string(), |ip| ip.to_string());
+                    println!("{:>20}  {:<20} {address}", ban.client_number, ban.name);
+                }
+            }
+            AdminCommand::Say(message) => {
+                let _ = manager.send_message_to_target::<Channel1, _>(
+                    &ChatMessage {
+                        color: Color::WHITE,
+                        sender: "Server".to_string(),
+                        message,
+                    },
+                    NetworkTarget::All,
+                );
+            }
+            AdminCommand::Announce(message) => {
+                announce(manager.as_mut(), AnnouncementKind::Info, message);
+            }
+            AdminCommand::MaxPlayers(max_players) => {
+                rooms.set_max_players(max_players);
+                println!("Rooms now accept {max_players} players");
+            }
+            AdminCommand::MaxRewind(ticks) => {
+                lag_compensation.max_rewind_ticks = ticks;
+                println!("The lag compensation rewinds by {ticks} ticks at most");
+            }
+            AdminCommand::Reset => {
+                for mut trail in trails.iter_mut() {
+                    trail.line.clear();
+                }
+                for mut zones in zones.iter_mut() {
+                    zones.zones.clear();
+                }
+                println!("Cleared every trail and zone");
+            }
+            AdminCommand::SaveSnapshot(path) => commands.trigger(SaveSnapshot(path)),
+            AdminCommand::RestoreSnapshot(path) => commands.trigger(RestoreSnapshot(path)),
+            AdminCommand::AddBots(count) => {
+                let Some(room) = rooms.default_room() else {
+                    println!("There is no room to add bots to");
+                    continue;
+                };
+                let used: HashSet<ClientId> = players
+                    .iter()
+                    .filter(|(.., is_bot)| *is_bot)
+                    .map(|(_, _, client_id, ..)| client_id.0)
+                    .collect();
+                // the bots take the free slots of the room, like the real players
+                let bots_in_room = players
+                    .iter()
+                    .filter(|(_, _, _, in_room, .., is_bot)| *is_bot && in_room.0 == room)
+                    .count();
+                let free_slots = rooms.get(room).map_or(0, |room| {
+                    room.rules
+                        .max_players
+                        .saturating_sub(room.clients.len() + bots_in_room)
+                });
+                if count > free_slots {
+                    println!("The room only has {free_slots} free slots");
+                }
+                let free = (0..MAX_BOTS).filter(|i| !used.contains(&bot_client_id(*i)));
+                let mut added = 0;
+                for index in free.take(count.min(free_slots)) {
+                    spawn_bot(
+                        &mut commands,
+                        index,
+                        &mut colors,
+                        InRoom(room),
+                        time.elapsed(),
+                    );
+                    added += 1;
+                }
+                println!("Added {added} bots");
+            }
+            AdminCommand::RemoveBots(count) => {
+                let mut removed = 0;
+                for (entity, ..) in players.iter().filter(|(.., is_bot)| *is_bot).take(count) {
+                    commands.entity(entity).despawn_recursive();
+                    removed += 1;
+                }
+                println!("Removed {removed} bots");
+            }
+            AdminCommand::Shutdown(0) => {
+                println!("Shutting down");
+                commands.stop_server();
+                app_exit.send(AppExit::Success);
+            }
+            AdminCommand::Shutdown(seconds) => {
+                println!("Shutting down in {seconds}s");
+                announce(
+                    manager.as_mut(),
+                    AnnouncementKind::Warning,
+                    format!("The server restarts in {seconds}s"),
+                );
+                commands.insert_resource(ScheduledShutdown {
+                    remaining: Duration::from_secs(seconds),
+                });
+            }
+            AdminCommand::CancelShutdown => {
+                commands.remove_resource::<ScheduledShutdown>();
+                announce(
+                    manager.as_mut(),
+                    AnnouncementKind::Info,
+                    "The server restart was cancelled".to_string(),
+                );
+                println!("Shutdown cancelled");
+            }
+            AdminCommand::Help => println!("{HELP}"),
+        }
+    }
+}
+
+fn shutdown_countdown(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut shutdown: ResMut<ScheduledShutdown>,
+    mut manager: ResMut<ConnectionManager>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    let before = shutdown.remaining;
+    shutdown.remaining = shutdown.remaining.saturating_sub(time.delta());
+    if shutdown.remaining.is_zero() {
+        info!("Shutting down");
+        commands.stop_server();
+        app_exit.send(AppExit::Success);
+        return;
+    }
+    let crossed = SHUTDOWN_WARNINGS.iter().find(|seconds| {
+        let warning = Duration::from_secs(**seconds);
+        before > warning && shutdown.remaining <= warning
+    });
+    if let Some(seconds) = crossed {
+        announce(
+            manager.as_mut(),
+            AnnouncementKind::Warning,
+            format!("The server restarts in {seconds}s"),
+        );
+    }
+}
+
+/// Disconnect the banned clients as soon as they connect from a banned address, or as soon as they
+/// send a banned identity
+fn refuse_banned_clients(
+    mut commands: Commands,
+    mut connections: EventReader<ServerConnectEvent>,
+    manager: Res<ConnectionManager>,
+    ban_list: Res<BanList>,
+    new_players: Query<(&ClientIdMarker, &PlayerIdentity), Added<PlayerIdentity>>,
+) {
+    for event in connections.read() {
+        let client_id = event.client_id();
+        let address = manager.client_addr(client_id).map(|addr| addr.ip());
+        if address.is_some_and(|address| ban_list.is_banned_address(address)) {
+            info!(?client_id, "Refusing a client with a banned address");
+            commands.disconnect(client_id);
+        }
+    }
+    for (client_id, identity) in new_players.iter() {
+        if ban_list.is_banned_identity(*identity) {
+            info!(client_id = ?client_id.0, "Refusing a banned player");
+            commands.disconnect(client_id.0);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Inactive players: a player whose inputs did not change for `timeout` is warned, and then moved
+//! to the spectators or disconnected, which frees their color and their zones
+use crate::game::announcements::announce_to;
+use crate::network::rooms::Rooms;
+use crate::player::bot::Bot;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use clap::ValueEnum;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::inputs::PlayerMovement;
+use shared::network::message::AnnouncementKind;
+use shared::player::bike::ClientIdMarker;
+use shared::player::PlayerMarker;
+
+pub const DEFAULT_AFK_TIMEOUT: Duration = Duration::from_secs(120);
+/// The player is warned this long before the timeout
+const AFK_WARNING: Duration = Duration::from_secs(20);
+/// The mouse must move by more than this (in pixels from the bike) to count as activity: the
+/// relative mouse position changes a bit on its own when the bike moves or the camera lags
+const INPUT_TOLERANCE: f32 = 20.0;
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AfkAction {
+    /// Remove the player from the game, but keep the client connected to watch the others
+    Spectate,
+    /// Disconnect the client
+    Disconnect,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct AfkSettings {
+    /// Time without any change of the inputs after which a player is inactive; `None` disables
+    /// the detection
+    pub timeout: Option<Duration>,
+    pub action: AfkAction,
+}
+
+impl Default for AfkSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Some(DEFAULT_AFK_TIMEOUT),
+            action: AfkAction::Spectate,
+        }
+    }
+}
+
+/// Inputs of a player when they last changed significantly, and when that happened
+#[derive(Component, Debug)]
+struct Activity {
+    last_input: Option<Vec2>,
+    last_change: Duration,
+    warned: bool,
+}
+
+impl Activity {
+    /// The player pressed or released the mouse, or moved it noticeably
+    fn is_active(&self, input: Option<Vec2>) -> bool {
+        match (self.last_input, input) {
+            (Some(last), Some(input)) => last.distance(input) > INPUT_TOLERANCE,
+            (last, input) => last.is_some() != input.is_some(),
+        }
+    }
+}
+
+pub(crate) struct AfkPlugin;
+
+impl Plugin for AfkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AfkSettings>();
+        app.add_systems(Update, detect_inactivity.run_if(is_started));
+    }
+}
+
+fn detect_inactivity(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<AfkSettings>,
+    mut manager: ResMut<ConnectionManager>,
+    mut rooms: ResMut<Rooms>,
+    mut players: Query<
+        (
+            Entity,
+            &PlayerMarker,
+            &ClientIdMarker,
+            &ActionState<PlayerMovement>,
+            Option<&mut Activity>,
+        ),
+        Without<Bot>,
+    >,
+) {
+    let Some(timeout) = settings.timeout else {
+        return;
+    };
+    let now = time.elapsed();
+    for (entity, player, client_id, action_state, activity) in players.iter_mut() {
+        let client_id = client_id.0;
+        // the player hosting the game is never removed from it
+        if matches!(client_id, ClientId::Local(_)) {
+            continue;
+        }
+        let input = action_state
+            .pressed(&PlayerMovement::MousePositionRelative)
+            .then(|| action_state.axis_pair(&PlayerMovement::MousePositionRelative))
+            .flatten()
+            .map(|axis_pair| axis_pair.xy());
+        let Some(mut activity) = activity else {
+            commands.entity(entity).insert(Activity {
+                last_input: input,
+                last_change: now,
+                warned: false,
+            });
+            continue;
+        };
+        if activity.is_active(input) {
+            *activity = Activity {
+                last_input: input,
+                last_change: now,
+                warned: false,
+            };
+            continue;
+        }
+
+        let idle = now - activity.last_change;
+        if idle >= timeout {
+            info!(
+                ?client_id,
+                name = player.name,
+                "Removing an inactive player"
+            );
+            match settings.action {
+                AfkAction::Spectate => {
+                    announce_to(
+                        manager.as_mut(),
+                        client_id,
+                        AnnouncementKind::Warning,
+                        "You were inactive: you are now spectating".to_string(),
+                    );
+                    // the color and the zones are freed with the player entity; the client stays
+                    // in the lightyear room to keep receiving the game, but frees its slot
+                    commands.entity(entity).despawn_recursive();
+                    rooms.make_spectator(client_id);
+                }
+                AfkAction::Disconnect => commands.disconnect(client_id),
+            }
+        } else if !activity.warned && idle + AFK_WARNING >= timeout {
+            activity.warned = true;
+            let verb = match settings.action {
+                AfkAction::Spectate => "moved to the spectators",
+                AfkAction::Disconnect => "disconnected",
+            };
+            announce_to(
+                manager.as_mut(),
+                client_id,
+                AnnouncementKind::Warning,
+                format!(
+                    "You are inactive: you will be {verb} in {} seconds",
+                    (timeout - idle).as_secs()
+                ),
+            );
+        }
+    }
+}
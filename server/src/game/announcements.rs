@@ -0,0 +1,109 @@
+//! System messages from the server: the message of the day sent to the players that join, and
+//! announcements repeated periodically
+use crate::network::rooms::JoinedRoom;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::message::{AnnouncementKind, AnnouncementMessage};
+use shared::network::protocol::Channel1;
+
+pub const DEFAULT_ANNOUNCEMENT_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Resource, Debug, Clone)]
+pub struct AnnouncementSettings {
+    /// Message of the day, sent to every player that joins
+    pub motd: Option<String>,
+    /// Announcements sent to every player in turn, one every `interval`
+    pub announcements: Vec<String>,
+    pub interval: Duration,
+}
+
+impl Default for AnnouncementSettings {
+    fn default() -> Self {
+        Self {
+            motd: None,
+            announcements: Vec::new(),
+            interval: DEFAULT_ANNOUNCEMENT_INTERVAL,
+        }
+    }
+}
+
+pub(crate) struct AnnouncementsPlugin;
+
+impl Plugin for AnnouncementsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AnnouncementSettings>();
+        app.init_resource::<AnnouncementTimer>();
+        app.add_systems(Update, (send_motd, timed_announcements).run_if(is_started));
+    }
+}
+
+/// Send an announcement to every connected client
+pub(crate) fn announce(manager: &mut ConnectionManager, kind: AnnouncementKind, text: String) {
+    let _ = manager.send_message_to_target::<Channel1, _>(
+        &AnnouncementMessage { kind, text },
+        NetworkTarget::All,
+    );
+}
+
+/// Send an announcement to a single client
+pub(crate) fn announce_to(
+    manager: &mut ConnectionManager,
+    client_id: ClientId,
+    kind: AnnouncementKind,
+    text: String,
+) {
+    let _ = manager.send_message::<Channel1, _>(client_id, &AnnouncementMessage { kind, text });
+}
+
+#[derive(Resource, Default)]
+struct AnnouncementTimer {
+    timer: Option<Timer>,
+    next: usize,
+}
+
+fn send_motd(
+    mut joined_room: EventReader<JoinedRoom>,
+    settings: Res<AnnouncementSettings>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    let Some(motd) = &settings.motd else {
+        joined_room.clear();
+        return;
+    };
+    for event in joined_room.read() {
+        let _ = manager.send_message::<Channel1, _>(
+            event.client_id,
+            &AnnouncementMessage {
+                kind: AnnouncementKind::Motd,
+                text: motd.clone(),
+            },
+        );
+    }
+}
+
+fn timed_announcements(
+    time: Res<Time>,
+    settings: Res<AnnouncementSettings>,
+    mut state: ResMut<AnnouncementTimer>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    if settings.announcements.is_empty() {
+        return;
+    }
+    let state = state.as_mut();
+    let timer = state
+        .timer
+        .get_or_insert_with(|| Timer::new(settings.interval, TimerMode::Repeating));
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let index = state.next % settings.announcements.len();
+    state.next = index + 1;
+    announce(
+        manager.as_mut(),
+        AnnouncementKind::Info,
+        settings.announcements[index].clone(),
+    );
+}
@@ -1 +1,6 @@
+pub mod admin;
+pub mod afk;
+pub mod announcements;
+pub mod replay;
+pub mod snapshot;
 pub mod start;
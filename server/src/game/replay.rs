@@ -0,0 +1,308 @@
+//! Record the matches to replay files, one per room
+//!
+//! The frames of each room are sent to a separate thread that appends them to the file of the
+//! room as the match goes, so the recording never holds the whole match in memory nor blocks the
+//! game to write it.
+
+use crate::network::rooms::{InRoom, Rooms};
+use crate::player::death::PlayerKillEvent;
+use avian2d::prelude::{Position, Rotation};
+use bevy::prelude::*;
+use bevy::utils::{Duration, HashMap, Instant};
+use crossbeam_channel::{Receiver, Sender};
+use lightyear::prelude::server::MessageEvent;
+use lightyear::prelude::{ClientId, RoomId};
+use lightyear::shared::replication::delta::Diffable;
+use shared::network::config::FIXED_TIMESTEP_HZ;
+use shared::network::message::ChatMessage;
+use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
+use shared::player::death::Dead;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
+use shared::replay::{BikeFrame, ReplayEvent, ReplayFrame, ReplayWriter};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+/// How often the frames written to the replay file are flushed while the match is running
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct ReplayPlugin {
+    /// The replay of a room is saved next to this path, with the code of the room added to the
+    /// file name
+    pub path: PathBuf,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ReplayRecorder {
+            path: self.path.clone(),
+            rooms: HashMap::default(),
+        });
+        // record after the trails and zones have been updated for this tick
+        app.add_systems(FixedPostUpdate, record_frame);
+        app.add_systems(Update, record_chat);
+        app.add_systems(Last, finish_on_exit.run_if(on_event::<AppExit>()));
+        app.observe(record_kill);
+        app.observe(record_player_left);
+    }
+}
+
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    path: PathBuf,
+    /// Recording of every room that had players; `None` if its file could not be created
+    rooms: HashMap<RoomId, Option<RoomRecording>>,
+}
+
+struct RoomRecording {
+    /// Sends the recorded frames to the writer thread
+    frames: Sender<ReplayFrame>,
+    writer_thread: JoinHandle<()>,
+    /// Last recorded trail of each player, used to store trails as diffs
+    trails: HashMap<ClientId, Trail>,
+    /// Last recorded zones of each player, used to store zones as diffs
+    zones: HashMap<ClientId, Zones>,
+    /// Events that happened since the last recorded frame
+    events: Vec<ReplayEvent>,
+}
+
+impl ReplayRecorder {
+    /// Recording of `room`, if it is recorded
+    fn room(&mut self, room: RoomId) -> Option<&mut RoomRecording> {
+        self.rooms.get_mut(&room).and_then(Option::as_mut)
+    }
+
+    /// Start recording `room` if it is not recorded yet
+    fn start(&mut self, room: RoomId, rooms: &Rooms) {
+        let Some(code) = rooms.get(room).map(|room| room.code.as_str()) else {
+            return;
+        };
+        let path = &self.path;
+        self.rooms.entry(room).or_insert_with(|| {
+            let path = room_path(path, code);
+            RoomRecording::start(&path)
+                .inspect_err(|e| error!("Could not record the replay to {:?}: {}", path, e))
+                .ok()
+        });
+    }
+
+    /// Wait for the writer threads to write the last frames
+    fn finish(&mut self) {
+        for (_, recording) in self.rooms.drain() {
+            if let Some(recording) = recording {
+                recording.finish();
+            }
+        }
+    }
+}
+
+impl RoomRecording {
+    fn start(path: &Path) -> std::io::Result<Self> {
+        let writer = ReplayWriter::create(path, 1.0 / FIXED_TIMESTEP_HZ as f32)?;
+        let (frames, receiver) = crossbeam_channel::unbounded();
+        let path = path.to_path_buf();
+        let writer_thread = std::thread::spawn(move || write_frames(writer, receiver, path));
+        Ok(Self {
+            frames,
+            writer_thread,
+            trails: HashMap::default(),
+            zones: HashMap::default(),
+            events: Vec::new(),
+        })
+    }
+
+    fn finish(self) {
+        // the writer thread stops once the channel is closed
+        drop(self.frames);
+        let _ = self.writer_thread.join();
+    }
+}
+
+/// `match.replay` becomes `match-ABCDE.replay` for the room `ABCDE`
+fn room_path(path: &Path, code: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{stem}-{code}");
+    if let Some(extension) = path.extension() {
+        file_name.push('.');
+        file_name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
+}
+
+fn write_frames(mut writer: ReplayWriter, frames: Receiver<ReplayFrame>, path: PathBuf) {
+    let mut written = 0;
+    let mut last_flush = Instant::now();
+    for frame in frames.iter() {
+        if let Err(e) = writer.write_frame(&frame) {
+            error!("Could not write the replay to {:?}: {}", path, e);
+            return;
+        }
+        written += 1;
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            last_flush = Instant::now();
+            if let Err(e) = writer.flush() {
+                error!("Could not write the replay to {:?}: {}", path, e);
+                return;
+            }
+        }
+    }
+    // the recorder is gone: the match is over
+    match writer.flush() {
+        Ok(()) => info!("Saved replay ({} frames) to {:?}", written, path),
+        Err(e) => error!("Could not save replay to {:?}: {}", path, e),
+    }
+}
+
+/// Record the bikes positions and the trail/zones changes of every room for the current tick
+fn record_frame(
+    mut recorder: ResMut<ReplayRecorder>,
+    rooms: Res<Rooms>,
+    players: Query<&InRoom, With<PlayerMarker>>,
+    new_players: Query<
+        (&ClientIdMarker, &PlayerMarker, &ColorComponent, &InRoom),
+        Added<PlayerMarker>,
+    >,
+    bikes: Query<(&ClientIdMarker, &Position, &Rotation, &InRoom), With<BikeMarker>>,
+    trails: Query<(&ClientIdMarker, &Trail, &InRoom), Changed<Trail>>,
+    zones: Query<(&ClientIdMarker, &Zones, &InRoom), Changed<Zones>>,
+) {
+    let recorder = recorder.as_mut();
+    // the recording of a room starts with its first player, and ends when the room is removed
+    for room in players.iter() {
+        recorder.start(room.0, &rooms);
+    }
+    let removed: Vec<RoomId> = recorder
+        .rooms
+        .keys()
+        .filter(|room| rooms.get(**room).is_none())
+        .copied()
+        .collect();
+    for room in removed {
+        if let Some(Some(recording)) = recorder.rooms.remove(&room) {
+            // don't wait for the writer thread
+            drop(recording.frames);
+        }
+    }
+
+    for (client_id, player, color, room) in new_players.iter() {
+        if let Some(recording) = recorder.room(room.0) {
+            recording.events.push(ReplayEvent::PlayerJoined {
+                client_id: client_id.0,
+                name: player.name.clone(),
+                color: color.0,
+            });
+        }
+    }
+    for (client_id, trail, room) in trails.iter() {
+        let Some(recording) = recorder.room(room.0) else {
+            continue;
+        };
+        let previous = recording.trails.entry(client_id.0).or_default();
+        recording.events.push(ReplayEvent::Trail {
+            client_id: client_id.0,
+            diff: previous.diff(trail),
+        });
+        previous.clone_from(trail);
+    }
+    for (client_id, new_zones, room) in zones.iter() {
+        let Some(recording) = recorder.room(room.0) else {
+            continue;
+        };
+        let previous = recording.zones.entry(client_id.0).or_default();
+        recording.events.push(ReplayEvent::Zones {
+            client_id: client_id.0,
+            diff: previous.diff(new_zones),
+        });
+        previous.clone_from(new_zones);
+    }
+    let mut room_bikes: HashMap<RoomId, Vec<BikeFrame>> = HashMap::default();
+    for (client_id, position, rotation, room) in bikes.iter() {
+        room_bikes.entry(room.0).or_default().push(BikeFrame {
+            client_id: client_id.0,
+            position: position.0,
+            rotation: rotation.as_radians(),
+        });
+    }
+    for (room, recording) in recorder.rooms.iter_mut() {
+        let Some(recording) = recording else {
+            continue;
+        };
+        let bikes = room_bikes.remove(room).unwrap_or_default();
+        let events = std::mem::take(&mut recording.events);
+        let _ = recording.frames.send(ReplayFrame { bikes, events });
+    }
+}
+
+fn record_chat(
+    mut recorder: ResMut<ReplayRecorder>,
+    rooms: Res<Rooms>,
+    mut messages: EventReader<MessageEvent<ChatMessage>>,
+) {
+    for message in messages.read() {
+        let Some(room) = rooms.client_room(message.context) else {
+            continue;
+        };
+        if let Some(recording) = recorder.room(room) {
+            recording
+                .events
+                .push(ReplayEvent::Chat(message.message.clone()));
+        }
+    }
+}
+
+/// Only the kills of living players count, like in `kill_player`: a player can be hit several
+/// times before it is marked as dead
+fn record_kill(
+    trigger: Trigger<PlayerKillEvent>,
+    mut recorder: ResMut<ReplayRecorder>,
+    players: Query<(&ClientIdMarker, &InRoom, Has<Dead>), With<PlayerMarker>>,
+    bikes: Query<(&ClientIdMarker, &Position), With<BikeMarker>>,
+) {
+    let (Ok((killer, _, _)), Ok((killed, room, false))) = (
+        players.get(trigger.event().killer),
+        players.get(trigger.event().killed),
+    ) else {
+        return;
+    };
+    let Some(recording) = recorder.room(room.0) else {
+        return;
+    };
+    // the `Dead` marker is only inserted once the commands are applied
+    let already_killed = recording.events.iter().any(
+        |event| matches!(event, ReplayEvent::Kill { killed: other, .. } if other == &killed.0),
+    );
+    if already_killed {
+        return;
+    }
+    let position = bikes
+        .iter()
+        .find(|(client_id, _)| *client_id == killed)
+        .map_or(Vec2::ZERO, |(_, position)| position.0);
+    recording.events.push(ReplayEvent::Kill {
+        killer: killer.0,
+        killed: killed.0,
+        position,
+    });
+}
+
+fn record_player_left(
+    trigger: Trigger<OnRemove, PlayerMarker>,
+    mut recorder: ResMut<ReplayRecorder>,
+    players: Query<(&ClientIdMarker, &InRoom)>,
+) {
+    let Ok((client_id, room)) = players.get(trigger.entity()) else {
+        return;
+    };
+    if let Some(recording) = recorder.room(room.0) {
+        recording.trails.remove(&client_id.0);
+        recording.zones.remove(&client_id.0);
+        recording.events.push(ReplayEvent::PlayerLeft {
+            client_id: client_id.0,
+        });
+    }
+}
+
+fn finish_on_exit(mut recorder: ResMut<ReplayRecorder>) {
+    recorder.finish();
+}
@@ -0,0 +1,247 @@
+//! Snapshots of the world, so that the territories survive a restart of the server.
+//!
+//! The zones and scores of the players are written to disk periodically and when the server
+//! exits. When a snapshot is restored, the zones of every player are held until a client with the
+//! same `PlayerIdentity` joins again, and then given back to them, minus the parts that other
+//! players took in the meantime.
+use crate::network::connections::PlayerIdentity;
+use crate::network::rooms::InRoom;
+use crate::player::bot::Bot;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use serde::{Deserialize, Serialize};
+use shared::player::scores::Score;
+use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Bump this when the snapshot format changes, so that old files are rejected instead of misread
+pub const SNAPSHOT_VERSION: u32 = 2;
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub players: Vec<PlayerSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerSnapshot {
+    /// `PlayerIdentity` of the client: the client id changes every time the game is launched
+    pub identity: u128,
+    pub name: String,
+    pub score: Score,
+    pub zones: Zones,
+}
+
+impl WorldSnapshot {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // write to a temporary file first, so that a crash while saving keeps the previous snapshot
+        let tmp_path = path.with_extension("tmp");
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(writer, self).map_err(io::Error::other)?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let snapshot: WorldSnapshot =
+            bincode::deserialize_from(reader).map_err(io::Error::other)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(io::Error::other(format!(
+                "unsupported snapshot version {} (expected {})",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Save a snapshot, to the given path or else to the snapshot file of the server
+#[derive(Event, Debug)]
+pub struct SaveSnapshot(pub Option<PathBuf>);
+
+/// Restore a snapshot, from the given path or else from the snapshot file of the server
+#[derive(Event, Debug)]
+pub struct RestoreSnapshot(pub Option<PathBuf>);
+
+pub struct SnapshotPlugin {
+    /// Snapshot file restored when the server starts, and saved periodically
+    pub path: Option<PathBuf>,
+}
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SnapshotSettings {
+            path: self.path.clone(),
+            autosave_timer: Timer::new(AUTOSAVE_INTERVAL, TimerMode::Repeating),
+        });
+        app.init_resource::<HeldPlayers>();
+        app.add_systems(Startup, restore_on_startup);
+        app.add_systems(Update, (reclaim_zones, autosave));
+        app.add_systems(Last, save_on_exit.run_if(on_event::<AppExit>()));
+        app.observe(save_snapshot);
+        app.observe(restore_snapshot);
+    }
+}
+
+#[derive(Resource)]
+struct SnapshotSettings {
+    path: Option<PathBuf>,
+    autosave_timer: Timer,
+}
+
+/// Players of a restored snapshot that did not join again yet
+#[derive(Resource, Default, Debug)]
+pub struct HeldPlayers {
+    players: Vec<PlayerSnapshot>,
+}
+
+impl HeldPlayers {
+    /// The names are not unique, so only the identity tells the players apart
+    fn take(&mut self, identity: PlayerIdentity) -> Option<PlayerSnapshot> {
+        let index = self
+            .players
+            .iter()
+            .position(|player| player.identity == identity.0)?;
+        Some(self.players.remove(index))
+    }
+}
+
+/// Zones and scores of the players that are playing (bots excluded) and of the held players
+fn take_snapshot(
+    held: &HeldPlayers,
+    players: &Query<(&PlayerMarker, &PlayerIdentity, &Score, &Children), Without<Bot>>,
+    zones: &Query<&Zones>,
+) -> WorldSnapshot {
+    let mut snapshot = WorldSnapshot {
+        version: SNAPSHOT_VERSION,
+        players: held.players.clone(),
+    };
+    for (player, identity, score, children) in players.iter() {
+        let Some(player_zones) = children.iter().find_map(|child| zones.get(*child).ok()) else {
+            continue;
+        };
+        snapshot.players.push(PlayerSnapshot {
+            identity: identity.0,
+            name: player.name.clone(),
+            score: score.clone(),
+            zones: player_zones.clone(),
+        });
+    }
+    snapshot
+}
+
+fn save_snapshot(
+    trigger: Trigger<SaveSnapshot>,
+    settings: Res<SnapshotSettings>,
+    held: Res<HeldPlayers>,
+    players: Query<(&PlayerMarker, &PlayerIdentity, &Score, &Children), Without<Bot>>,
+    zones: Query<&Zones>,
+) {
+    let Some(path) = trigger.event().0.as_ref().or(settings.path.as_ref()) else {
+        warn!("No snapshot file to save to");
+        return;
+    };
+    write_snapshot(&take_snapshot(&held, &players, &zones), path);
+}
+
+fn write_snapshot(snapshot: &WorldSnapshot, path: &Path) {
+    match snapshot.save(path) {
+        Ok(()) => info!(
+            "Saved a snapshot of {} players to {:?}",
+            snapshot.players.len(),
+            path
+        ),
+        Err(e) => error!("Could not save the snapshot to {:?}: {}", path, e),
+    }
+}
+
+/// Hold the zones of every player of the snapshot; the players that are already playing get them
+/// back right away
+fn restore_snapshot(
+    trigger: Trigger<RestoreSnapshot>,
+    settings: Res<SnapshotSettings>,
+    mut held: ResMut<HeldPlayers>,
+) {
+    let Some(path) = trigger.event().0.as_ref().or(settings.path.as_ref()) else {
+        warn!("No snapshot file to restore");
+        return;
+    };
+    match WorldSnapshot::load(path) {
+        Ok(snapshot) => {
+            info!(
+                "Restored a snapshot of {} players from {:?}",
+                snapshot.players.len(),
+                path
+            );
+            held.players = snapshot.players;
+        }
+        Err(e) => error!("Could not restore the snapshot from {:?}: {}", path, e),
+    }
+}
+
+fn restore_on_startup(mut commands: Commands, settings: Res<SnapshotSettings>) {
+    // a missing file just means that this is the first start of the server
+    if settings.path.as_ref().is_some_and(|path| path.exists()) {
+        commands.trigger(RestoreSnapshot(None));
+    }
+}
+
+/// Give their zones and score back to the players of the snapshot. The other players of the room
+/// keep the zones that they own now: they are cut out of the restored zones
+fn reclaim_zones(
+    mut held: ResMut<HeldPlayers>,
+    mut players: Query<(&PlayerMarker, &PlayerIdentity, &mut Score, &Children), Without<Bot>>,
+    mut zones: Query<(Entity, &mut Zones, &InRoom)>,
+) {
+    if held.players.is_empty() {
+        return;
+    }
+    for (player, identity, mut score, children) in players.iter_mut() {
+        let Some(mut snapshot) = held.take(*identity) else {
+            continue;
+        };
+        let Some(zones_entity) = children.iter().find(|child| zones.contains(**child)) else {
+            // the zones are not spawned yet
+            held.players.push(snapshot);
+            continue;
+        };
+        let room = *zones.get(*zones_entity).unwrap().2;
+        for (_, other_zones, _) in zones
+            .iter()
+            .filter(|(entity, _, other_room)| entity != zones_entity && **other_room == room)
+        {
+            for zone in other_zones.zones.iter() {
+                snapshot.zones.cut_out_zones(zone);
+            }
+        }
+        info!(
+            "{} reclaimed {} zones",
+            player.name,
+            snapshot.zones.zones.len()
+        );
+        *zones.get_mut(*zones_entity).unwrap().1 = snapshot.zones;
+        // the zone score is computed from the zones
+        score.kill_score = snapshot.score.kill_score;
+    }
+}
+
+fn autosave(mut commands: Commands, time: Res<Time>, mut settings: ResMut<SnapshotSettings>) {
+    if settings.path.is_some() && settings.autosave_timer.tick(time.delta()).just_finished() {
+        commands.trigger(SaveSnapshot(None));
+    }
+}
+
+fn save_on_exit(
+    settings: Res<SnapshotSettings>,
+    held: Res<HeldPlayers>,
+    players: Query<(&PlayerMarker, &PlayerIdentity, &Score, &Children), Without<Bot>>,
+    zones: Query<&Zones>,
+) {
+    if let Some(path) = &settings.path {
+        write_snapshot(&take_snapshot(&held, &players, &zones), path);
+    }
+}
@@ -0,0 +1,338 @@
+//! In-process test harness.
+//!
+//! Runs the server `App` and headless client `App`s in the same process, connected with
+//! in-memory channels instead of sockets. The bevy clock of every app only advances when the
+//! harness is stepped, one fixed tick at a time, so tests are deterministic and fast.
+//!
+//! The clients only run the shared game logic and lightyear (no rendering, no UI); their
+//! inputs are scripted with [`TestHarness::set_input`].
+//!
+//! NOTE: lightyear still uses the real clock internally (for pings and time sync), which is
+//! fine since the harness steps much faster than real time.
+use crate::network::config::build_server_plugins;
+use crate::network::start_server;
+use crate::player::death::PlayerKillEvent;
+use crate::ServerGamePlugin;
+use avian2d::prelude::Position;
+use bevy::ecs::query::QueryFilter;
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::{Duration, Instant};
+use leafwing_input_manager::axislike::DualAxisData;
+use leafwing_input_manager::prelude::*;
+use lightyear::client::input::leafwing::InputSystemSet;
+use lightyear::prelude::client::*;
+use lightyear::prelude::server::ServerTransport;
+use lightyear::prelude::*;
+use shared::network::config::{shared_config, FIXED_TIMESTEP_HZ, KEY, PROTOCOL_ID};
+use shared::network::inputs::PlayerMovement;
+use shared::network::message::{ProtocolVersionMessage, RoomChoice, SpawnPlayerMessage};
+use shared::network::protocol::{Channel1, ProtocolPlugin, ProtocolVersion};
+use shared::player::bike::{BikeMarker, ClientIdMarker};
+use shared::player::death::Dead;
+use shared::player::scores::Score;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use shared::player::PlayerMarker;
+use shared::SharedPlugin;
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// How long we wait for the clients to connect, or for a player to spawn
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Inputs sent by a test client
+#[derive(Resource, Default, Debug, Clone)]
+pub struct ScriptedInput {
+    /// Position of the mouse relative to the bike; `None` means that the mouse is not used
+    pub mouse_position_relative: Option<Vec2>,
+}
+
+pub struct TestHarness {
+    pub server: App,
+    pub clients: Vec<App>,
+    client_ids: Vec<ClientId>,
+    start_time: Instant,
+    current_time: Instant,
+    frame_duration: Duration,
+}
+
+impl TestHarness {
+    /// Create a server and `num_clients` clients; call [`Self::connect`] to connect them
+    pub fn new(num_clients: usize) -> Self {
+        let frame_duration = Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ);
+        let mut channels = Vec::new();
+        let mut clients = Vec::new();
+        let mut client_ids = Vec::new();
+        for i in 0..num_clients {
+            let client_id = i as u64 + 1;
+            let client_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 1000 + i as u16);
+            let (to_server_send, to_server_recv) = crossbeam_channel::unbounded();
+            let (from_server_send, from_server_recv) = crossbeam_channel::unbounded();
+            channels.push((client_addr, to_server_recv, from_server_send));
+            clients.push(client_app(
+                client_id,
+                ClientTransport::LocalChannel {
+                    recv: from_server_recv,
+                    send: to_server_send,
+                },
+            ));
+            client_ids.push(ClientId::Netcode(client_id));
+        }
+        let server = server_app(ServerTransport::Channels { channels });
+
+        let now = Instant::now();
+        Self {
+            server,
+            clients,
+            client_ids,
+            start_time: now,
+            current_time: now,
+            frame_duration,
+        }
+    }
+
+    pub fn client_id(&self, client: usize) -> ClientId {
+        self.client_ids[client]
+    }
+
+    /// Time elapsed since the harness was created
+    pub fn elapsed(&self) -> Duration {
+        self.current_time - self.start_time
+    }
+
+    /// Advance the clock by one fixed tick, and update the server then every client
+    pub fn frame_step(&mut self) {
+        self.current_time += self.frame_duration;
+        let now = self.current_time;
+        for app in std::iter::once(&mut self.server).chain(self.clients.iter_mut()) {
+            app.insert_resource(TimeUpdateStrategy::ManualInstant(now));
+            app.update();
+        }
+    }
+
+    /// Step the harness for `duration`
+    pub fn advance(&mut self, duration: Duration) {
+        let end = self.current_time + duration;
+        while self.current_time < end {
+            self.frame_step();
+        }
+    }
+
+    /// Step the harness until `condition` is true, or until `timeout` has elapsed.
+    /// Returns whether the condition was met.
+    pub fn run_until(
+        &mut self,
+        timeout: Duration,
+        mut condition: impl FnMut(&mut Self) -> bool,
+    ) -> bool {
+        let end = self.current_time + timeout;
+        while self.current_time < end {
+            if condition(self) {
+                return true;
+            }
+            self.frame_step();
+        }
+        condition(self)
+    }
+
+    /// Connect every client to the server, and send the protocol version like the game does
+    pub fn connect(&mut self) {
+        for client in self.clients.iter_mut() {
+            client.world_mut().commands().connect_client();
+            client.world_mut().flush();
+        }
+        let connected = self.run_until(SETUP_TIMEOUT, |harness| {
+            harness.clients.iter().all(|client| {
+                client.world().resource::<State<NetworkingState>>().get()
+                    == &NetworkingState::Connected
+            })
+        });
+        assert!(connected, "the clients could not connect to the server");
+        for client in self.clients.iter_mut() {
+            let version = client.world().resource::<ProtocolVersion>().0;
+            client
+                .world_mut()
+                .resource_mut::<ConnectionManager>()
+                .send_message::<Channel1, _>(&ProtocolVersionMessage { version })
+                .expect("could not send message");
+        }
+    }
+
+    /// Send the spawn message from `client`, and wait until its bike is predicted
+    pub fn spawn_player(&mut self, client: usize, name: &str) {
+        self.clients[client]
+            .world_mut()
+            .resource_mut::<ConnectionManager>()
+            .send_message::<Channel1, _>(&SpawnPlayerMessage {
+                name: name.to_string(),
+                room: RoomChoice::Any,
+                identity: client as u128,
+            })
+            .expect("could not send message");
+        let client_id = self.client_id(client);
+        let spawned = self.run_until(SETUP_TIMEOUT, |harness| {
+            let world = harness.clients[client].world_mut();
+            world
+                .query_filtered::<&ClientIdMarker, (With<BikeMarker>, With<Predicted>)>()
+                .iter(world)
+                .any(|id| id.0 == client_id)
+        });
+        assert!(spawned, "the player of client {client} was not spawned");
+    }
+
+    /// Set the inputs that `client` will send from now on
+    pub fn set_input(&mut self, client: usize, mouse_position_relative: Option<Vec2>) {
+        self.clients[client]
+            .world_mut()
+            .resource_mut::<ScriptedInput>()
+            .mouse_position_relative = mouse_position_relative;
+    }
+
+    /// Make the player of `killer` kill the player of `killed`, on the server
+    pub fn kill(&mut self, killer: usize, killed: usize) {
+        let killer = self.server_player(killer).expect("killer not found");
+        let killed = self.server_player(killed).expect("killed player not found");
+        let world = self.server.world_mut();
+        world.trigger(PlayerKillEvent { killer, killed });
+        world.flush();
+    }
+
+    /// Player entity of `client` on the server
+    pub fn server_player(&mut self, client: usize) -> Option<Entity> {
+        let client_id = self.client_id(client);
+        let world = self.server.world_mut();
+        world
+            .query_filtered::<(Entity, &ClientIdMarker), With<PlayerMarker>>()
+            .iter(world)
+            .find(|(_, id)| id.0 == client_id)
+            .map(|(entity, _)| entity)
+    }
+
+    /// Component `C` of the confirmed entity matching `F` that belongs to `owner`, as
+    /// replicated on `client`
+    pub fn confirmed<C: Component + Clone, F: QueryFilter>(
+        &mut self,
+        client: usize,
+        owner: usize,
+    ) -> Option<C> {
+        let owner_id = self.client_id(owner);
+        let world = self.clients[client].world_mut();
+        world
+            .query_filtered::<(&ClientIdMarker, &C), (F, With<Confirmed>)>()
+            .iter(world)
+            .find(|(id, _)| id.0 == owner_id)
+            .map(|(_, component)| component.clone())
+    }
+
+    pub fn trail(&mut self, client: usize, owner: usize) -> Option<Trail> {
+        self.confirmed::<Trail, ()>(client, owner)
+    }
+
+    pub fn zones(&mut self, client: usize, owner: usize) -> Option<Zones> {
+        self.confirmed::<Zones, ()>(client, owner)
+    }
+
+    pub fn score(&mut self, client: usize, owner: usize) -> Option<Score> {
+        self.confirmed::<Score, With<PlayerMarker>>(client, owner)
+    }
+
+    /// Position of the bike of `owner`, or `None` if it has no bike
+    pub fn bike_position(&mut self, client: usize, owner: usize) -> Option<Vec2> {
+        self.confirmed::<Position, With<BikeMarker>>(client, owner)
+            .map(|position| position.0)
+    }
+
+    pub fn is_dead(&mut self, client: usize, owner: usize) -> bool {
+        self.confirmed::<Dead, With<PlayerMarker>>(client, owner)
+            .is_some()
+    }
+}
+
+fn server_app(transport: ServerTransport) -> App {
+    let mut app = App::new();
+    app.add_plugins(SharedPlugin { headless: true });
+    app.add_plugins(build_server_plugins(transport, Mode::Separate));
+    app.add_plugins(ServerGamePlugin);
+    app.add_systems(Startup, start_server);
+    app.finish();
+    app.cleanup();
+    app
+}
+
+fn client_app(client_id: u64, transport: ClientTransport) -> App {
+    let mut app = App::new();
+    app.add_plugins(SharedPlugin { headless: true });
+    // leafwing reads the keyboard/mouse resources
+    app.add_plugins(InputPlugin);
+    let config = ClientConfig {
+        shared: shared_config(Mode::Separate),
+        net: NetConfig::Netcode {
+            auth: Authentication::Manual {
+                // the address is not used by the local channels
+                server_addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0),
+                client_id,
+                private_key: KEY,
+                protocol_id: PROTOCOL_ID,
+            },
+            config: NetcodeConfig::default(),
+            io: IoConfig::from_transport(transport),
+        },
+        ..default()
+    };
+    // the ClientPlugins must be added before the Protocol plugins
+    app.add_plugins(ClientPlugins::new(config));
+    app.add_plugins(ProtocolPlugin);
+
+    app.init_resource::<ScriptedInput>();
+    app.add_systems(Update, add_input_map);
+    app.add_systems(
+        FixedPreUpdate,
+        apply_scripted_input
+            .before(InputSystemSet::BufferClientInputs)
+            .run_if(not(is_in_rollback)),
+    );
+    app.finish();
+    app.cleanup();
+    app
+}
+
+/// Same as the client: the `InputMap` lives on the predicted player entity
+fn add_input_map(
+    mut commands: Commands,
+    predicted_players: Query<
+        Entity,
+        (
+            With<Predicted>,
+            With<PlayerMarker>,
+            Without<InputMap<PlayerMovement>>,
+        ),
+    >,
+) {
+    for entity in predicted_players.iter() {
+        commands
+            .entity(entity)
+            .insert(InputMap::<PlayerMovement>::default());
+    }
+}
+
+fn apply_scripted_input(
+    input: Res<ScriptedInput>,
+    mut action_states: Query<
+        &mut ActionState<PlayerMovement>,
+        (With<PlayerMarker>, With<Predicted>),
+    >,
+) {
+    for mut action_state in action_states.iter_mut() {
+        match input.mouse_position_relative {
+            Some(mouse_position_relative) => {
+                action_state.press(&PlayerMovement::MousePositionRelative);
+                action_state
+                    .action_data_mut(&PlayerMovement::MousePositionRelative)
+                    .unwrap()
+                    .axis_pair = Some(DualAxisData::from_xy(mouse_position_relative));
+            }
+            None => action_state.release(&PlayerMovement::MousePositionRelative),
+        }
+    }
+}
@@ -1,13 +1,27 @@
 use bevy::prelude::*;
 use clap::Parser;
+use lightyear::prelude::server::{NetConfig, ServerPlugins};
+use lightyear::prelude::{Key, Mode};
 
-use shared::network::config::Transports;
+use shared::network::config::{load_key, Transports, KEY};
+use shared::player::trail::{TrailSettings, DEFAULT_TICKS_PER_POINT};
 use shared::SharedPlugin;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
 
 mod game;
+#[cfg(any(test, feature = "test-support"))]
+pub mod harness;
 mod network;
 mod player;
 
+pub use game::afk::{AfkAction, AfkSettings, DEFAULT_AFK_TIMEOUT};
+pub use game::announcements::{AnnouncementSettings, DEFAULT_ANNOUNCEMENT_INTERVAL};
+pub use network::rooms::{PublicRooms, DEFAULT_PUBLIC_ROOMS};
+pub use player::anti_cheat::{AntiCheatSettings, CheatAction};
+pub use player::bot::{Bots, MAX_BOTS};
+
 pub const SERVER_PORT: u16 = 5000;
 
 #[derive(Parser, PartialEq, Debug)]
@@ -23,6 +37,78 @@ pub struct Cli {
 
     #[arg(short, long, value_enum, default_value_t = Transports::WebTransport)]
     transport: Transports,
+
+    /// Record the matches to replay files named after this path, one per room (`match-ABCDE.replay`
+    /// for the room `ABCDE`)
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Number of bots to add to the game
+    #[arg(long, default_value_t = 0)]
+    bots: usize,
+
+    /// Number of public rooms (independent arenas) hosted by the server
+    #[arg(long, default_value_t = DEFAULT_PUBLIC_ROOMS)]
+    rooms: usize,
+
+    /// Address of the matchmaker to report the number of players to. The matchmaker signs the
+    /// connect tokens, so the server needs its key
+    #[arg(long, requires = "key")]
+    matchmaker: Option<SocketAddr>,
+
+    /// File with the private key of the connect tokens, shared with the matchmaker, as 64
+    /// hexadecimal digits. Without it the server uses a public key, so anyone can forge a token
+    #[arg(long = "key-file", value_parser = load_key)]
+    key: Option<Key>,
+
+    /// IP address that the clients use to reach this server, sent to the matchmaker
+    #[arg(long, default_value_t = Ipv4Addr::LOCALHOST.into())]
+    public_ip: IpAddr,
+
+    /// Serve the server metrics in the Prometheus format on this port
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Read admin commands from the standard input
+    #[arg(long, default_value = "false")]
+    console: bool,
+
+    /// Message of the day, shown to the players when they join
+    #[arg(long)]
+    motd: Option<String>,
+
+    /// Announcement repeated to every player; can be given several times, to cycle through them
+    #[arg(long)]
+    announcement: Vec<String>,
+
+    /// Time between two announcements, in seconds
+    #[arg(long, default_value_t = DEFAULT_ANNOUNCEMENT_INTERVAL.as_secs())]
+    announcement_interval: u64,
+
+    /// Save the zones and scores of the players to this file, and restore them when the server starts
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+
+    /// What to do with the clients that are suspected of cheating
+    #[arg(long, value_enum, default_value_t = CheatAction::Log)]
+    cheat_action: CheatAction,
+
+    /// Number of anomalies (invalid inputs, impossible movements) after which a client is
+    /// suspected of cheating
+    #[arg(long, default_value_t = AntiCheatSettings::default().max_violations)]
+    max_violations: u32,
+
+    /// Time without any input after which a player is inactive, in seconds; 0 disables it
+    #[arg(long, default_value_t = DEFAULT_AFK_TIMEOUT.as_secs())]
+    afk_timeout: u64,
+
+    /// What to do with the inactive players
+    #[arg(long, value_enum, default_value_t = AfkAction::Spectate)]
+    afk_action: AfkAction,
+
+    /// Number of ticks between two points of the trails; sent to the clients when they connect
+    #[arg(long, default_value_t = DEFAULT_TICKS_PER_POINT)]
+    ticks_per_point: u16,
 }
 
 pub fn app(cli: Cli) -> App {
@@ -31,16 +117,84 @@ pub fn app(cli: Cli) -> App {
         headless: !cli.render,
     });
 
-    // game
-    app.add_plugins(game::start::GamePlugin);
-
     // networking
-    app.add_plugins(network::NetworkPlugin {
-        server_port: cli.port,
-        transport: cli.transport,
-    });
+    app.add_plugins(network::config::build_lightyear_server(
+        cli.port,
+        cli.transport,
+        cli.key.unwrap_or(KEY),
+        Mode::Separate,
+    ));
 
-    // player
-    app.add_plugins(player::PlayerPlugin);
+    app.add_plugins(ServerGamePlugin);
+    app.insert_resource(Bots { count: cli.bots });
+    app.insert_resource(PublicRooms { count: cli.rooms });
+    app.insert_resource(AntiCheatSettings {
+        action: cli.cheat_action,
+        max_violations: cli.max_violations.max(1),
+    });
+    app.insert_resource(AfkSettings {
+        timeout: (cli.afk_timeout > 0).then(|| Duration::from_secs(cli.afk_timeout)),
+        action: cli.afk_action,
+    });
+    app.insert_resource(TrailSettings {
+        ticks_per_point: cli.ticks_per_point.max(1),
+    });
+    app.insert_resource(AnnouncementSettings {
+        motd: cli.motd,
+        announcements: cli.announcement,
+        interval: Duration::from_secs(cli.announcement_interval.max(1)),
+    });
+    app.add_systems(Startup, network::start_server);
+    if let Some(matchmaker) = cli.matchmaker {
+        app.add_plugins(network::matchmaker::MatchmakerPlugin {
+            matchmaker,
+            public_addr: SocketAddr::new(cli.public_ip, cli.port),
+            transport: cli.transport,
+        });
+    }
+    if let Some(port) = cli.metrics_port {
+        app.add_plugins(network::metrics::MetricsPlugin { port });
+    }
+    app.add_plugins(game::snapshot::SnapshotPlugin { path: cli.snapshot });
+    if cli.console {
+        app.add_plugins(game::admin::AdminConsolePlugin);
+    }
+    if let Some(path) = cli.record {
+        app.add_plugins(game::replay::ReplayPlugin { path });
+    }
     app
 }
+
+/// Lightyear server plugins for a server running inside a client app (host mode). They must be
+/// added before the client's protocol. The server doesn't listen on the network until the config
+/// from [`build_host_net_config`] is set, when hosting starts.
+pub fn build_host_server() -> ServerPlugins {
+    network::config::build_server_plugins_with(Vec::new(), Mode::HostServer)
+}
+
+/// Net config of a hosted server that other players can join on `port`. This fails if the
+/// WebTransport certificate cannot be loaded
+pub fn build_host_net_config(port: u16, transport: Transports) -> Result<NetConfig, String> {
+    // the players joining a hosted game connect directly, with the default key
+    network::config::build_server_transport(port, transport)
+        .map(|transport| network::config::build_net_config(transport, KEY))
+}
+
+/// All the server logic, independently of the lightyear transport used.
+/// The server systems only run once the server is started.
+pub struct ServerGamePlugin;
+
+impl Plugin for ServerGamePlugin {
+    fn build(&self, app: &mut App) {
+        // game
+        app.add_plugins(game::start::GamePlugin);
+        app.add_plugins(game::announcements::AnnouncementsPlugin);
+        app.add_plugins(game::afk::AfkPlugin);
+
+        // networking
+        app.add_plugins(network::NetworkPlugin);
+
+        // player
+        app.add_plugins(player::PlayerPlugin);
+    }
+}
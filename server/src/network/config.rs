@@ -9,8 +9,23 @@ use std::time::Duration;
 
 use shared::network::config::{shared_config, Transports, KEY, PROTOCOL_ID};
 
-pub(crate) fn build_lightyear_server(port: u16, transport: Transports) -> ServerPlugins {
+pub(crate) fn build_lightyear_server(
+    port: u16,
+    transport: Transports,
+    key: Key,
+    mode: Mode,
+) -> ServerPlugins {
     // Step 1: create the io (transport + link conditioner)
+    let transport_config = build_server_transport(port, transport).unwrap();
+    build_server_plugins_with(vec![build_net_config(transport_config, key)], mode)
+}
+
+/// Transport listening on `port`. The WebTransport certificate is loaded from the client assets,
+/// relative to the working directory, so this fails if the server is not launched from the repo
+pub(crate) fn build_server_transport(
+    port: u16,
+    transport: Transports,
+) -> Result<ServerTransport, String> {
     let server_addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
     let transport_config = match transport {
         Transports::Udp => ServerTransport::UdpSocket(server_addr),
@@ -26,11 +41,11 @@ pub(crate) fn build_lightyear_server(port: u16, transport: Transports) -> Server
                             "../client/assets/certificates/key.pem",
                         )
                         .await
-                        .unwrap()
                     }));
                 })
                 .pop()
-                .unwrap();
+                .unwrap()
+                .map_err(|error| format!("Could not load the certificate: {error}"))?;
             let digest = certificate.certificate_chain().as_slice()[0].hash();
             info!("Generated self-signed certificate with digest: {}", digest);
             ServerTransport::WebTransportServer {
@@ -40,25 +55,25 @@ pub(crate) fn build_lightyear_server(port: u16, transport: Transports) -> Server
         }
         Transports::WebSocket => ServerTransport::WebSocketServer { server_addr },
     };
-    let link_conditioner = LinkConditionerConfig {
-        incoming_latency: Duration::from_millis(0),
-        incoming_jitter: Duration::from_millis(0),
-        incoming_loss: 0.0,
-    };
+    Ok(transport_config)
+}
+
+/// Build the lightyear server plugins from an already created transport, with the default `KEY`
+pub(crate) fn build_server_plugins(transport_config: ServerTransport, mode: Mode) -> ServerPlugins {
+    build_server_plugins_with(vec![build_net_config(transport_config, KEY)], mode)
+}
+
+/// Build the lightyear server plugins, listening with every config of `net`
+pub(crate) fn build_server_plugins_with(net: Vec<NetConfig>, mode: Mode) -> ServerPlugins {
     // Step 2: define the server configuration
-    let shared_config = shared_config();
+    let shared_config = shared_config(mode);
     let replication_config = ReplicationConfig {
         send_updates_mode: SendUpdatesMode::SinceLastAck,
         send_interval: shared_config.server_replication_send_interval,
     };
     let config = ServerConfig {
         shared: shared_config,
-        net: vec![NetConfig::Netcode {
-            config: NetcodeConfig::default()
-                .with_protocol_id(PROTOCOL_ID)
-                .with_key(KEY),
-            io: IoConfig::from_transport(transport_config).with_conditioner(link_conditioner),
-        }],
+        net,
         replication: replication_config,
         ..default()
     };
@@ -66,3 +81,19 @@ pub(crate) fn build_lightyear_server(port: u16, transport: Transports) -> Server
     // Step 3: create the plugin
     ServerPlugins::new(config)
 }
+
+/// Netcode config of the server, listening with `transport_config`. `key` must be the one that
+/// signs the connect tokens of the clients
+pub(crate) fn build_net_config(transport_config: ServerTransport, key: Key) -> NetConfig {
+    let link_conditioner = LinkConditionerConfig {
+        incoming_latency: Duration::from_millis(0),
+        incoming_jitter: Duration::from_millis(0),
+        incoming_loss: 0.0,
+    };
+    NetConfig::Netcode {
+        config: NetcodeConfig::default()
+            .with_protocol_id(PROTOCOL_ID)
+            .with_key(key),
+        io: IoConfig::from_transport(transport_config).with_conditioner(link_conditioner),
+    }
+}
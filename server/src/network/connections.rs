@@ -1,5 +1,8 @@
 //! Handle client connections
 
+use crate::network::handshake::Handshakes;
+use crate::network::rooms::{InRoom, JoinedRoom, Rooms};
+use crate::player::lag_compensation::{PlayerLatency, PositionHistory};
 use avian2d::prelude::{Position, RigidBody};
 use bevy::color::palettes::css;
 use bevy::prelude::*;
@@ -7,124 +10,258 @@ use bevy::utils::HashSet;
 use lightyear::prelude::server::*;
 use lightyear::prelude::*;
 use rand::Rng;
-use shared::network::message::SpawnPlayerMessage;
+use shared::network::message::{RoomJoinedMessage, RoomRejectedMessage, SpawnPlayerMessage};
+use shared::network::protocol::Channel1;
 use shared::player::bike::{BikeBundle, BikeMarker};
 use shared::player::trail::{Trail, TrailBundle};
 use shared::player::zone::{Zones, ZonesBundle};
+use shared::player::PlayerBundle;
 use std::time::Duration;
 
+/// Colors given to the players, as long as some are left
+const PALETTE: [Srgba; 12] = [
+    css::LIMEGREEN,
+    css::PINK,
+    css::YELLOW,
+    css::AQUA,
+    css::CRIMSON,
+    css::GOLD,
+    css::ORANGE_RED,
+    css::SILVER,
+    css::SALMON,
+    css::YELLOW_GREEN,
+    css::WHITE,
+    css::RED,
+];
+
 #[derive(Resource)]
 pub struct AvailableColors(pub Vec<Color>);
 
 impl Default for AvailableColors {
     fn default() -> Self {
-        Self(Vec::from([
-            css::LIMEGREEN.into(),
-            css::PINK.into(),
-            css::YELLOW.into(),
-            css::AQUA.into(),
-            css::CRIMSON.into(),
-            css::GOLD.into(),
-            css::ORANGE_RED.into(),
-            css::SILVER.into(),
-            css::SALMON.into(),
-            css::YELLOW_GREEN.into(),
-            css::WHITE.into(),
-            css::RED.into(),
-        ]))
+        Self(PALETTE.into_iter().map(Color::from).collect())
     }
 }
 
 impl AvailableColors {
+    /// Take a color from the palette, or make up a random one once every color is taken
+    /// (a room can have more players than there are colors, and there are several rooms)
     pub fn pick_color(&mut self) -> Color {
+        if self.0.is_empty() {
+            return random_color();
+        }
         let index = rand::thread_rng().gen_range(0..self.0.len());
         self.0.swap_remove(index)
     }
 
+    /// Give back the color of a player that left. Made up colors are not kept
     pub fn add_color(&mut self, color: Color) {
-        self.0.push(color);
+        let in_palette = PALETTE.into_iter().any(|c| Color::from(c) == color);
+        if in_palette && !self.0.contains(&color) {
+            self.0.push(color);
+        }
     }
 }
 
-/// Spawn a new bike when a player connects, along with a `Trail` and a `Zones` entities
-pub(crate) fn spawn_bike(
+/// Identity that the client of a player keeps across launches, sent with its `SpawnPlayerMessage`.
+/// The bots don't have one
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerIdentity(pub u128);
+
+/// A bright color with a random hue
+pub(crate) fn random_color() -> Color {
+    Color::hsl(rand::thread_rng().gen_range(0.0..360.0), 0.9, 0.6)
+}
+
+/// Spawn a new player when a client sends its name, along with a `Trail`, a `Zones` and a `Bike` entities,
+/// in the room that the client asked for
+pub(crate) fn spawn_player(
     mut messages: ResMut<Events<MessageEvent<SpawnPlayerMessage>>>,
+    handshakes: Res<Handshakes>,
     time: Res<Time>,
     mut colors: ResMut<AvailableColors>,
+    mut rooms: ResMut<Rooms>,
+    mut room_manager: ResMut<RoomManager>,
+    mut manager: ResMut<ConnectionManager>,
+    mut joined_room: EventWriter<JoinedRoom>,
     mut commands: Commands,
 ) {
     for message in messages.drain() {
         let client_id = message.context;
-        let name = message.message.name;
+        if !handshakes.is_verified(client_id) {
+            continue;
+        }
+        let SpawnPlayerMessage {
+            name,
+            room,
+            identity,
+        } = message.message;
 
+        let room = match rooms.resolve(&mut commands, &room) {
+            Ok(room) => room,
+            Err(reason) => {
+                info!(?client_id, reason, "Could not join the room");
+                let _ =
+                    manager.send_message::<Channel1, _>(client_id, &RoomRejectedMessage { reason });
+                continue;
+            }
+        };
         info!(
-            "Spawning bike for client {:?}, player {:?}",
-            client_id, name
+            "Spawning player for client {:?}, player {:?}, in room {:?}",
+            client_id, name, room
         );
+        rooms.add_client(client_id, room);
+        room_manager.add_client(client_id, room);
+        joined_room.send(JoinedRoom { client_id, room });
+        if let Some(room) = rooms.get(room) {
+            let _ = manager.send_message::<Channel1, _>(
+                client_id,
+                &RoomJoinedMessage {
+                    code: room.code.clone(),
+                    name: room.name.clone(),
+                },
+            );
+        }
+
         let color = colors.pick_color();
-        let pos = Vec2::new(0.0, 0.0);
+        let player = spawn_player_entities(
+            &mut commands,
+            client_id,
+            name,
+            color,
+            InRoom(room),
+            time.elapsed(),
+        );
+        commands
+            .entity(player)
+            .insert((PlayerLatency::default(), PlayerIdentity(identity)));
+    }
+}
+
+/// Spawn the player entity for `client_id`, with its trail, zones and bike as children
+pub(crate) fn spawn_player_entities(
+    commands: &mut Commands,
+    client_id: ClientId,
+    name: String,
+    color: Color,
+    room: InRoom,
+    spawn_time: Duration,
+) -> Entity {
+    let pos = Vec2::new(0.0, 0.0);
 
-        // NOTE: for complicated reasons related to lightyear:
-        //  - each entity must be replicated in a different replication group (so that delta compression works)
-        //  - but the trail/zones must be replicated after the bike, so that the ParentSync has a pointer to the correct entities
-        //
-        // As a solution, we will replicate bike/trail/zone without replicating the hierarchy
-        // We will add the hierarchy manually on the client side by comparing client ids
-        let bike = commands
-            .spawn((
-                BikeBundle::new_at(client_id, name, pos, color, time.elapsed()),
-                RigidBody::Kinematic,
-                Replicate {
-                    sync: SyncTarget {
-                        prediction: NetworkTarget::Single(client_id),
-                        interpolation: NetworkTarget::AllExceptSingle(client_id),
-                    },
-                    // TODO: add network relevance
-                    controlled_by: ControlledBy {
-                        target: NetworkTarget::Single(client_id),
-                        ..default()
-                    },
+    // NOTE: for complicated reasons related to lightyear:
+    //  - each entity must be replicated in a different replication group (so that delta compression works)
+    //  - but the trail/zones must be replicated after the player, so that the ParentSync has a pointer to the correct entities
+    //
+    // As a solution, we will replicate player/bike/trail/zone without replicating the hierarchy
+    // We will add the hierarchy manually on the client side by comparing client ids
+    let player = commands
+        .spawn((
+            PlayerBundle::new(client_id, name, color),
+            Replicate {
+                // the player entity holds the inputs, so it must be predicted by its owner
+                sync: SyncTarget {
+                    prediction: NetworkTarget::Single(client_id),
+                    ..default()
+                },
+                controlled_by: ControlledBy {
+                    target: NetworkTarget::Single(client_id),
                     ..default()
                 },
-            ))
-            // do not replicate the hierarchy at all, because the ParentSync component might be invalid
-            // instead we will build the hierarchy on the client side manually
-            .remove::<ReplicateHierarchy>()
-            .id();
+                // only replicated to the clients in the same room
+                visibility: VisibilityMode::InterestManagement,
+                ..default()
+            },
+            room,
+        ))
+        .remove::<ReplicateHierarchy>()
+        .id();
 
-        let trail = commands
-            .spawn((
-                TrailBundle::new_at(pos, client_id),
-                // Enable delta compression when replicating the trail
-                DeltaCompression::<Trail>::default(),
-                Replicate {
-                    // TODO: add network relevance
-                    controlled_by: ControlledBy {
-                        target: NetworkTarget::Single(client_id),
-                        ..default()
-                    },
+    let trail = commands
+        .spawn((
+            TrailBundle::new_at(pos, client_id),
+            // Enable delta compression when replicating the trail
+            DeltaCompression::<Trail>::default(),
+            Replicate {
+                // the owner predicts its own trail
+                sync: SyncTarget {
+                    prediction: NetworkTarget::Single(client_id),
                     ..default()
                 },
-            ))
-            .remove::<(ReplicateHierarchy, SyncTarget)>()
-            .id();
-        //
-        let zones = commands
-            .spawn((
-                ZonesBundle::new(client_id),
-                // Enable delta compression when replicating the zones
-                DeltaCompression::<Zones>::default(),
-                Replicate {
-                    // TODO: add network relevance
-                    controlled_by: ControlledBy {
-                        target: NetworkTarget::Single(client_id),
-                        ..default()
-                    },
+                controlled_by: ControlledBy {
+                    target: NetworkTarget::Single(client_id),
                     ..default()
                 },
-            ))
-            .remove::<(ReplicateHierarchy, SyncTarget)>()
-            .id();
-        commands.entity(bike).add_child(trail).add_child(zones);
-    }
+                // clients that join later receive the trails and zones progressively (see `sync`)
+                visibility: VisibilityMode::InterestManagement,
+                ..default()
+            },
+            room,
+        ))
+        .remove::<ReplicateHierarchy>()
+        .id();
+    //
+    let zones = commands
+        .spawn((
+            ZonesBundle::new(client_id),
+            // Enable delta compression when replicating the zones
+            DeltaCompression::<Zones>::default(),
+            Replicate {
+                // the owner predicts its own zones
+                sync: SyncTarget {
+                    prediction: NetworkTarget::Single(client_id),
+                    ..default()
+                },
+                controlled_by: ControlledBy {
+                    target: NetworkTarget::Single(client_id),
+                    ..default()
+                },
+                // clients that join later receive the trails and zones progressively (see `sync`)
+                visibility: VisibilityMode::InterestManagement,
+                ..default()
+            },
+            room,
+        ))
+        .remove::<ReplicateHierarchy>()
+        .id();
+    commands.entity(player).add_child(trail).add_child(zones);
+    spawn_bike(commands, player, client_id, room, pos, spawn_time);
+    player
+}
+
+/// Spawn a new bike for a player. The bike is a child of the player entity, and gets despawned
+/// when the player dies.
+pub(crate) fn spawn_bike(
+    commands: &mut Commands,
+    player: Entity,
+    client_id: ClientId,
+    room: InRoom,
+    pos: Vec2,
+    spawn_time: Duration,
+) -> Entity {
+    let bike = commands
+        .spawn((
+            BikeBundle::new_at(client_id, pos, spawn_time),
+            RigidBody::Kinematic,
+            PositionHistory::default(),
+            Replicate {
+                sync: SyncTarget {
+                    prediction: NetworkTarget::Single(client_id),
+                    interpolation: NetworkTarget::AllExceptSingle(client_id),
+                },
+                controlled_by: ControlledBy {
+                    target: NetworkTarget::Single(client_id),
+                    ..default()
+                },
+                visibility: VisibilityMode::InterestManagement,
+                ..default()
+            },
+            room,
+        ))
+        // do not replicate the hierarchy at all, because the ParentSync component might be invalid
+        // instead we will build the hierarchy on the client side manually
+        .remove::<ReplicateHierarchy>()
+        .id();
+    commands.entity(player).add_child(bike);
+    bike
 }
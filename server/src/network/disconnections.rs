@@ -1,15 +1,16 @@
 use crate::network::connections::AvailableColors;
 use bevy::prelude::*;
 use lightyear::prelude::ServerDisconnectEvent;
-use shared::player::bike::{BikeMarker, ColorComponent};
+use shared::player::bike::ColorComponent;
+use shared::player::PlayerMarker;
 
 // NOTE: we cannot use Trigger<DisconnectEvent> because we have an observer
 pub(crate) fn observe_disconnect(
     trigger: Trigger<OnRemove, ColorComponent>,
-    bikes: Query<&ColorComponent, With<BikeMarker>>,
+    players: Query<&ColorComponent, With<PlayerMarker>>,
     mut colors: ResMut<AvailableColors>,
 ) {
-    if let Ok(color) = bikes.get(trigger.entity()) {
+    if let Ok(color) = players.get(trigger.entity()) {
         info!("Player disconnected: {:?}", color.0);
         colors.add_color(color.0);
     }
@@ -0,0 +1,146 @@
+//! Check that the clients use the same protocol as the server
+//!
+//! A client sends its `ProtocolVersion` right after connecting; the server only handles its
+//! other messages once the versions match, and disconnects it otherwise (or if it never sends one).
+//! A verified client then receives the settings that it needs to predict the game
+use bevy::prelude::*;
+use bevy::utils::{Duration, HashMap, HashSet};
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::message::{
+    ProtocolMismatchMessage, ProtocolVersionMessage, TrailSettingsMessage,
+};
+use shared::network::protocol::{Channel1, ProtocolVersion};
+use shared::player::trail::TrailSettings;
+
+/// Clients that did not send their protocol version after this long are disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Rejected clients are disconnected a bit later, so that they receive the mismatch message
+const REJECTION_DELAY: Duration = Duration::from_secs(1);
+
+pub(crate) struct HandshakePlugin;
+
+impl Plugin for HandshakePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Handshakes>();
+        app.add_systems(
+            Update,
+            (
+                start_handshake,
+                check_protocol_version,
+                handshake_timeout,
+                disconnect_rejected,
+            )
+                .chain()
+                .in_set(HandshakeSet)
+                .run_if(is_started),
+        );
+    }
+}
+
+/// Systems that handle the client messages must run after this set, so that they see the clients
+/// that were just verified
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct HandshakeSet;
+
+#[derive(Resource, Default, Debug)]
+pub(crate) struct Handshakes {
+    /// Connection time of the clients that did not send their protocol version yet
+    pending: HashMap<ClientId, Duration>,
+    verified: HashSet<ClientId>,
+    /// Time at which the clients with a different protocol version get disconnected
+    rejected: HashMap<ClientId, Duration>,
+}
+
+impl Handshakes {
+    /// Whether the client uses the same protocol as the server
+    pub(crate) fn is_verified(&self, client_id: ClientId) -> bool {
+        self.verified.contains(&client_id)
+    }
+}
+
+fn start_handshake(
+    time: Res<Time>,
+    mut connections: EventReader<ServerConnectEvent>,
+    mut disconnections: EventReader<ServerDisconnectEvent>,
+    mut handshakes: ResMut<Handshakes>,
+) {
+    for event in connections.read() {
+        handshakes.pending.insert(event.client_id(), time.elapsed());
+    }
+    for event in disconnections.read() {
+        handshakes.pending.remove(&event.client_id());
+        handshakes.verified.remove(&event.client_id());
+        handshakes.rejected.remove(&event.client_id());
+    }
+}
+
+fn check_protocol_version(
+    time: Res<Time>,
+    protocol_version: Res<ProtocolVersion>,
+    trail_settings: Res<TrailSettings>,
+    mut messages: ResMut<Events<MessageEvent<ProtocolVersionMessage>>>,
+    mut handshakes: ResMut<Handshakes>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    for message in messages.drain() {
+        let client_id = message.context;
+        handshakes.pending.remove(&client_id);
+        if message.message.version == protocol_version.0 {
+            handshakes.verified.insert(client_id);
+            let _ = manager.send_message::<Channel1, _>(
+                client_id,
+                &TrailSettingsMessage {
+                    ticks_per_point: trail_settings.ticks_per_point,
+                },
+            );
+            continue;
+        }
+        info!(
+            ?client_id,
+            client_version = message.message.version,
+            server_version = protocol_version.0,
+            "Disconnecting a client with a different protocol version"
+        );
+        let _ = manager.send_message::<Channel1, _>(
+            client_id,
+            &ProtocolMismatchMessage {
+                server_version: protocol_version.0,
+            },
+        );
+        handshakes
+            .rejected
+            .insert(client_id, time.elapsed() + REJECTION_DELAY);
+    }
+}
+
+/// Clients built before the handshake existed never send their version
+fn handshake_timeout(mut commands: Commands, time: Res<Time>, mut handshakes: ResMut<Handshakes>) {
+    let now = time.elapsed();
+    handshakes.pending.retain(|client_id, connected_at| {
+        if now - *connected_at < HANDSHAKE_TIMEOUT {
+            return true;
+        }
+        info!(
+            ?client_id,
+            "Disconnecting a client that did not send its protocol version"
+        );
+        commands.disconnect(*client_id);
+        false
+    });
+}
+
+fn disconnect_rejected(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut handshakes: ResMut<Handshakes>,
+) {
+    let now = time.elapsed();
+    handshakes.rejected.retain(|client_id, disconnect_at| {
+        if now < *disconnect_at {
+            return true;
+        }
+        commands.disconnect(*client_id);
+        false
+    });
+}
@@ -0,0 +1,84 @@
+//! Report the number of players to the matchmaker, so that it can send new players to this server
+use crate::network::rooms::Rooms;
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use lightyear::prelude::server::*;
+use shared::network::config::Transports;
+use shared::network::matchmaker::{
+    send_request, transport_name, MatchmakerRequest, MatchmakerResponse, ServerReport,
+    REPORT_INTERVAL,
+};
+use shared::network::protocol::ProtocolVersion;
+use std::net::SocketAddr;
+
+pub(crate) struct MatchmakerPlugin {
+    /// Address of the matchmaker
+    pub(crate) matchmaker: SocketAddr,
+    /// Address of this server, as seen by the clients
+    pub(crate) public_addr: SocketAddr,
+    pub(crate) transport: Transports,
+}
+
+impl Plugin for MatchmakerPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let matchmaker = self.matchmaker;
+        // the requests block, so they are sent from their own thread
+        std::thread::spawn(move || send_reports(matchmaker, receiver));
+        app.insert_resource(MatchmakerReporter {
+            sender,
+            public_addr: self.public_addr,
+            transport: transport_name(self.transport),
+            timer: Timer::new(REPORT_INTERVAL, TimerMode::Repeating),
+        });
+        app.add_systems(Update, report_players.run_if(is_started));
+    }
+}
+
+#[derive(Resource)]
+struct MatchmakerReporter {
+    sender: Sender<ServerReport>,
+    public_addr: SocketAddr,
+    transport: String,
+    timer: Timer,
+}
+
+fn send_reports(matchmaker: SocketAddr, reports: Receiver<ServerReport>) {
+    let mut registered = false;
+    for report in reports {
+        match send_request(matchmaker, &MatchmakerRequest::Report(report)) {
+            Ok(MatchmakerResponse::Registered) => {
+                if !registered {
+                    info!("Registered with the matchmaker at {matchmaker}");
+                    registered = true;
+                }
+            }
+            Ok(response) => warn!("Unexpected response from the matchmaker: {response:?}"),
+            Err(e) => {
+                warn!("Could not reach the matchmaker at {matchmaker}: {e}");
+                registered = false;
+            }
+        }
+    }
+}
+
+fn report_players(
+    time: Res<Time>,
+    mut reporter: ResMut<MatchmakerReporter>,
+    mut reported: Local<bool>,
+    rooms: Res<Rooms>,
+    protocol_version: Res<ProtocolVersion>,
+) {
+    // report right away when the server starts, then periodically
+    if !reporter.timer.tick(time.delta()).just_finished() && *reported {
+        return;
+    }
+    *reported = true;
+    let _ = reporter.sender.send(ServerReport {
+        addr: reporter.public_addr,
+        protocol_version: protocol_version.0,
+        transport: reporter.transport.clone(),
+        players: rooms.player_count() as u32,
+        max_players: rooms.public_capacity() as u32,
+    });
+}
@@ -0,0 +1,332 @@
+//! Server metrics, exposed in the Prometheus text format on `http://<server>:<port>/metrics`.
+//!
+//! The metrics are collected by bevy systems and rendered to text once per second; a separate
+//! thread answers the HTTP requests with the latest rendering, so scraping never blocks the game.
+use crate::network::rooms::{InRoom, Rooms};
+use crate::player::death::PlayerKillEvent;
+use bevy::diagnostic::{DiagnosticsPlugin, DiagnosticsStore};
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use bevy::utils::{Duration, HashMap, Instant};
+use lightyear::prelude::server::*;
+use lightyear::shared::replication::delta::Diffable;
+use lightyear::transport::io::IoDiagnosticsPlugin;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+/// Number of ticks used to compute the tick duration percentiles
+const TICK_SAMPLES: usize = 1024;
+const RENDER_INTERVAL: Duration = Duration::from_secs(1);
+const KILLS_WINDOW: Duration = Duration::from_secs(60);
+/// A slow or idle scraper must not hold the thread that answers the requests
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The bytes sent for the trails and zones are only measured once every this many frames, since
+/// it serializes them again
+const BYTES_SAMPLE_INTERVAL: u32 = 16;
+
+pub(crate) struct MetricsPlugin {
+    pub(crate) port: u16,
+}
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, app: &mut App) {
+        // the io diagnostics give the bandwidth used by the server
+        if !app.is_plugin_added::<DiagnosticsPlugin>() {
+            app.add_plugins(DiagnosticsPlugin);
+        }
+        let rendered = Arc::new(Mutex::new(String::new()));
+        let port = self.port;
+        let served = rendered.clone();
+        std::thread::spawn(move || serve_metrics(port, served));
+        app.insert_resource(ServerMetrics {
+            rendered,
+            ..default()
+        });
+        app.add_systems(FixedFirst, start_tick);
+        app.add_systems(FixedLast, end_tick);
+        app.add_systems(
+            Update,
+            (
+                count_connections,
+                measure_replicated_components,
+                render_metrics.run_if(on_timer(RENDER_INTERVAL)),
+            )
+                .chain(),
+        );
+        app.observe(count_kill);
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct ServerMetrics {
+    /// Latest rendering of the metrics, served over HTTP
+    rendered: Arc<Mutex<String>>,
+    connected_clients: usize,
+    tick_start: Option<Instant>,
+    /// Durations of the last ticks, in seconds
+    tick_durations: VecDeque<f64>,
+    /// Estimated bytes sent per replicated component
+    bytes_sent: HashMap<&'static str, u64>,
+    /// Value of the trails and zones on the frame before a measure, to measure the size of their
+    /// diffs
+    trails: HashMap<Entity, Trail>,
+    zones: HashMap<Entity, Zones>,
+    zone_ops_seconds: f64,
+    zone_ops: u64,
+    kills: u64,
+    recent_kills: VecDeque<Instant>,
+}
+
+impl ServerMetrics {
+    /// Record the time spent in the boolean operations between zones
+    pub(crate) fn record_zone_op(&mut self, duration: Duration) {
+        self.zone_ops_seconds += duration.as_secs_f64();
+        self.zone_ops += 1;
+    }
+
+    fn tick_percentile(&self, percentile: f64) -> f64 {
+        let mut durations: Vec<f64> = self.tick_durations.iter().copied().collect();
+        durations.sort_by(f64::total_cmp);
+        let index = ((durations.len() as f64 - 1.0) * percentile).round() as usize;
+        durations.get(index).copied().unwrap_or_default()
+    }
+}
+
+fn start_tick(mut metrics: ResMut<ServerMetrics>) {
+    metrics.tick_start = Some(Instant::now());
+}
+
+fn end_tick(mut metrics: ResMut<ServerMetrics>) {
+    let Some(start) = metrics.tick_start.take() else {
+        return;
+    };
+    if metrics.tick_durations.len() == TICK_SAMPLES {
+        metrics.tick_durations.pop_front();
+    }
+    metrics
+        .tick_durations
+        .push_back(start.elapsed().as_secs_f64());
+}
+
+fn count_connections(
+    mut connections: EventReader<ServerConnectEvent>,
+    mut disconnections: EventReader<ServerDisconnectEvent>,
+    mut metrics: ResMut<ServerMetrics>,
+) {
+    metrics.connected_clients += connections.read().count();
+    metrics.connected_clients = metrics
+        .connected_clients
+        .saturating_sub(disconnections.read().count());
+}
+
+fn count_kill(_trigger: Trigger<PlayerKillEvent>, mut metrics: ResMut<ServerMetrics>) {
+    metrics.kills += 1;
+    metrics.recent_kills.push_back(Instant::now());
+}
+
+/// Estimate the bytes sent for the trails and zones: lightyear sends them as diffs, to every
+/// client of their room. The diffs are measured on one frame out of `BYTES_SAMPLE_INTERVAL`, and
+/// the value of the components is kept on the frame before
+fn measure_replicated_components(
+    mut frame: Local<u32>,
+    mut metrics: ResMut<ServerMetrics>,
+    rooms: Res<Rooms>,
+    trails: Query<(Entity, Ref<Trail>, &InRoom)>,
+    zones: Query<(Entity, Ref<Zones>, &InRoom)>,
+) {
+    *frame = (*frame + 1) % BYTES_SAMPLE_INTERVAL;
+    let metrics = metrics.as_mut();
+    if *frame == BYTES_SAMPLE_INTERVAL - 1 {
+        metrics.trails = trails
+            .iter()
+            .map(|(entity, trail, _)| (entity, trail.clone()))
+            .collect();
+        metrics.zones = zones
+            .iter()
+            .map(|(entity, zones, _)| (entity, zones.clone()))
+            .collect();
+        return;
+    }
+    if *frame != 0 {
+        return;
+    }
+    let scale = BYTES_SAMPLE_INTERVAL as u64;
+    for (entity, trail, room) in trails.iter().filter(|(_, trail, _)| trail.is_changed()) {
+        let size = match metrics.trails.get(&entity) {
+            Some(previous) => bincode::serialized_size(&previous.diff(&trail)),
+            None => bincode::serialized_size(&*trail),
+        };
+        let clients = rooms.clients_in(room.0).len() as u64;
+        *metrics.bytes_sent.entry("trail").or_default() +=
+            size.unwrap_or_default() * clients * scale;
+    }
+    for (entity, zones, room) in zones.iter().filter(|(_, zones, _)| zones.is_changed()) {
+        let size = match metrics.zones.get(&entity) {
+            Some(previous) => bincode::serialized_size(&previous.diff(&zones)),
+            None => bincode::serialized_size(&*zones),
+        };
+        let clients = rooms.clients_in(room.0).len() as u64;
+        *metrics.bytes_sent.entry("zones").or_default() +=
+            size.unwrap_or_default() * clients * scale;
+    }
+    metrics.trails.clear();
+    metrics.zones.clear();
+}
+
+/// Resident memory of the server process, in bytes
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+fn render_metrics(
+    mut metrics: ResMut<ServerMetrics>,
+    diagnostics: Res<DiagnosticsStore>,
+    replicated: Query<(), With<Replicating>>,
+) {
+    let now = Instant::now();
+    while metrics
+        .recent_kills
+        .front()
+        .is_some_and(|kill| now - *kill > KILLS_WINDOW)
+    {
+        metrics.recent_kills.pop_front();
+    }
+
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, f64)]| {
+        let _ = writeln!(text, "# HELP {name} {help}");
+        let _ = writeln!(text, "# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(text, "{name}{labels} {value}");
+        }
+    };
+    metric(
+        "bikes_connected_clients",
+        "gauge",
+        "Number of connected clients",
+        &[("", metrics.connected_clients as f64)],
+    );
+    metric(
+        "bikes_tick_duration_seconds",
+        "summary",
+        "Duration of the fixed update ticks",
+        &[
+            ("{quantile=\"0.5\"}", metrics.tick_percentile(0.5)),
+            ("{quantile=\"0.9\"}", metrics.tick_percentile(0.9)),
+            ("{quantile=\"0.99\"}", metrics.tick_percentile(0.99)),
+        ],
+    );
+    metric(
+        "bikes_replicated_entities",
+        "gauge",
+        "Number of replicated entities",
+        &[("", replicated.iter().count() as f64)],
+    );
+    let trail_bytes = metrics.bytes_sent.get("trail").copied().unwrap_or_default();
+    let zones_bytes = metrics.bytes_sent.get("zones").copied().unwrap_or_default();
+    metric(
+        "bikes_component_bytes_sent_total",
+        "counter",
+        "Estimated bytes sent per replicated component",
+        &[
+            ("{component=\"trail\"}", trail_bytes as f64),
+            ("{component=\"zones\"}", zones_bytes as f64),
+        ],
+    );
+    let bandwidth = |path| {
+        diagnostics
+            .get(path)
+            .and_then(|diagnostic| diagnostic.smoothed())
+            .unwrap_or_default()
+    };
+    metric(
+        "bikes_network_kilobytes_per_second",
+        "gauge",
+        "Bandwidth used by the server",
+        &[
+            (
+                "{direction=\"in\"}",
+                bandwidth(&IoDiagnosticsPlugin::BYTES_IN),
+            ),
+            (
+                "{direction=\"out\"}",
+                bandwidth(&IoDiagnosticsPlugin::BYTES_OUT),
+            ),
+        ],
+    );
+    metric(
+        "bikes_zone_ops_seconds_total",
+        "counter",
+        "Time spent in the boolean operations between zones",
+        &[("", metrics.zone_ops_seconds)],
+    );
+    metric(
+        "bikes_zone_ops_total",
+        "counter",
+        "Number of boolean operations between zones",
+        &[("", metrics.zone_ops as f64)],
+    );
+    metric(
+        "bikes_kills_total",
+        "counter",
+        "Number of kills",
+        &[("", metrics.kills as f64)],
+    );
+    metric(
+        "bikes_kills_per_minute",
+        "gauge",
+        "Number of kills in the last minute",
+        &[("", metrics.recent_kills.len() as f64)],
+    );
+    if let Some(memory) = resident_memory() {
+        metric(
+            "bikes_resident_memory_bytes",
+            "gauge",
+            "Resident memory of the server process",
+            &[("", memory as f64)],
+        );
+    }
+    *metrics.rendered.lock().unwrap() = text;
+}
+
+fn serve_metrics(port: u16, rendered: Arc<Mutex<String>>) {
+    let addr = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port);
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not serve the metrics on port {port}: {e}");
+            return;
+        }
+    };
+    info!("Serving the metrics on http://{addr}/metrics");
+    for stream in listener.incoming().flatten() {
+        if let Err(e) = answer(stream, &rendered) {
+            debug!("Metrics request failed: {e}");
+        }
+    }
+}
+
+fn answer(mut stream: TcpStream, rendered: &Mutex<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let response = if request_line.starts_with("GET /metrics") {
+        let body = rendered.lock().unwrap().clone();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes())
+}
@@ -1,38 +1,57 @@
 /// Server networking related plugins
-mod config;
+pub(crate) mod config;
 pub mod connections;
 pub mod disconnections;
+pub(crate) mod handshake;
+pub(crate) mod matchmaker;
+pub(crate) mod metrics;
+pub mod rooms;
+mod sync;
 
 use bevy::prelude::*;
 use lightyear::prelude::server::*;
 
-use shared::network::config::Transports;
 use shared::network::protocol::ProtocolPlugin;
+use shared::player::PlayerMarker;
 
-pub struct NetworkPlugin {
-    pub server_port: u16,
-    pub transport: Transports,
-}
+/// The lightyear `ServerPlugins` must be added before this plugin
+pub struct NetworkPlugin;
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut App) {
         // plugins
-        app.add_plugins(config::build_lightyear_server(
-            self.server_port,
-            self.transport,
-        ));
-        app.add_plugins(ProtocolPlugin);
+        // in host-server mode, the client already added the protocol
+        if !app.is_plugin_added::<ProtocolPlugin>() {
+            app.add_plugins(ProtocolPlugin);
+        }
+        app.add_plugins(handshake::HandshakePlugin);
+        app.add_plugins(rooms::RoomsPlugin);
+        app.add_plugins(sync::InitialSyncPlugin);
 
         // resources
         app.init_resource::<connections::AvailableColors>();
 
         // systems
-        app.add_systems(Startup, start_server);
-        app.add_systems(Update, connections::spawn_bike);
+        app.add_systems(
+            Update,
+            connections::spawn_player
+                .after(handshake::HandshakeSet)
+                .run_if(is_started),
+        );
+        app.add_systems(OnEnter(NetworkingState::Stopped), despawn_players);
         app.observe(disconnections::observe_disconnect);
     }
 }
 
-fn start_server(mut commands: Commands) {
+/// The server is started by the standalone server at startup, or by the client when hosting a game
+pub(crate) fn start_server(mut commands: Commands) {
     commands.start_server();
 }
+
+/// When a hosted game ends, remove every player (including the bots) so that the next game
+/// starts from scratch
+fn despawn_players(mut commands: Commands, players: Query<Entity, With<PlayerMarker>>) {
+    for player in players.iter() {
+        commands.entity(player).despawn_recursive();
+    }
+}
@@ -0,0 +1,353 @@
+//! Several independent arenas in the same server, using lightyear rooms.
+//!
+//! Every player, bike, trail and zones entity belongs to a room ([`InRoom`]); the clients only
+//! receive the entities of their room, and the game logic only lets entities of the same room
+//! interact. Public rooms are created when the server starts, and players can create private
+//! rooms protected by a password.
+use crate::network::handshake::{HandshakeSet, Handshakes};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use rand::Rng;
+use shared::map::{MapMarker, MapRadius, MAP_SIZE};
+use shared::network::message::{RoomChoice, RoomInfo, RoomListMessage, RoomListRequest};
+use shared::network::protocol::Channel1;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+
+/// By default, the server runs a single public room
+pub const DEFAULT_PUBLIC_ROOMS: usize = 1;
+pub const DEFAULT_MAX_PLAYERS: usize = 12;
+const ROOM_CODE_LENGTH: usize = 5;
+
+/// Number of public rooms created when the server starts
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PublicRooms {
+    pub count: usize,
+}
+
+impl Default for PublicRooms {
+    fn default() -> Self {
+        Self {
+            count: DEFAULT_PUBLIC_ROOMS,
+        }
+    }
+}
+
+/// The room that an entity belongs to
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InRoom(pub RoomId);
+
+/// Rules that can differ between rooms
+#[derive(Debug, Clone)]
+pub struct RoomRules {
+    pub max_players: usize,
+}
+
+impl Default for RoomRules {
+    fn default() -> Self {
+        Self {
+            max_players: DEFAULT_MAX_PLAYERS,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Room {
+    pub code: String,
+    pub name: String,
+    password: Option<String>,
+    pub rules: RoomRules,
+    /// Map entity of the room
+    pub map: Entity,
+    /// Clients that play in the room
+    pub clients: HashSet<ClientId>,
+    /// Clients that only watch the room; they don't count towards `max_players`
+    pub spectators: HashSet<ClientId>,
+}
+
+impl Room {
+    fn info(&self) -> RoomInfo {
+        RoomInfo {
+            code: self.code.clone(),
+            name: self.name.clone(),
+            players: self.clients.len() as u32,
+            max_players: self.rules.max_players as u32,
+            private: self.password.is_some(),
+        }
+    }
+}
+
+/// Sent when a client enters a room
+#[derive(Event, Debug, Clone, Copy)]
+pub(crate) struct JoinedRoom {
+    pub(crate) client_id: ClientId,
+    pub(crate) room: RoomId,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct Rooms {
+    rooms: HashMap<RoomId, Room>,
+    /// Public rooms, in creation order
+    public: Vec<RoomId>,
+    next_id: u64,
+    /// Rules of the rooms created from now on
+    rules: RoomRules,
+}
+
+impl Rooms {
+    pub fn get(&self, room: RoomId) -> Option<&Room> {
+        self.rooms.get(&room)
+    }
+
+    /// Room that the bots and the players without a preference join
+    pub fn default_room(&self) -> Option<RoomId> {
+        self.public.first().copied()
+    }
+
+    /// Room that the client plays in or watches
+    pub fn client_room(&self, client_id: ClientId) -> Option<RoomId> {
+        self.rooms
+            .iter()
+            .find(|(_, room)| {
+                room.clients.contains(&client_id) || room.spectators.contains(&client_id)
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Clients that play in or watch `room`
+    pub fn clients_in(&self, room: RoomId) -> Vec<ClientId> {
+        self.get(room)
+            .map(|room| {
+                room.clients
+                    .iter()
+                    .chain(room.spectators.iter())
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of players in all the rooms
+    pub fn player_count(&self) -> usize {
+        self.rooms.values().map(|room| room.clients.len()).sum()
+    }
+
+    /// Number of players that the public rooms can hold
+    pub fn public_capacity(&self) -> usize {
+        self.public
+            .iter()
+            .map(|id| self.rooms[id].rules.max_players)
+            .sum()
+    }
+
+    /// Change the maximum number of players of every room
+    pub(crate) fn set_max_players(&mut self, max_players: usize) {
+        self.rules.max_players = max_players;
+        for room in self.rooms.values_mut() {
+            room.rules.max_players = max_players;
+        }
+    }
+
+    fn create(
+        &mut self,
+        commands: &mut Commands,
+        name: String,
+        password: Option<String>,
+    ) -> RoomId {
+        let id = RoomId(self.next_id);
+        self.next_id += 1;
+        let code = loop {
+            let code = random_room_code();
+            if !self.rooms.values().any(|room| room.code == code) {
+                break code;
+            }
+        };
+        let map = commands
+            .spawn((
+                MapRadius { radius: MAP_SIZE },
+                MapMarker,
+                Name::new(format!("Map {code}")),
+                InRoom(id),
+            ))
+            .id();
+        info!(?id, code, name, "Created room");
+        if password.is_none() {
+            self.public.push(id);
+        }
+        self.rooms.insert(
+            id,
+            Room {
+                code,
+                name,
+                password,
+                rules: self.rules.clone(),
+                map,
+                clients: HashSet::default(),
+                spectators: HashSet::default(),
+            },
+        );
+        id
+    }
+
+    /// Find (or create) the room that a player asked for
+    pub(crate) fn resolve(
+        &mut self,
+        commands: &mut Commands,
+        choice: &RoomChoice,
+    ) -> Result<RoomId, String> {
+        let id = match choice {
+            RoomChoice::Any => self
+                .public
+                .iter()
+                .filter(|id| {
+                    let room = &self.rooms[*id];
+                    room.clients.len() < room.rules.max_players
+                })
+                .min_by_key(|id| self.rooms[*id].clients.len())
+                .copied()
+                .ok_or_else(|| "All the rooms are full".to_string())?,
+            RoomChoice::Join { code, password } => {
+                let (id, room) = self
+                    .rooms
+                    .iter()
+                    .find(|(_, room)| room.code.eq_ignore_ascii_case(code))
+                    .ok_or_else(|| format!("No room with the code {code}"))?;
+                if room.password.is_some() && room.password != *password {
+                    return Err("Wrong password".to_string());
+                }
+                if room.clients.len() >= room.rules.max_players {
+                    return Err("The room is full".to_string());
+                }
+                *id
+            }
+            RoomChoice::CreatePrivate { password } => {
+                self.create(commands, "Private room".to_string(), Some(password.clone()))
+            }
+        };
+        Ok(id)
+    }
+
+    pub(crate) fn add_client(&mut self, client_id: ClientId, room: RoomId) {
+        for room in self.rooms.values_mut() {
+            room.spectators.remove(&client_id);
+        }
+        if let Some(room) = self.rooms.get_mut(&room) {
+            room.clients.insert(client_id);
+        }
+    }
+
+    /// The client stops playing but keeps watching its room, which frees its slot
+    pub(crate) fn make_spectator(&mut self, client_id: ClientId) {
+        for room in self.rooms.values_mut() {
+            if room.clients.remove(&client_id) {
+                room.spectators.insert(client_id);
+            }
+        }
+    }
+}
+
+fn random_room_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+        .collect()
+}
+
+pub(crate) struct RoomsPlugin;
+
+impl Plugin for RoomsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PublicRooms>();
+        app.init_resource::<Rooms>();
+        app.add_event::<JoinedRoom>();
+        app.add_systems(OnEnter(NetworkingState::Started), create_public_rooms);
+        app.add_systems(OnEnter(NetworkingState::Stopped), remove_rooms);
+        app.add_systems(
+            Update,
+            (send_room_list.after(HandshakeSet), leave_room_on_disconnect).run_if(is_started),
+        );
+        app.observe(add_to_lightyear_room);
+    }
+}
+
+pub(crate) fn create_public_rooms(
+    mut commands: Commands,
+    public_rooms: Res<PublicRooms>,
+    mut rooms: ResMut<Rooms>,
+) {
+    for i in 0..public_rooms.count.max(1) {
+        rooms.create(&mut commands, format!("Arena {}", i + 1), None);
+    }
+}
+
+fn remove_rooms(mut commands: Commands, mut rooms: ResMut<Rooms>) {
+    for room in rooms.rooms.values() {
+        commands.entity(room.map).despawn_recursive();
+    }
+    *rooms = Rooms::default();
+}
+
+/// Replicate the entities of a room to the clients of the room.
+/// The trails and zones are not part of the lightyear room: they are synced progressively,
+/// see `sync`.
+fn add_to_lightyear_room(
+    trigger: Trigger<OnAdd, InRoom>,
+    entities: Query<(&InRoom, Has<Trail>, Has<Zones>), With<Replicating>>,
+    mut room_manager: ResMut<RoomManager>,
+) {
+    if let Ok((room, false, false)) = entities.get(trigger.entity()) {
+        room_manager.add_entity(trigger.entity(), room.0);
+    }
+}
+
+fn send_room_list(
+    mut requests: ResMut<Events<MessageEvent<RoomListRequest>>>,
+    handshakes: Res<Handshakes>,
+    rooms: Res<Rooms>,
+    mut manager: ResMut<ConnectionManager>,
+) {
+    for request in requests.drain() {
+        if !handshakes.is_verified(request.context) {
+            continue;
+        }
+        let mut list: Vec<(RoomId, RoomInfo)> = rooms
+            .rooms
+            .iter()
+            .map(|(id, room)| (*id, room.info()))
+            .collect();
+        list.sort_by_key(|(id, _)| id.0);
+        let _ = manager.send_message::<Channel1, _>(
+            request.context,
+            &RoomListMessage {
+                rooms: list.into_iter().map(|(_, info)| info).collect(),
+            },
+        );
+    }
+}
+
+/// Private rooms are removed when their last player leaves
+fn leave_room_on_disconnect(
+    mut commands: Commands,
+    mut disconnections: EventReader<ServerDisconnectEvent>,
+    mut rooms: ResMut<Rooms>,
+    mut room_manager: ResMut<RoomManager>,
+) {
+    for event in disconnections.read() {
+        let client_id = event.client_id();
+        let Some(id) = rooms.client_room(client_id) else {
+            continue;
+        };
+        room_manager.remove_client(client_id, id);
+        let rooms = rooms.as_mut();
+        let room = rooms.rooms.get_mut(&id).unwrap();
+        room.clients.remove(&client_id);
+        room.spectators.remove(&client_id);
+        if room.clients.is_empty() && room.spectators.is_empty() && room.password.is_some() {
+            info!(?id, code = room.code, "Removing empty private room");
+            commands.entity(room.map).despawn_recursive();
+            rooms.rooms.remove(&id);
+        }
+    }
+}
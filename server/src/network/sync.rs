@@ -0,0 +1,181 @@
+//! Progressive sync of the world for clients that join a game in progress.
+//!
+//! The trails and zones are replicated with interest management: instead of receiving every
+//! entity of its room in full at once, a new client first gets simplified outlines of the zones,
+//! then the entities become visible a few at a time, the closest to the spawn point first.
+use crate::network::rooms::{InRoom, JoinedRoom, Rooms};
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use bevy::utils::{Duration, HashMap};
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::config::SERVER_SEND_HZ;
+use shared::network::message::{SyncProgressMessage, ZoneOutline, ZoneOutlinesMessage};
+use shared::network::protocol::Channel1;
+use shared::player::bike::ClientIdMarker;
+use shared::player::trail::Trail;
+use shared::player::zone::Zones;
+use std::collections::VecDeque;
+
+/// Approximate number of bytes of trails and zones that become visible to a syncing client per
+/// replication send
+const SYNC_BYTES_PER_SEND: usize = 4_000;
+/// Maximum distance between a zone and its outline
+const OUTLINE_EPSILON: f32 = 20.0;
+/// Size of a point of a trail or a zone
+const POINT_SIZE: usize = 8;
+
+pub(crate) struct InitialSyncPlugin;
+
+impl Plugin for InitialSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SyncState>();
+        app.add_systems(
+            Update,
+            (
+                start_sync,
+                show_new_entities,
+                // reveal entities once per replication send, so that the bandwidth used doesn't
+                // depend on the frame rate of the server
+                progressive_sync.run_if(on_timer(Duration::from_secs_f64(1.0 / SERVER_SEND_HZ))),
+            )
+                .chain()
+                .run_if(is_started),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+struct SyncState {
+    pending: HashMap<ClientId, PendingSync>,
+}
+
+struct PendingSync {
+    /// Trails and zones that are not visible to the client yet, the closest first
+    remaining: VecDeque<Entity>,
+    total: usize,
+    /// Progress that was last sent to the client
+    sent: Option<usize>,
+}
+
+/// Distance between the spawn point and the closest point of a trail or of a zone
+fn distance_to_spawn<'a>(points: impl Iterator<Item = &'a Vec2>) -> f32 {
+    points.map(|point| point.length()).fold(f32::MAX, f32::min)
+}
+
+/// When a client enters a room, send it the zone outlines, and queue the trails and zones of the room
+fn start_sync(
+    mut joined_room: EventReader<JoinedRoom>,
+    mut disconnections: EventReader<ServerDisconnectEvent>,
+    mut state: ResMut<SyncState>,
+    mut manager: ResMut<ConnectionManager>,
+    trails: Query<(Entity, &Trail, &InRoom)>,
+    zones: Query<(Entity, &ClientIdMarker, &Zones, &InRoom)>,
+) {
+    for event in disconnections.read() {
+        state.pending.remove(&event.client_id());
+    }
+    for JoinedRoom { client_id, room } in joined_room.read().copied() {
+        let room = InRoom(room);
+        let mut entities: Vec<(f32, Entity)> = trails
+            .iter()
+            .filter(|(_, _, trail_room)| **trail_room == room)
+            .map(|(entity, trail, _)| (distance_to_spawn(trail.line.iter()), entity))
+            .chain(
+                zones
+                    .iter()
+                    .filter(|(_, _, _, zones_room)| **zones_room == room)
+                    .map(|(entity, _, zones, _)| {
+                        let points = zones.zones.iter().flat_map(|zone| zone.exterior.iter());
+                        (distance_to_spawn(points), entity)
+                    }),
+            )
+            .collect();
+        entities.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let outlines = zones
+            .iter()
+            .filter(|(_, _, _, zones_room)| **zones_room == room)
+            .flat_map(|(_, owner, zones, _)| {
+                zones.zones.iter().map(|zone| ZoneOutline {
+                    client_id: owner.0,
+                    exterior: zone.simplified_exterior(OUTLINE_EPSILON),
+                })
+            })
+            .collect();
+        let _ = manager.send_message::<Channel1, _>(client_id, &ZoneOutlinesMessage { outlines });
+
+        debug!(
+            ?client_id,
+            "Starting the initial sync of {} entities",
+            entities.len()
+        );
+        state.pending.insert(
+            client_id,
+            PendingSync {
+                total: entities.len(),
+                sent: None,
+                remaining: entities.into_iter().map(|(_, entity)| entity).collect(),
+            },
+        );
+    }
+}
+
+/// New trails and zones are small, so they are visible to the clients of the room right away
+fn show_new_entities(
+    rooms: Res<Rooms>,
+    mut visibility: ResMut<VisibilityManager>,
+    new_entities: Query<(Entity, &InRoom), Or<(Added<Trail>, Added<Zones>)>>,
+) {
+    for (entity, room) in new_entities.iter() {
+        for client_id in rooms.clients_in(room.0) {
+            visibility.gain_visibility(client_id, entity);
+        }
+    }
+}
+
+/// Make a few more trails and zones visible to each syncing client, within a bandwidth budget
+fn progressive_sync(
+    mut state: ResMut<SyncState>,
+    mut manager: ResMut<ConnectionManager>,
+    mut visibility: ResMut<VisibilityManager>,
+    trails: Query<&Trail>,
+    zones: Query<&Zones>,
+) {
+    state.pending.retain(|client_id, sync| {
+        let mut budget = SYNC_BYTES_PER_SEND;
+        while budget > 0 {
+            let Some(entity) = sync.remaining.pop_front() else {
+                break;
+            };
+            let points = if let Ok(trail) = trails.get(entity) {
+                trail.line.len()
+            } else if let Ok(zones) = zones.get(entity) {
+                zones
+                    .zones
+                    .iter()
+                    .map(|zone| {
+                        zone.exterior.len() + zone.interiors.iter().map(Vec::len).sum::<usize>()
+                    })
+                    .sum()
+            } else {
+                // the entity was despawned in the meantime
+                continue;
+            };
+            visibility.gain_visibility(*client_id, entity);
+            budget = budget.saturating_sub(points * POINT_SIZE);
+        }
+        let synced = sync.total - sync.remaining.len();
+        if sync.sent != Some(synced) {
+            let _ = manager.send_message::<Channel1, _>(
+                *client_id,
+                &SyncProgressMessage {
+                    synced: synced as u32,
+                    total: sync.total as u32,
+                },
+            );
+            sync.sent = Some(synced);
+        }
+        !sync.remaining.is_empty()
+    });
+}
@@ -0,0 +1,207 @@
+//! Server-side checks of the players, so that a modified client cannot cheat.
+//!
+//! The server is authoritative: the physics components are only replicated from the server to the
+//! clients, and the only thing a client controls is its `MousePositionRelative` input. The inputs
+//! are sanitized before they move the bikes: every invalid input counts as a violation, and a
+//! client that reaches `max_violations` is logged or kicked, depending on the `CheatAction`.
+//!
+//! The bikes are also checked for impossible speeds or jumps after the physics step. Since only
+//! the server moves them, such an anomaly is a bug of the server rather than a cheat, and is only
+//! logged.
+use avian2d::prelude::{LinearVelocity, Position};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use clap::ValueEnum;
+use leafwing_input_manager::axislike::DualAxisData;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::prelude::server::*;
+use lightyear::prelude::*;
+use shared::network::inputs::PlayerMovement;
+use shared::physics::FixedSet;
+use shared::player::bike::{
+    BikeMarker, ClientIdMarker, FAST_SPEED, FAST_SPEED_MAX_SPEED_DISTANCE,
+    OUR_ZONE_SPEED_MULTIPLIER,
+};
+use shared::player::PlayerMarker;
+
+/// Fastest that a bike can go, in its own zone with the mouse far away
+const MAX_BIKE_SPEED: f32 = FAST_SPEED * OUR_ZONE_SPEED_MULTIPLIER;
+/// Margin over `MAX_BIKE_SPEED`, for the acceleration overshoot and the map bounds
+const SPEED_TOLERANCE: f32 = 1.5;
+/// No screen is this large: a mouse distance above it was not produced by a real mouse
+const MAX_MOUSE_DISTANCE: f32 = 100_000.0;
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheatAction {
+    /// Only log the suspicious clients
+    Log,
+    /// Disconnect the suspicious clients
+    Kick,
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct AntiCheatSettings {
+    pub action: CheatAction,
+    /// Number of violations after which a client is considered to be cheating
+    pub max_violations: u32,
+}
+
+impl Default for AntiCheatSettings {
+    fn default() -> Self {
+        Self {
+            action: CheatAction::Log,
+            max_violations: 10,
+        }
+    }
+}
+
+/// Something that a client should not be able to do with an unmodified game
+#[derive(Event, Debug)]
+pub(crate) struct CheatViolation {
+    pub(crate) client_id: ClientId,
+    pub(crate) reason: String,
+}
+
+/// Number of violations of every connected client
+#[derive(Resource, Default, Debug)]
+struct Violations(HashMap<ClientId, u32>);
+
+/// Position of a bike at the previous tick
+#[derive(Component, Debug)]
+struct LastPosition(Vec2);
+
+pub(crate) struct AntiCheatPlugin;
+
+impl Plugin for AntiCheatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AntiCheatSettings>();
+        app.init_resource::<Violations>();
+        app.add_event::<CheatViolation>();
+        app.add_systems(
+            FixedUpdate,
+            (
+                sanitize_inputs.before(FixedSet::HandleInputs),
+                check_bike_movement.after(FixedSet::Physics),
+            )
+                .run_if(is_started),
+        );
+        app.add_systems(
+            Update,
+            (handle_violations, forget_disconnected).run_if(is_started),
+        );
+    }
+}
+
+/// Drop the inputs that are not numbers, and clamp the mouse distance to the range that has an
+/// effect on the movement
+fn sanitize_inputs(
+    mut players: Query<(&ClientIdMarker, &mut ActionState<PlayerMovement>), With<PlayerMarker>>,
+    mut violations: EventWriter<CheatViolation>,
+) {
+    for (client_id, mut action_state) in players.iter_mut() {
+        let Some(action_data) =
+            action_state.action_data_mut(&PlayerMovement::MousePositionRelative)
+        else {
+            continue;
+        };
+        let Some(axis_pair) = action_data.axis_pair.as_mut() else {
+            continue;
+        };
+        let mouse_position = axis_pair.xy();
+        if !mouse_position.is_finite() {
+            action_data.axis_pair = None;
+            violations.send(CheatViolation {
+                client_id: client_id.0,
+                reason: "non-finite mouse input".to_string(),
+            });
+            continue;
+        }
+        if mouse_position.length() > MAX_MOUSE_DISTANCE {
+            violations.send(CheatViolation {
+                client_id: client_id.0,
+                reason: format!("mouse input out of range: {mouse_position}"),
+            });
+        }
+        *axis_pair =
+            DualAxisData::from_xy(mouse_position.clamp_length_max(FAST_SPEED_MAX_SPEED_DISTANCE));
+    }
+}
+
+/// The bikes are only moved by the server, so a bike faster than possible, or that moved further
+/// than its speed allows, means that the server simulation went wrong
+fn check_bike_movement(
+    mut commands: Commands,
+    fixed_time: Res<Time<Fixed>>,
+    mut bikes: Query<
+        (
+            Entity,
+            &ClientIdMarker,
+            &Position,
+            &LinearVelocity,
+            Option<&mut LastPosition>,
+        ),
+        (With<BikeMarker>, With<Replicating>),
+    >,
+) {
+    let max_speed = MAX_BIKE_SPEED * SPEED_TOLERANCE;
+    let max_distance = max_speed * fixed_time.delta_seconds();
+    for (entity, client_id, position, velocity, last_position) in bikes.iter_mut() {
+        let speed = velocity.0.length();
+        if speed > max_speed {
+            error!(client_id = ?client_id.0, "Bike speed anomaly: {speed:.0}");
+        }
+        let Some(mut last_position) = last_position else {
+            commands.entity(entity).insert(LastPosition(position.0));
+            continue;
+        };
+        let distance = position.0.distance(last_position.0);
+        if distance > max_distance {
+            error!(
+                client_id = ?client_id.0,
+                "Bike teleport anomaly: moved {distance:.0} in one tick"
+            );
+        }
+        last_position.0 = position.0;
+    }
+}
+
+fn handle_violations(
+    mut commands: Commands,
+    settings: Res<AntiCheatSettings>,
+    mut events: EventReader<CheatViolation>,
+    mut violations: ResMut<Violations>,
+) {
+    for CheatViolation { client_id, reason } in events.read() {
+        // the bots and the player hosting the game are driven by the server itself
+        if matches!(client_id, ClientId::Local(_)) {
+            continue;
+        }
+        let count = violations.0.entry(*client_id).or_default();
+        *count += 1;
+        warn!(?client_id, count, "Cheat violation: {reason}");
+        if *count < settings.max_violations {
+            continue;
+        }
+        match settings.action {
+            CheatAction::Log => {
+                if *count == settings.max_violations {
+                    warn!(?client_id, "Client is suspected of cheating");
+                }
+            }
+            CheatAction::Kick => {
+                warn!(?client_id, "Kicking a client suspected of cheating");
+                commands.disconnect(*client_id);
+                *count = 0;
+            }
+        }
+    }
+}
+
+fn forget_disconnected(
+    mut disconnections: EventReader<ServerDisconnectEvent>,
+    mut violations: ResMut<Violations>,
+) {
+    for event in disconnections.read() {
+        violations.0.remove(&event.client_id());
+    }
+}
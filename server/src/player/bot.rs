@@ -0,0 +1,160 @@
+//! Bots: players controlled by the server, to practice offline or to fill up a server
+use crate::network::connections::{random_color, spawn_player_entities, AvailableColors};
+use crate::network::rooms::{create_public_rooms, InRoom, Rooms};
+use avian2d::prelude::{Position, Rotation};
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use leafwing_input_manager::axislike::DualAxisData;
+use leafwing_input_manager::prelude::ActionState;
+use lightyear::prelude::server::NetworkingState;
+use lightyear::prelude::ClientId;
+use rand::Rng;
+use shared::map::MAP_SIZE;
+use shared::network::inputs::PlayerMovement;
+use shared::physics::FixedSet;
+use shared::player::bike::{BikeMarker, ClientIdMarker};
+use shared::player::PlayerMarker;
+
+/// Maximum number of bots
+pub const MAX_BOTS: usize = 8;
+/// The bots only take a color from the palette while this many are left for the real players
+const COLORS_KEPT_FOR_PLAYERS: usize = 4;
+
+/// Bots steer back towards the center of the map when they are further than this
+const MAX_BOT_DISTANCE: f32 = MAP_SIZE * 0.6;
+/// Distance of the virtual mouse from the bike, which controls the speed
+const BOT_MOUSE_DISTANCE: f32 = 250.0;
+/// Range of the turn rates of the bots, in radians per second
+const MIN_TURN_RATE: f32 = 1.0;
+const MAX_TURN_RATE: f32 = 2.5;
+
+/// Number of bots to spawn when the server starts
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct Bots {
+    pub count: usize,
+}
+
+/// Marker for the player entities controlled by the server
+#[derive(Component, Debug)]
+pub struct Bot {
+    /// Current turn rate in radians per second; the sign gives the direction
+    turn_rate: f32,
+    /// When to pick a new turn rate
+    change_timer: Timer,
+}
+
+impl Bot {
+    fn new() -> Self {
+        let mut bot = Self {
+            turn_rate: 0.0,
+            change_timer: Timer::default(),
+        };
+        bot.change_direction();
+        bot
+    }
+
+    fn change_direction(&mut self) {
+        let mut rng = rand::thread_rng();
+        let turn_rate = rng.gen_range(MIN_TURN_RATE..MAX_TURN_RATE);
+        self.turn_rate = if rng.gen_bool(0.5) {
+            turn_rate
+        } else {
+            -turn_rate
+        };
+        self.change_timer = Timer::from_seconds(rng.gen_range(2.0..5.0), TimerMode::Once);
+    }
+}
+
+pub struct BotPlugin;
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bots>();
+        // the bots join one of the rooms created when the server starts
+        app.add_systems(
+            OnEnter(NetworkingState::Started),
+            spawn_bots.after(create_public_rooms),
+        );
+        // override the ActionState of the bots before it is used to move the bikes
+        app.add_systems(FixedUpdate, drive_bots.before(FixedSet::HandleInputs));
+    }
+}
+
+fn spawn_bots(
+    mut commands: Commands,
+    time: Res<Time>,
+    bots: Res<Bots>,
+    rooms: Res<Rooms>,
+    mut colors: ResMut<AvailableColors>,
+) {
+    // the bots play in the first public room
+    let Some(room) = rooms.default_room() else {
+        return;
+    };
+    for i in 0..bots.count.min(MAX_BOTS) {
+        spawn_bot(&mut commands, i, &mut colors, InRoom(room), time.elapsed());
+    }
+}
+
+/// Spawn the bot number `index` (starting from 0) in `room`
+pub(crate) fn spawn_bot(
+    commands: &mut Commands,
+    index: usize,
+    colors: &mut AvailableColors,
+    room: InRoom,
+    spawn_time: Duration,
+) -> Entity {
+    let color = if colors.0.len() > COLORS_KEPT_FOR_PLAYERS {
+        colors.pick_color()
+    } else {
+        random_color()
+    };
+    let player = spawn_player_entities(
+        commands,
+        bot_client_id(index),
+        format!("Bot {}", index + 1),
+        color,
+        room,
+        spawn_time,
+    );
+    commands
+        .entity(player)
+        .insert((Bot::new(), ActionState::<PlayerMovement>::default()));
+    player
+}
+
+/// Bots are not connected, so their client id only has to be different from the real clients
+pub(crate) fn bot_client_id(index: usize) -> ClientId {
+    ClientId::Local(u64::MAX - index as u64)
+}
+
+/// Drive in loops of random sizes, and come back towards the center of the map
+fn drive_bots(
+    time: Res<Time>,
+    mut bots: Query<
+        (&ClientIdMarker, &mut Bot, &mut ActionState<PlayerMovement>),
+        With<PlayerMarker>,
+    >,
+    bikes: Query<(&ClientIdMarker, &Position, &Rotation), With<BikeMarker>>,
+) {
+    for (client_id, mut bot, mut action_state) in bots.iter_mut() {
+        let Some((_, position, rotation)) = bikes.iter().find(|(id, _, _)| *id == client_id) else {
+            continue;
+        };
+        bot.change_timer.tick(time.delta());
+        if bot.change_timer.finished() {
+            bot.change_direction();
+        }
+        let heading = Vec2::new(rotation.cos, rotation.sin);
+        let wish_dir = if position.0.length() > MAX_BOT_DISTANCE {
+            -position.0.normalize_or_zero()
+        } else {
+            heading.rotate(Vec2::from_angle(bot.turn_rate * time.delta_seconds()))
+        };
+        action_state.press(&PlayerMovement::MousePositionRelative);
+        action_state
+            .action_data_mut(&PlayerMovement::MousePositionRelative)
+            .unwrap()
+            .axis_pair = Some(DualAxisData::from_xy(wish_dir * BOT_MOUSE_DISTANCE));
+    }
+}
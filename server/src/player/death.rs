@@ -1,5 +1,8 @@
+use crate::network::connections::spawn_bike;
+use crate::network::rooms::{InRoom, Rooms};
 use avian2d::prelude::*;
 use bevy::prelude::*;
+use lightyear::prelude::server::is_started;
 use lightyear::prelude::{NetworkTarget, ServerConnectionManager};
 use shared::network::message::{BikeDeathMessage, KillMessage, KilledByMessage};
 use shared::network::protocol::Channel1;
@@ -7,11 +10,14 @@ use shared::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
 use shared::player::death::{Dead, DeathTimer, DEATH_TIMER};
 use shared::player::scores::{Score, Stats};
 use shared::player::trail::Trail;
+use shared::player::PlayerMarker;
 
 const KILL_SCORE: u32 = 1;
 
 pub struct DeathPlugin;
 
+/// Event triggered when a player kills another player.
+/// Both `killer` and `killed` are player entities.
 #[derive(Event)]
 pub struct PlayerKillEvent {
     pub killer: Entity,
@@ -20,75 +26,84 @@ pub struct PlayerKillEvent {
 
 impl Plugin for DeathPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, respawn_player);
+        app.add_systems(Update, respawn_player.run_if(is_started));
         app.observe(kill_player);
     }
 }
 
-/// Tick death timers and respawn players
+/// Tick death timers and respawn players with a new bike
 fn respawn_player(
     mut commands: Commands,
     time: Res<Time>,
-    mut dead: Query<(Entity, &mut BikeMarker, &mut DeathTimer), With<Dead>>,
+    mut dead: Query<
+        (Entity, &ClientIdMarker, &InRoom, &mut DeathTimer),
+        (With<PlayerMarker>, With<Dead>),
+    >,
 ) {
-    for (entity, mut bike, mut timer) in dead.iter_mut() {
+    for (entity, client_id, room, mut timer) in dead.iter_mut() {
         timer.respawn_timer.tick(time.delta());
         if timer.respawn_timer.finished() {
-            bike.spawn_time = time.elapsed();
             commands
                 .entity(entity)
-                .insert((
-                    Position::default(),
-                    Rotation::default(),
-                    LinearVelocity::default(),
-                ))
                 .remove::<Dead>()
                 .remove::<DeathTimer>();
+            spawn_bike(
+                &mut commands,
+                entity,
+                client_id.0,
+                *room,
+                Vec2::ZERO,
+                time.elapsed(),
+            );
         }
     }
 }
 
-/// Observer that handles player_kill_events to put bikes in Dead state
+/// Observer that handles player_kill_events to put players in Dead state and despawn their bike
 fn kill_player(
     trigger: Trigger<PlayerKillEvent>,
     time: Res<Time>,
     mut server: ResMut<ServerConnectionManager>,
+    rooms: Res<Rooms>,
     mut commands: Commands,
-    mut bikes: Query<
+    mut players: Query<
         (
             &Children,
-            &BikeMarker,
             &ColorComponent,
             &ClientIdMarker,
-            &mut Position,
+            &InRoom,
             &mut Score,
             &mut Stats,
         ),
-        Without<Dead>,
+        (With<PlayerMarker>, Without<Dead>),
     >,
+    bikes: Query<(&BikeMarker, &Position)>,
     mut trails: Query<&mut Trail>,
 ) {
     let killed = trigger.event().killed;
     let killer = trigger.event().killer;
-    if let Some(mut com) = commands.get_entity(killed) {
-        com.insert(Dead);
-        com.insert(DeathTimer {
-            respawn_timer: Timer::new(DEATH_TIMER, TimerMode::Once),
-        });
-    }
-    if let Ok((children, bike, color, client_id, mut position, mut score, mut stats)) =
-        bikes.get_mut(killed)
-    {
+    if let Ok((children, color, client_id, room, mut score, mut stats)) = players.get_mut(killed) {
+        commands.entity(killed).insert((
+            Dead,
+            DeathTimer {
+                respawn_timer: Timer::new(DEATH_TIMER, TimerMode::Once),
+            },
+        ));
+        let mut death_position = Vec2::ZERO;
         children.into_iter().for_each(|e| {
             // clear the trail
             if let Ok(mut trail) = trails.get_mut(*e) {
                 trail.line.clear();
             }
+            // despawn the bike, a new one will be spawned on respawn
+            if let Ok((bike, position)) = bikes.get(*e) {
+                stats.time_lived_secs = (time.elapsed() - bike.spawn_time).as_secs() as u32;
+                death_position = position.0;
+                commands.entity(*e).despawn_recursive();
+            }
             // TODO: clear the zones when a player is killed?
         });
 
-        stats.time_lived_secs = (time.elapsed() - bike.spawn_time).as_secs() as u32;
-
         server
             .send_message::<Channel1, _>(
                 client_id.0,
@@ -102,19 +117,16 @@ fn kill_player(
             .send_message_to_target::<Channel1, _>(
                 &BikeDeathMessage {
                     color: color.0,
-                    position: position.0,
+                    position: death_position,
                 },
-                NetworkTarget::All,
+                NetworkTarget::Only(rooms.clients_in(room.0)),
             )
             .expect("could not send message");
 
-        // we send dead bikes to narnia
-        *position = Position::new(Vec2::new(100000.0, 1000000.0));
-
         *score = Score::default();
         *stats = Stats::default();
     }
-    if let Ok((_, _, _, client_id, _, mut score, mut stats)) = bikes.get_mut(killer) {
+    if let Ok((_, _, client_id, _, mut score, mut stats)) = players.get_mut(killer) {
         score.kill_score += KILL_SCORE;
         stats.kills += 1;
         stats.max_score = stats.max_score.max(score.total());
@@ -0,0 +1,106 @@
+//! Lag compensation: clients see the other bikes in the past (interpolation delay + latency), so
+//! the kill checks are done against the positions that the acting player saw.
+use avian2d::prelude::Position;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use lightyear::prelude::server::{is_started, ConnectionManager};
+use lightyear::prelude::{Tick, TickManager};
+use shared::network::config::{
+    FIXED_TIMESTEP_HZ, INTERPOLATION_SEND_INTERVAL_RATIO, SERVER_SEND_HZ,
+};
+use shared::physics::FixedSet;
+use shared::player::bike::{BikeMarker, ClientIdMarker};
+use shared::player::PlayerMarker;
+use std::collections::VecDeque;
+
+/// By default, never rewind more than ~300ms
+pub const DEFAULT_MAX_REWIND_TICKS: u16 = 20;
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct LagCompensationSettings {
+    /// Maximum number of ticks that we rewind the bikes by, so that players with a very high
+    /// latency cannot kill bikes that have long left the area
+    pub max_rewind_ticks: u16,
+}
+
+impl Default for LagCompensationSettings {
+    fn default() -> Self {
+        Self {
+            max_rewind_ticks: DEFAULT_MAX_REWIND_TICKS,
+        }
+    }
+}
+
+/// Round-trip time of the client of a player, measured by the server itself: a value reported by
+/// the client could be inflated to get a larger rewind
+#[derive(Component, Default, Debug)]
+pub struct PlayerLatency {
+    pub rtt: Duration,
+}
+
+/// Positions of a bike during the last ticks
+#[derive(Component, Default, Debug)]
+pub struct PositionHistory {
+    positions: VecDeque<(Tick, Vec2)>,
+}
+
+impl PositionHistory {
+    /// Position of the bike at `tick`, or the oldest recorded position if the bike didn't exist yet
+    pub fn get(&self, tick: Tick) -> Option<Vec2> {
+        self.positions
+            .iter()
+            .rev()
+            .find(|(recorded_tick, _)| *recorded_tick <= tick)
+            .or(self.positions.front())
+            .map(|(_, position)| *position)
+    }
+}
+
+pub struct LagCompensationPlugin;
+
+impl Plugin for LagCompensationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LagCompensationSettings>();
+        app.add_systems(
+            FixedUpdate,
+            record_position_history.after(FixedSet::Physics),
+        );
+        app.add_systems(Update, update_latency.run_if(is_started));
+    }
+}
+
+/// Record the position of every bike once the physics have run for this tick
+pub(crate) fn record_position_history(
+    settings: Res<LagCompensationSettings>,
+    tick_manager: Res<TickManager>,
+    mut bikes: Query<(&Position, &mut PositionHistory), With<BikeMarker>>,
+) {
+    let tick = tick_manager.tick();
+    for (position, mut history) in bikes.iter_mut() {
+        history.positions.push_back((tick, position.0));
+        while history.positions.len() > settings.max_rewind_ticks as usize + 1 {
+            history.positions.pop_front();
+        }
+    }
+}
+
+/// The bots have no connection, and keep a zero latency
+fn update_latency(
+    manager: Res<ConnectionManager>,
+    mut players: Query<(&ClientIdMarker, &mut PlayerLatency), With<PlayerMarker>>,
+) {
+    for (client_id, mut latency) in players.iter_mut() {
+        if let Ok(connection) = manager.connection(client_id.0) {
+            latency.rtt = connection.rtt();
+        }
+    }
+}
+
+/// Number of ticks between the tick simulated by the server and what the player saw of the
+/// other bikes: the interpolation delay, plus the round-trip time since the client predicts
+/// its own bike ahead of the server
+pub(crate) fn rewind_ticks(settings: &LagCompensationSettings, latency: &PlayerLatency) -> u16 {
+    let interpolation_delay = INTERPOLATION_SEND_INTERVAL_RATIO as f64 / SERVER_SEND_HZ;
+    let delay = interpolation_delay + latency.rtt.as_secs_f64();
+    ((delay * FIXED_TIMESTEP_HZ).round() as u16).min(settings.max_rewind_ticks)
+}
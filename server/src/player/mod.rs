@@ -1,12 +1,18 @@
 use bevy::prelude::*;
 
+pub mod anti_cheat;
+pub mod bot;
+pub mod lag_compensation;
 mod trail;
 pub mod death;
 
 pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(anti_cheat::AntiCheatPlugin);
         app.add_plugins(trail::TrailPlugin);
         app.add_plugins(death::DeathPlugin);
+        app.add_plugins(bot::BotPlugin);
+        app.add_plugins(lag_compensation::LagCompensationPlugin);
     }
 }
@@ -1,78 +1,139 @@
+use crate::network::metrics::ServerMetrics;
+use crate::network::rooms::InRoom;
 use crate::player::death::PlayerKillEvent;
+use crate::player::lag_compensation::{
+    record_position_history, rewind_ticks, LagCompensationSettings, PlayerLatency, PositionHistory,
+};
 use avian2d::position::Position;
 use bevy::prelude::*;
-use bevy::time::common_conditions::on_timer;
-use bevy::utils::HashMap;
+use bevy::utils::{HashMap, Instant};
+use lightyear::prelude::server::is_started;
+use lightyear::prelude::{Replicating, TickManager};
 use shared::physics::FixedSet;
+use shared::player::bike::BikeMarker;
 use shared::player::death::Dead;
 use shared::player::scores::{Score, Stats};
-use shared::player::trail::ADD_POINT_INTERVAL;
+use shared::player::trail::{TrailClock, TrailSettings};
 use shared::player::zone::Zones;
-use shared::player::{trail::Trail, zone::Zone};
+use shared::player::{trail::Trail, zone::Zone, PlayerMarker};
 
 pub struct TrailPlugin;
 
 impl Plugin for TrailPlugin {
     fn build(&self, app: &mut App) {
+        app.observe(set_spawn_tick);
         app.add_systems(
             FixedUpdate,
             // after we have advanced objects with physics, maybe add a point
             (mark_trail_system, update_score)
                 .chain()
-                .run_if(on_timer(ADD_POINT_INTERVAL))
-                .after(FixedSet::Physics),
+                .run_if(is_started)
+                .after(FixedSet::Physics)
+                .after(record_position_history),
         );
         // app.add_systems(FixedUpdate, mark_trail_system);
     }
 }
 
+/// The trail points of a bike are counted from the tick at which it spawned; the clients get that
+/// tick with the `BikeMarker`
+fn set_spawn_tick(
+    trigger: Trigger<OnAdd, BikeMarker>,
+    tick_manager: Res<TickManager>,
+    mut bikes: Query<&mut BikeMarker, With<Replicating>>,
+) {
+    if let Ok(mut bike) = bikes.get_mut(trigger.entity()) {
+        bike.spawn_tick = tick_manager.tick().0;
+    }
+}
+
 /// Add a new point to the trail and update the zones accordingly
 fn mark_trail_system(
     mut commands: Commands,
-    mut bikes: Query<(Entity, &Position, &Children, &mut Stats), Without<Dead>>,
-    mut trails: Query<(Entity, &Parent, &mut Trail), Without<Dead>>,
-    mut zones_query: Query<(&Parent, &mut Zones), Without<Dead>>,
+    tick_manager: Res<TickManager>,
+    settings: Res<TrailSettings>,
+    lag_compensation: Res<LagCompensationSettings>,
+    mut bikes: Query<(
+        &Parent,
+        &Position,
+        &PositionHistory,
+        &InRoom,
+        &BikeMarker,
+        &mut TrailClock,
+    )>,
+    latencies: Query<&PlayerLatency>,
+    mut players: Query<(&Children, &mut Stats), (With<PlayerMarker>, Without<Dead>)>,
+    mut trails: Query<(&Parent, &mut Trail)>,
+    mut zones_query: Query<(&Parent, &mut Zones, &InRoom)>,
+    mut metrics: Option<ResMut<ServerMetrics>>,
 ) {
-    let mut new_zones = HashMap::<Entity, Zone>::new();
-    for (trail_entity, parent, mut trail) in trails.iter_mut() {
-        if let Ok((_, position, children, mut stats)) = bikes.get_mut(parent.get()) {
+    let mut new_zones = HashMap::<Entity, (Zone, InRoom)>::new();
+    for (parent, mut trail) in trails.iter_mut() {
+        if let Ok((children, mut stats)) = players.get_mut(parent.get()) {
+            // the bike is the child of the player that has a Position
+            let Some(bike) = children.iter().find(|entity| bikes.contains(**entity)) else {
+                continue;
+            };
+            let Ok((_, position, _, _, marker, mut clock)) = bikes.get_mut(*bike) else {
+                continue;
+            };
+            if !clock.is_trail_tick(&settings, marker, tick_manager.tick()) {
+                continue;
+            }
             if let Some(shape) = trail.try_add_point(position.0) {
                 // update stats
                 stats.max_trail_length = stats.max_trail_length.max(trail.len() as u32);
 
                 trail.line.clear();
-                // we find the zone entity by querying the children of Bike that are not Trail
+                // we find the zone entity by querying the children of the Player
                 let zone_entity = children
-                    .into_iter()
-                    .find(|entity| **entity != trail_entity)
+                    .iter()
+                    .find(|entity| zones_query.contains(**entity))
                     .unwrap();
-                if let Ok((_, mut zones)) = zones_query.get_mut(*zone_entity) {
+                if let Ok((_, mut zones, room)) = zones_query.get_mut(*zone_entity) {
                     let new_zone = Zone::new(shape);
                     trace!("new zone: {:?}", new_zone);
+                    let start = Instant::now();
                     zones.add_zone(new_zone.clone());
+                    if let Some(metrics) = metrics.as_mut() {
+                        metrics.record_zone_op(start.elapsed());
+                    }
                     trace!("zones: {:?}", zones);
-                    new_zones.insert(parent.get(), new_zone);
+                    new_zones.insert(parent.get(), (new_zone, *room));
                 }
             }
         }
     }
 
-    // cut out all other zones
-    for (bike_entity, zone) in new_zones.iter() {
-        for (parent, mut zones) in zones_query.iter_mut() {
+    // cut out all other zones of the same room
+    for (player_entity, (zone, room)) in new_zones.iter() {
+        for (parent, mut zones, zones_room) in zones_query.iter_mut() {
             // we don't cut our own zone
-            if parent.get() != *bike_entity {
+            if parent.get() != *player_entity && zones_room == room {
+                let start = Instant::now();
                 zones.cut_out_zones(zone);
+                if let Some(metrics) = metrics.as_mut() {
+                    metrics.record_zone_op(start.elapsed());
+                }
             }
         }
 
-        // check if a player was killed
-        for (entity, position, _, _) in bikes.iter() {
+        // check if a player was killed, using the positions that the killer saw.
+        // Bots don't have any latency, so they use the current positions.
+        let rewind = latencies
+            .get(*player_entity)
+            .map_or(0, |latency| rewind_ticks(&lag_compensation, latency));
+        let tick = tick_manager.tick() - rewind as i16;
+        for (parent, position, history, bike_room, ..) in bikes.iter() {
+            if bike_room != room {
+                continue;
+            }
+            let position = history.get(tick).unwrap_or(position.0);
             // you cannot kill yourself
-            if *bike_entity != entity && zone.contains(position.0) {
+            if *player_entity != parent.get() && zone.contains(position) {
                 commands.trigger(PlayerKillEvent {
-                    killer: *bike_entity,
-                    killed: entity,
+                    killer: *player_entity,
+                    killed: parent.get(),
                 })
             }
         }
@@ -81,11 +142,11 @@ fn mark_trail_system(
 
 /// Update the player scores when the zones change
 fn update_score(
-    mut bikes: Query<(&mut Stats, &mut Score)>,
+    mut players: Query<(&mut Stats, &mut Score)>,
     mut zones_query: Query<(&Parent, &mut Zones), Changed<Zones>>,
 ) {
     for (parent, mut zones) in zones_query.iter_mut() {
-        if let Ok((mut stats, mut score)) = bikes.get_mut(parent.get()) {
+        if let Ok((mut stats, mut score)) = players.get_mut(parent.get()) {
             // we use the area / 1000 as score
             let zone_score = (zones.area() / 1000.0) as u32;
             score.zone_score = zone_score;
@@ -0,0 +1,79 @@
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use server::harness::TestHarness;
+use shared::player::death::DEATH_TIMER;
+
+/// Distance of the scripted mouse from the bike
+const MOUSE_DISTANCE: f32 = 100.0;
+
+#[test]
+fn trail_follows_the_bike() {
+    let mut harness = TestHarness::new(1);
+    harness.connect();
+    harness.spawn_player(0, "Alice");
+
+    harness.set_input(0, Some(Vec2::X * MOUSE_DISTANCE));
+    harness.advance(Duration::from_secs(2));
+
+    let position = harness.bike_position(0, 0).expect("the bike is replicated");
+    assert!(position.x > 100.0, "the bike moved right: {position:?}");
+    let trail = harness.trail(0, 0).expect("the trail is replicated");
+    assert!(trail.line.len() > 2, "the trail grew: {trail:?}");
+    assert!(!harness.is_dead(0, 0));
+}
+
+#[test]
+fn closing_a_loop_creates_a_zone() {
+    let mut harness = TestHarness::new(1);
+    harness.connect();
+    harness.spawn_player(0, "Alice");
+
+    // rotate the mouse around the bike so that it drives in a circle
+    let start = harness.elapsed();
+    let zone_created = harness.run_until(Duration::from_secs(10), |harness| {
+        let angle = (harness.elapsed() - start).as_secs_f32() * std::f32::consts::TAU / 3.0;
+        harness.set_input(0, Some(Vec2::from_angle(angle) * MOUSE_DISTANCE));
+        harness
+            .zones(0, 0)
+            .is_some_and(|zones| !zones.zones.is_empty())
+    });
+    assert!(zone_created, "the loop created a zone");
+
+    let scored = harness.run_until(Duration::from_secs(1), |harness| {
+        harness
+            .score(0, 0)
+            .is_some_and(|score| score.zone_score > 0)
+    });
+    assert!(scored, "the zone gives points");
+}
+
+#[test]
+fn killed_player_respawns() {
+    let mut harness = TestHarness::new(2);
+    harness.connect();
+    harness.spawn_player(0, "Alice");
+    harness.spawn_player(1, "Bob");
+    harness.set_input(0, Some(Vec2::X * MOUSE_DISTANCE));
+    harness.set_input(1, Some(Vec2::NEG_X * MOUSE_DISTANCE));
+    harness.advance(Duration::from_secs(1));
+
+    harness.kill(0, 1);
+    let dead = harness.run_until(Duration::from_secs(1), |harness| {
+        harness.is_dead(0, 1) && harness.is_dead(1, 1) && harness.bike_position(1, 1).is_none()
+    });
+    assert!(
+        dead,
+        "every client sees the death, and the bike is despawned"
+    );
+    let killer_scored = harness.run_until(Duration::from_secs(1), |harness| {
+        harness
+            .score(1, 0)
+            .is_some_and(|score| score.kill_score == 1)
+    });
+    assert!(killer_scored, "the killer gets a point");
+
+    let respawned = harness.run_until(DEATH_TIMER + Duration::from_secs(1), |harness| {
+        !harness.is_dead(1, 1) && harness.bike_position(1, 1).is_some()
+    });
+    assert!(respawned, "the player respawns with a new bike");
+}
@@ -7,6 +7,8 @@ pub mod player;
 
 pub mod physics;
 
+pub mod replay;
+
 use bevy::log::{Level, LogPlugin};
 use bevy::state::app::StatesPlugin;
 use bevy::{
@@ -82,6 +84,7 @@ impl Plugin for SharedPlugin {
         // Add shared game logic plugins
         app.add_plugins(map::MapPlugin);
         app.add_plugins(physics::PhysicsPlugin);
+        app.add_plugins(player::PlayerPlugin);
         app.add_plugins(player::bike::BikePlugin);
         app.add_plugins(player::death::DeathPlugin);
         app.add_plugins(player::trail::TrailPlugin);
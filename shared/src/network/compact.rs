@@ -0,0 +1,410 @@
+//! Compact encoding of the bike state and of the trails, enabled with the `compact_protocol` feature.
+//!
+//! - positions are 16-bit fixed-point values relative to the map bounds
+//! - angles are stored in 16 bits
+//! - velocities are absolute fixed-point values (1/16 unit steps), written as zigzag varints. They
+//!   are not delta-encoded: the custom serde functions don't know the last value acked by each
+//!   client, and lightyear's delta compression needs a `Diffable` component, which avian's
+//!   `LinearVelocity` is not. A bike at full speed still takes 2-3 bytes per axis
+//! - trail points are quantized like positions, and delta-encoded from the previous point
+//!
+//! The quantized values never exactly match the predicted ones, so the predicted components only
+//! roll back when the difference is larger than the precision of the encoding.
+use crate::map::MAP_SIZE;
+use crate::player::trail::{Trail, TrailDiff};
+use avian2d::prelude::{LinearVelocity, Position, Rotation};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+use lightyear::protocol::serialize::SerializeFns;
+use lightyear::serialize::reader::Reader;
+use lightyear::serialize::writer::Writer;
+use lightyear::serialize::SerializationError;
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::f32::consts::TAU;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Positions are encoded in `[-POSITION_RANGE, POSITION_RANGE]`, which leaves some margin around the map
+const POSITION_RANGE: f32 = 2.0 * MAP_SIZE;
+/// Size of one step of the position encoding (~0.18 units)
+pub const POSITION_PRECISION: f32 = 2.0 * POSITION_RANGE / u16::MAX as f32;
+/// Size of one step of the angle encoding
+pub const ANGLE_PRECISION: f32 = TAU / (u16::MAX as f32 + 1.0);
+/// Size of one step of the velocity encoding
+pub const VELOCITY_PRECISION: f32 = 1.0 / 16.0;
+
+/// Size of the values when they are serialized without the compact encoding
+const RAW_VEC2_SIZE: usize = 2 * std::mem::size_of::<f32>();
+/// `TrailDiff` without the compact encoding: the `Vec` length, the points, and the `new_line` flag
+const RAW_TRAIL_DIFF_SIZE: usize = std::mem::size_of::<u64>() + std::mem::size_of::<bool>();
+/// Every point of an encoded `TrailDiff` uses at least two bytes (two varints)
+const MIN_TRAIL_POINT_SIZE: usize = 2;
+/// Largest allocation made from a length announced by the sender, before reading the data
+const MAX_PREALLOCATED_BYTES: usize = 1024;
+
+/// Bytes of the compacted components received since the diagnostics were last updated, and what
+/// they would have used without the compact encoding
+static RAW_BYTES: AtomicUsize = AtomicUsize::new(0);
+static COMPACT_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+fn record_bytes(raw: usize, compact: usize) {
+    RAW_BYTES.fetch_add(raw, Ordering::Relaxed);
+    COMPACT_BYTES.fetch_add(compact, Ordering::Relaxed);
+}
+
+/// Bandwidth used by the bike state and the trails, with and without the compact encoding
+pub struct CompactDiagnosticsPlugin;
+
+impl CompactDiagnosticsPlugin {
+    /// Bytes per second that the received bike state would use without the compact encoding
+    pub const RAW_BYTES: DiagnosticPath = DiagnosticPath::const_new("compact/raw_bytes");
+    /// Bytes per second actually used by the received bike state
+    pub const COMPACT_BYTES: DiagnosticPath = DiagnosticPath::const_new("compact/compact_bytes");
+}
+
+impl Plugin for CompactDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::RAW_BYTES).with_suffix("B/s"));
+        app.register_diagnostic(Diagnostic::new(Self::COMPACT_BYTES).with_suffix("B/s"));
+        app.add_systems(Update, update_diagnostics);
+    }
+}
+
+fn update_diagnostics(time: Res<Time<Real>>, mut diagnostics: Diagnostics) {
+    let delta = time.delta_seconds_f64();
+    if delta == 0.0 {
+        return;
+    }
+    let raw = RAW_BYTES.swap(0, Ordering::Relaxed);
+    let compact = COMPACT_BYTES.swap(0, Ordering::Relaxed);
+    diagnostics.add_measurement(&CompactDiagnosticsPlugin::RAW_BYTES, || raw as f64 / delta);
+    diagnostics.add_measurement(&CompactDiagnosticsPlugin::COMPACT_BYTES, || {
+        compact as f64 / delta
+    });
+}
+
+fn quantize_coordinate(value: f32) -> u16 {
+    let normalized =
+        (value.clamp(-POSITION_RANGE, POSITION_RANGE) + POSITION_RANGE) / (2.0 * POSITION_RANGE);
+    (normalized * u16::MAX as f32).round() as u16
+}
+
+fn dequantize_coordinate(value: u16) -> f32 {
+    value as f32 / u16::MAX as f32 * 2.0 * POSITION_RANGE - POSITION_RANGE
+}
+
+fn quantize_position(position: Vec2) -> [u16; 2] {
+    [
+        quantize_coordinate(position.x),
+        quantize_coordinate(position.y),
+    ]
+}
+
+fn dequantize_position([x, y]: [u16; 2]) -> Vec2 {
+    Vec2::new(dequantize_coordinate(x), dequantize_coordinate(y))
+}
+
+fn write_u16(writer: &mut impl Write, value: u16) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Zigzag + LEB128 encoding: small values (positive or negative) use a single byte.
+/// Returns the number of bytes written.
+fn write_varint(writer: &mut impl Write, value: i32) -> io::Result<usize> {
+    let mut zigzag = ((value << 1) ^ (value >> 31)) as u32;
+    let mut written = 0;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        written += 1;
+        if zigzag == 0 {
+            writer.write_all(&[byte])?;
+            return Ok(written);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<(i32, usize)> {
+    let mut zigzag = 0u32;
+    for i in 0..5 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        zigzag |= ((byte[0] & 0x7f) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            let value = ((zigzag >> 1) as i32) ^ -((zigzag & 1) as i32);
+            return Ok((value, i + 1));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
+pub fn position_serde() -> SerializeFns<Position> {
+    SerializeFns {
+        serialize: serialize_position,
+        deserialize: deserialize_position,
+    }
+}
+
+fn serialize_position(position: &Position, writer: &mut Writer) -> Result<(), SerializationError> {
+    let [x, y] = quantize_position(position.0);
+    write_u16(writer, x)?;
+    write_u16(writer, y)?;
+    Ok(())
+}
+
+fn deserialize_position(reader: &mut Reader) -> Result<Position, SerializationError> {
+    let x = read_u16(reader)?;
+    let y = read_u16(reader)?;
+    record_bytes(RAW_VEC2_SIZE, 2 * std::mem::size_of::<u16>());
+    Ok(Position(dequantize_position([x, y])))
+}
+
+pub fn position_should_rollback(this: &Position, that: &Position) -> bool {
+    (this.0 - that.0).length() >= POSITION_PRECISION
+}
+
+pub fn rotation_serde() -> SerializeFns<Rotation> {
+    SerializeFns {
+        serialize: serialize_rotation,
+        deserialize: deserialize_rotation,
+    }
+}
+
+fn serialize_rotation(rotation: &Rotation, writer: &mut Writer) -> Result<(), SerializationError> {
+    // the angle is in [-PI, PI]; wrapping it into a u16 covers the full turn
+    let angle = (rotation.as_radians() / ANGLE_PRECISION).round() as i32 as u16;
+    write_u16(writer, angle)?;
+    Ok(())
+}
+
+fn deserialize_rotation(reader: &mut Reader) -> Result<Rotation, SerializationError> {
+    let angle = read_u16(reader)? as i16;
+    record_bytes(RAW_VEC2_SIZE, std::mem::size_of::<u16>());
+    Ok(Rotation::radians(angle as f32 * ANGLE_PRECISION))
+}
+
+pub fn rotation_should_rollback(this: &Rotation, that: &Rotation) -> bool {
+    this.angle_between(*that).abs() >= ANGLE_PRECISION
+}
+
+pub fn linear_velocity_serde() -> SerializeFns<LinearVelocity> {
+    SerializeFns {
+        serialize: serialize_linear_velocity,
+        deserialize: deserialize_linear_velocity,
+    }
+}
+
+fn serialize_linear_velocity(
+    velocity: &LinearVelocity,
+    writer: &mut Writer,
+) -> Result<(), SerializationError> {
+    write_varint(writer, (velocity.0.x / VELOCITY_PRECISION).round() as i32)?;
+    write_varint(writer, (velocity.0.y / VELOCITY_PRECISION).round() as i32)?;
+    Ok(())
+}
+
+fn deserialize_linear_velocity(reader: &mut Reader) -> Result<LinearVelocity, SerializationError> {
+    let (x, x_size) = read_varint(reader)?;
+    let (y, y_size) = read_varint(reader)?;
+    record_bytes(RAW_VEC2_SIZE, x_size + y_size);
+    Ok(LinearVelocity(
+        Vec2::new(x as f32, y as f32) * VELOCITY_PRECISION,
+    ))
+}
+
+pub fn linear_velocity_should_rollback(this: &LinearVelocity, that: &LinearVelocity) -> bool {
+    (this.0 - that.0).length() >= VELOCITY_PRECISION
+}
+
+pub fn trail_should_rollback(this: &Trail, that: &Trail) -> bool {
+    this.line.len() != that.line.len()
+        || this
+            .line
+            .iter()
+            .zip(that.line.iter())
+            .any(|(a, b)| (*a - *b).length() >= POSITION_PRECISION)
+}
+
+/// The first point is quantized like a position, the next ones are varint deltas (in quantization
+/// steps) from the previous point, which are small since points are sampled every few ticks
+fn encode_trail_diff(diff: &TrailDiff) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    bytes.write_all(&[diff.new_line as u8])?;
+    write_varint(&mut bytes, diff.line_diff.len() as i32)?;
+    let mut previous: Option<[u16; 2]> = None;
+    for point in diff.line_diff.iter() {
+        let [x, y] = quantize_position(*point);
+        match previous {
+            None => {
+                write_u16(&mut bytes, x)?;
+                write_u16(&mut bytes, y)?;
+            }
+            Some([previous_x, previous_y]) => {
+                write_varint(&mut bytes, x as i32 - previous_x as i32)?;
+                write_varint(&mut bytes, y as i32 - previous_y as i32)?;
+            }
+        }
+        previous = Some([x, y]);
+    }
+    Ok(bytes)
+}
+
+fn decode_trail_diff(mut bytes: &[u8]) -> io::Result<TrailDiff> {
+    let compact_size = bytes.len();
+    let mut new_line = [0];
+    bytes.read_exact(&mut new_line)?;
+    let (len, _) = read_varint(&mut bytes)?;
+    // the length comes from the network: it must fit in the bytes that are left
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= bytes.len() / MIN_TRAIL_POINT_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid trail length"))?;
+    let mut line_diff = Vec::with_capacity(len);
+    let mut previous: Option<[u16; 2]> = None;
+    for _ in 0..len {
+        let point = match previous {
+            None => [read_u16(&mut bytes)?, read_u16(&mut bytes)?],
+            Some([previous_x, previous_y]) => {
+                let (dx, _) = read_varint(&mut bytes)?;
+                let (dy, _) = read_varint(&mut bytes)?;
+                [
+                    (previous_x as i32 + dx) as u16,
+                    (previous_y as i32 + dy) as u16,
+                ]
+            }
+        };
+        line_diff.push(dequantize_position(point));
+        previous = Some(point);
+    }
+    record_bytes(
+        RAW_TRAIL_DIFF_SIZE + RAW_VEC2_SIZE * line_diff.len(),
+        compact_size,
+    );
+    Ok(TrailDiff {
+        line_diff,
+        new_line: new_line[0] != 0,
+    })
+}
+
+/// `TrailDiff`s are sent through the delta-compression messages, which use serde
+impl Serialize for TrailDiff {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = encode_trail_diff(self).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for TrailDiff {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TrailDiffVisitor;
+
+        impl<'de> Visitor<'de> for TrailDiffVisitor {
+            type Value = TrailDiff;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a compact trail diff")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<TrailDiff, E> {
+                decode_trail_diff(bytes).map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<TrailDiff, A::Error> {
+                let capacity = seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATED_BYTES);
+                let mut bytes = Vec::with_capacity(capacity);
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+                self.visit_bytes(&bytes)
+            }
+        }
+
+        deserializer.deserialize_bytes(TrailDiffVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_round_trip() {
+        for value in [0, 1, -1, 63, -64, 64, -65, 300, -300, i32::MAX, i32::MIN] {
+            let mut bytes = Vec::new();
+            let written = write_varint(&mut bytes, value).unwrap();
+            assert_eq!(written, bytes.len());
+            assert_eq!(
+                read_varint(&mut bytes.as_slice()).unwrap(),
+                (value, written)
+            );
+        }
+        // small values use a single byte
+        assert_eq!(write_varint(&mut Vec::new(), -64).unwrap(), 1);
+    }
+
+    #[test]
+    fn varint_too_long() {
+        let bytes = [0xff; 6];
+        assert!(read_varint(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn position_quantization() {
+        for position in [
+            Vec2::ZERO,
+            Vec2::new(MAP_SIZE, -MAP_SIZE),
+            Vec2::new(123.456, -789.012),
+        ] {
+            let decoded = dequantize_position(quantize_position(position));
+            assert!((decoded - position).abs().max_element() < POSITION_PRECISION);
+        }
+        // out of range positions are clamped
+        let far = dequantize_position(quantize_position(Vec2::splat(10.0 * MAP_SIZE)));
+        assert!((far - Vec2::splat(POSITION_RANGE)).length() < POSITION_PRECISION);
+    }
+
+    #[test]
+    fn trail_diff_round_trip() {
+        let diff = TrailDiff {
+            line_diff: vec![
+                Vec2::new(10.0, 20.0),
+                Vec2::new(15.0, 18.0),
+                Vec2::new(-300.0, 400.0),
+            ],
+            new_line: true,
+        };
+        let decoded = decode_trail_diff(&encode_trail_diff(&diff).unwrap()).unwrap();
+        assert_eq!(decoded.new_line, diff.new_line);
+        assert_eq!(decoded.line_diff.len(), diff.line_diff.len());
+        for (decoded, point) in decoded.line_diff.iter().zip(diff.line_diff.iter()) {
+            assert!((*decoded - *point).abs().max_element() < POSITION_PRECISION);
+        }
+
+        let empty = TrailDiff::default();
+        assert_eq!(
+            decode_trail_diff(&encode_trail_diff(&empty).unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn trail_diff_rejects_invalid_length() {
+        for len in [-1, i32::MAX, 3] {
+            let mut bytes = vec![0];
+            write_varint(&mut bytes, len).unwrap();
+            bytes.extend_from_slice(&[0; 4]);
+            assert!(decode_trail_diff(&bytes).is_err());
+        }
+    }
+}
@@ -4,18 +4,40 @@ use clap::ValueEnum;
 use lightyear::prelude::*;
 
 pub const PROTOCOL_ID: u64 = 0;
+/// Default key of the connect tokens. It is public, so it only fits the servers that the clients
+/// connect to directly; the servers behind a matchmaker share a private key with it, see `load_key`
 pub const KEY: Key = [0; 32];
 
 pub const FIXED_TIMESTEP_HZ: f64 = 64.0;
 pub const SERVER_SEND_HZ: f64 = 32.0;
+/// Clients render remote entities this many server send intervals in the past
+pub const INTERPOLATION_SEND_INTERVAL_RATIO: f32 = 2.0;
 
-pub fn shared_config() -> SharedConfig {
+/// Read a private key from the file at `path`, written as 64 hexadecimal digits.
+/// Its signature fits clap's `value_parser`
+pub fn load_key(path: &str) -> Result<Key, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| format!("Could not read the key file {path}: {error}"))?;
+    let hex = text.trim();
+    let invalid = || format!("The key file {path} must contain 64 hexadecimal digits");
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+/// `mode` is `Mode::HostServer` when the server runs inside a client app, `Mode::Separate` otherwise
+pub fn shared_config(mode: Mode) -> SharedConfig {
     SharedConfig {
         server_replication_send_interval: Duration::from_secs_f64(1.0 / SERVER_SEND_HZ),
         tick: TickConfig {
             tick_duration: Duration::from_secs_f64(1.0 / FIXED_TIMESTEP_HZ),
         },
-        mode: Mode::Separate,
+        mode,
     }
 }
 
@@ -0,0 +1,91 @@
+//! Protocol between the matchmaker, the game servers and the clients.
+//!
+//! Every request is a single line of JSON sent over a new TCP connection, and the matchmaker
+//! answers with a single line of JSON.
+use crate::network::config::Transports;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+pub const MATCHMAKER_PORT: u16 = 5100;
+/// The game servers report their player count this often
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Servers that have not reported for this long are considered gone
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(15);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchmakerRequest {
+    /// Sent periodically by a game server; the first report registers the server
+    Report(ServerReport),
+    /// Sent by a client to get a server to connect to
+    FindServer {
+        /// `ProtocolVersion` of the client
+        protocol_version: u64,
+        transport: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ServerReport {
+    /// Address that the clients connect to
+    pub addr: SocketAddr,
+    /// `ProtocolVersion` of the server: only the clients with the same version can play on it
+    pub protocol_version: u64,
+    /// Name of the transport used by the server (`udp`, `webtransport`, `websocket`)
+    pub transport: String,
+    pub players: u32,
+    pub max_players: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchmakerResponse {
+    Registered,
+    /// Server to connect to, with a netcode connect token for it
+    Server {
+        addr: SocketAddr,
+        client_id: u64,
+        connect_token: Vec<u8>,
+    },
+    Error {
+        reason: String,
+    },
+}
+
+/// Name of the transport in the matchmaker requests, the same as on the command line
+pub fn transport_name(transport: Transports) -> String {
+    transport
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Send a request to the matchmaker and wait for its response.
+/// This blocks, so it should be run outside of the game loop.
+pub fn send_request(
+    matchmaker: SocketAddr,
+    request: &MatchmakerRequest,
+) -> io::Result<MatchmakerResponse> {
+    let mut stream = TcpStream::connect_timeout(&matchmaker, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    write_message(&mut stream, request)?;
+    read_message(&mut BufReader::new(stream))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+pub fn read_message<T: for<'de> Deserialize<'de>>(reader: &mut impl BufRead) -> io::Result<T> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(serde_json::from_str(&line)?)
+}
@@ -2,12 +2,101 @@ use crate::player::scores::Stats;
 use bevy::color::Color;
 use bevy::ecs::entity::MapEntities;
 use bevy::prelude::{Component, Entity, EntityMapper, Reflect, Vec2};
-use lightyear::prelude::{Deserialize, Serialize};
+use lightyear::prelude::{ClientId, Deserialize, Serialize};
 
-/// Message sent from the client to spawn the player with a given name
-#[derive(Reflect, Serialize, Deserialize, PartialEq, Debug, Clone)]
+/// First message sent by a client once connected, with the `ProtocolVersion` it was built with
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ProtocolVersionMessage {
+    pub version: u64,
+}
+
+/// Sent by the server before disconnecting a client that was built with a different protocol
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ProtocolMismatchMessage {
+    pub server_version: u64,
+}
+
+/// Message sent from the client to spawn the player with a given name, in the chosen room
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct SpawnPlayerMessage {
     pub name: String,
+    pub room: RoomChoice,
+    /// Random token that the client keeps across launches, so that the server recognizes the
+    /// player; the client id changes at every launch
+    pub identity: u128,
+}
+
+/// Which room a player wants to play in
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub enum RoomChoice {
+    /// The public room with the fewest players
+    #[default]
+    Any,
+    /// Join a room by its code; private rooms need the password
+    Join {
+        code: String,
+        password: Option<String>,
+    },
+    /// Create a new private room, that other players can join with its code and password
+    CreatePrivate { password: String },
+}
+
+/// Sent by the client to get the list of public and private rooms
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoomListRequest;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoomListMessage {
+    pub rooms: Vec<RoomInfo>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoomInfo {
+    pub code: String,
+    pub name: String,
+    pub players: u32,
+    pub max_players: u32,
+    pub private: bool,
+}
+
+/// Sent to the client when it entered a room
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoomJoinedMessage {
+    pub code: String,
+    pub name: String,
+}
+
+/// Sent to the client when it could not enter the room it asked for
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RoomRejectedMessage {
+    pub reason: String,
+}
+
+/// Sent to a client that joins a game in progress, before the zones are replicated:
+/// simplified outlines of the zones of every player, to draw while the full zones are loading
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ZoneOutlinesMessage {
+    pub outlines: Vec<ZoneOutline>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ZoneOutline {
+    pub client_id: ClientId,
+    pub exterior: Vec<Vec2>,
+}
+
+/// Settings of the server that the client needs to predict the game, sent once the protocol
+/// versions were checked
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TrailSettingsMessage {
+    pub ticks_per_point: u16,
+}
+
+/// Progress of the initial sync of the trails and zones, sent to a client that joins a game in progress
+#[derive(Reflect, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SyncProgressMessage {
+    pub synced: u32,
+    pub total: u32,
 }
 
 #[derive(Reflect, Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -22,6 +111,22 @@ pub struct KilledByMessage {
     pub stats: Stats,
 }
 
+/// System message from the server, displayed apart from the chat
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AnnouncementMessage {
+    pub kind: AnnouncementKind,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AnnouncementKind {
+    /// Message of the day, sent when joining the server
+    Motd,
+    Info,
+    /// For example that the server restarts soon
+    Warning,
+}
+
 #[derive(Reflect, Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ChatMessage {
     pub color: Color,
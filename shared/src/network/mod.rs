@@ -1,4 +1,8 @@
+#[cfg(feature = "compact_protocol")]
+pub mod compact;
 pub mod config;
 pub mod inputs;
+#[cfg(not(target_family = "wasm"))]
+pub mod matchmaker;
 pub mod message;
 pub mod protocol;
@@ -1,17 +1,23 @@
 //! Defines the shared network protocol between the client and server
 
+#[cfg(feature = "compact_protocol")]
+use crate::network::compact;
 use crate::network::inputs::PlayerMovement;
 use crate::network::message::{
-    BikeDeathMessage, ChatMessage, KillMessage, KilledByMessage, SpawnPlayerMessage,
+    AnnouncementMessage, BikeDeathMessage, ChatMessage, KillMessage, KilledByMessage,
+    ProtocolMismatchMessage, ProtocolVersionMessage, RoomJoinedMessage, RoomListMessage,
+    RoomListRequest, RoomRejectedMessage, SpawnPlayerMessage, SyncProgressMessage,
+    TrailSettingsMessage, ZoneOutlinesMessage,
 };
 use crate::player::bike::{BikeMarker, ClientIdMarker, ColorComponent};
 use crate::player::death::Dead;
 use crate::player::scores::{Score, Stats};
 use crate::player::trail::Trail;
 use crate::player::zone::Zones;
+use crate::player::PlayerMarker;
 use avian2d::prelude::*;
 use bevy::app::{App, Plugin};
-use bevy::prelude::{default, Name};
+use bevy::prelude::{default, Name, Resource};
 use lightyear::prelude::client::*;
 use lightyear::prelude::*;
 use lightyear::utils::avian2d::*;
@@ -21,76 +27,209 @@ pub struct ProtocolPlugin;
 #[derive(Channel)]
 pub struct Channel1;
 
+/// Version of the protocol: a hash of the names and directions of the registered messages and
+/// components, in registration order. Clients send it to the server right after connecting, and
+/// the server rejects the clients that were built with a different protocol
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion(pub u64);
+
+/// FNV-1a hash of the registrations
+struct VersionHasher(u64);
+
+impl VersionHasher {
+    const PRIME: u64 = 0x100000001b3;
+
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Hash the registration of `T`; returns `direction`, to register `T` with it
+    fn add<T>(&mut self, direction: ChannelDirection) -> ChannelDirection {
+        self.write(std::any::type_name::<T>().as_bytes());
+        self.write(&[direction as u8]);
+        direction
+    }
+}
+
 impl Plugin for ProtocolPlugin {
     fn build(&self, app: &mut App) {
+        let mut version = VersionHasher::new();
+
         // Channels
+        version.add::<Channel1>(ChannelDirection::Bidirectional);
         app.add_channel::<Channel1>(ChannelSettings {
             mode: ChannelMode::OrderedReliable(ReliableSettings::default()),
             ..default()
         });
 
         // Inputs
+        version.add::<PlayerMovement>(ChannelDirection::ClientToServer);
         app.add_plugins(LeafwingInputPlugin::<PlayerMovement>::default());
 
         // Messages
-        app.register_message::<KilledByMessage>(ChannelDirection::ServerToClient)
-            .add_map_entities();
-        app.register_message::<KillMessage>(ChannelDirection::ServerToClient)
-            .add_map_entities();
-        app.register_message::<SpawnPlayerMessage>(ChannelDirection::ClientToServer);
-        app.register_message::<BikeDeathMessage>(ChannelDirection::ServerToClient);
-        app.register_message::<ChatMessage>(ChannelDirection::Bidirectional);
+        // the handshake messages are registered first, so that their ids stay the same across
+        // protocol versions and outdated clients can still be told about the mismatch
+        app.register_message::<ProtocolVersionMessage>(
+            version.add::<ProtocolVersionMessage>(ChannelDirection::ClientToServer),
+        );
+        app.register_message::<ProtocolMismatchMessage>(
+            version.add::<ProtocolMismatchMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<KilledByMessage>(
+            version.add::<KilledByMessage>(ChannelDirection::ServerToClient),
+        )
+        .add_map_entities();
+        app.register_message::<KillMessage>(
+            version.add::<KillMessage>(ChannelDirection::ServerToClient),
+        )
+        .add_map_entities();
+        app.register_message::<SpawnPlayerMessage>(
+            version.add::<SpawnPlayerMessage>(ChannelDirection::ClientToServer),
+        );
+        app.register_message::<RoomListRequest>(
+            version.add::<RoomListRequest>(ChannelDirection::ClientToServer),
+        );
+        app.register_message::<RoomListMessage>(
+            version.add::<RoomListMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<RoomJoinedMessage>(
+            version.add::<RoomJoinedMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<RoomRejectedMessage>(
+            version.add::<RoomRejectedMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<BikeDeathMessage>(
+            version.add::<BikeDeathMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<ZoneOutlinesMessage>(
+            version.add::<ZoneOutlinesMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<SyncProgressMessage>(
+            version.add::<SyncProgressMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<ChatMessage>(
+            version.add::<ChatMessage>(ChannelDirection::Bidirectional),
+        );
+        app.register_message::<AnnouncementMessage>(
+            version.add::<AnnouncementMessage>(ChannelDirection::ServerToClient),
+        );
+        app.register_message::<TrailSettingsMessage>(
+            version.add::<TrailSettingsMessage>(ChannelDirection::ServerToClient),
+        );
 
         // Components
-        app.register_component::<Score>(ChannelDirection::ServerToClient);
-        app.register_component::<Stats>(ChannelDirection::ServerToClient);
-
-        app.register_component::<ClientIdMarker>(ChannelDirection::ServerToClient)
-            .add_prediction(ComponentSyncMode::Once)
-            .add_interpolation(ComponentSyncMode::Once);
-
-        app.register_component::<ColorComponent>(ChannelDirection::ServerToClient)
-            .add_prediction(ComponentSyncMode::Once)
-            .add_interpolation(ComponentSyncMode::Once);
-
-        app.register_component::<BikeMarker>(ChannelDirection::ServerToClient)
-            // .add_map_entities()
-            .add_prediction(ComponentSyncMode::Once)
-            .add_interpolation(ComponentSyncMode::Once);
-
-        app.register_component::<Dead>(ChannelDirection::ServerToClient)
+        app.register_component::<Score>(version.add::<Score>(ChannelDirection::ServerToClient));
+        app.register_component::<Stats>(version.add::<Stats>(ChannelDirection::ServerToClient));
+
+        app.register_component::<ClientIdMarker>(
+            version.add::<ClientIdMarker>(ChannelDirection::ServerToClient),
+        )
+        .add_prediction(ComponentSyncMode::Once)
+        .add_interpolation(ComponentSyncMode::Once);
+
+        app.register_component::<ColorComponent>(
+            version.add::<ColorComponent>(ChannelDirection::ServerToClient),
+        )
+        .add_prediction(ComponentSyncMode::Once)
+        .add_interpolation(ComponentSyncMode::Once);
+
+        app.register_component::<PlayerMarker>(
+            version.add::<PlayerMarker>(ChannelDirection::ServerToClient),
+        )
+        .add_prediction(ComponentSyncMode::Once)
+        .add_interpolation(ComponentSyncMode::Once);
+
+        app.register_component::<BikeMarker>(
+            version.add::<BikeMarker>(ChannelDirection::ServerToClient),
+        )
+        // .add_map_entities()
+        .add_prediction(ComponentSyncMode::Once)
+        .add_interpolation(ComponentSyncMode::Once);
+
+        app.register_component::<Dead>(version.add::<Dead>(ChannelDirection::ServerToClient))
             // .add_map_entities()
             .add_prediction(ComponentSyncMode::Simple)
             .add_interpolation(ComponentSyncMode::Simple);
 
-        app.register_component::<Position>(ChannelDirection::Bidirectional)
+        // the bike state is sent every server tick, so it can use a more compact encoding
+        #[cfg(not(feature = "compact_protocol"))]
+        let position = app.register_component::<Position>(
+            version.add::<Position>(ChannelDirection::ServerToClient),
+        );
+        #[cfg(feature = "compact_protocol")]
+        let position = app.register_component_custom_serde::<Position>(
+            version.add::<Position>(ChannelDirection::ServerToClient),
+            compact::position_serde(),
+        );
+        let position = position
             .add_prediction(ComponentSyncMode::Full)
             .add_interpolation(ComponentSyncMode::Full)
             .add_interpolation_fn(position::lerp);
         // TODO: remove correction for now to make rollbacks more obvious
         // .add_correction_fn(position::lerp);
-
-        app.register_component::<Rotation>(ChannelDirection::Bidirectional)
+        #[cfg(feature = "compact_protocol")]
+        position.add_should_rollback(compact::position_should_rollback);
+
+        #[cfg(not(feature = "compact_protocol"))]
+        let rotation = app.register_component::<Rotation>(
+            version.add::<Rotation>(ChannelDirection::ServerToClient),
+        );
+        #[cfg(feature = "compact_protocol")]
+        let rotation = app.register_component_custom_serde::<Rotation>(
+            version.add::<Rotation>(ChannelDirection::ServerToClient),
+            compact::rotation_serde(),
+        );
+        let rotation = rotation
             .add_prediction(ComponentSyncMode::Full)
             .add_interpolation(ComponentSyncMode::Full)
             .add_interpolation_fn(rotation::lerp);
         // TODO: remove correction for now to make rollbacks more obvious
         // .add_correction_fn(rotation::lerp);
+        #[cfg(feature = "compact_protocol")]
+        rotation.add_should_rollback(compact::rotation_should_rollback);
 
         // NOTE: interpolation/correction is only needed for components that are visually displayed!
         // we still need prediction to be able to correctly predict the physics on the client
-        app.register_component::<LinearVelocity>(ChannelDirection::Bidirectional)
+        #[cfg(not(feature = "compact_protocol"))]
+        let linear_velocity = app.register_component::<LinearVelocity>(
+            version.add::<LinearVelocity>(ChannelDirection::ServerToClient),
+        );
+        #[cfg(feature = "compact_protocol")]
+        let linear_velocity = app.register_component_custom_serde::<LinearVelocity>(
+            version.add::<LinearVelocity>(ChannelDirection::ServerToClient),
+            compact::linear_velocity_serde(),
+        );
+        let linear_velocity = linear_velocity
             .add_prediction(ComponentSyncMode::Full)
             // copy Speed for interpolation because we need for spatial audio
             .add_interpolation(ComponentSyncMode::Simple);
+        #[cfg(feature = "compact_protocol")]
+        linear_velocity.add_should_rollback(compact::linear_velocity_should_rollback);
 
-        app.register_component::<Name>(ChannelDirection::ServerToClient)
+        app.register_component::<Name>(version.add::<Name>(ChannelDirection::ServerToClient))
             .add_prediction(ComponentSyncMode::Once)
             .add_interpolation(ComponentSyncMode::Once);
 
-        app.register_component::<Trail>(ChannelDirection::ServerToClient)
+        // the owner predicts its own trail and zones; any mismatch with the server triggers a rollback
+        let trail = app
+            .register_component::<Trail>(version.add::<Trail>(ChannelDirection::ServerToClient))
+            .add_prediction(ComponentSyncMode::Full)
             .add_delta_compression();
-        app.register_component::<Zones>(ChannelDirection::ServerToClient)
+        // the compact trail points are quantized
+        #[cfg(feature = "compact_protocol")]
+        trail.add_should_rollback(compact::trail_should_rollback);
+        app.register_component::<Zones>(version.add::<Zones>(ChannelDirection::ServerToClient))
+            .add_prediction(ComponentSyncMode::Full)
             .add_delta_compression();
+
+        // the compact protocol encodes the bike state differently
+        version.write(&[cfg!(feature = "compact_protocol") as u8]);
+        app.insert_resource(ProtocolVersion(version.0));
     }
 }
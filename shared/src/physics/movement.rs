@@ -5,9 +5,9 @@ use crate::player::bike::{
     BikeMarker, ClientIdMarker, ACCEL, BASE_SPEED, DRAG, FAST_DRAG, FAST_SPEED,
     FAST_SPEED_MAX_SPEED_DISTANCE, MAX_ROTATION_SPEED, OUR_ZONE_SPEED_MULTIPLIER,
 };
-use crate::player::death::Dead;
 use crate::player::trail::Trail;
 use crate::player::zone::Zones;
+use crate::player::PlayerMarker;
 use avian2d::prelude::*;
 use bevy::prelude::*;
 use leafwing_input_manager::prelude::ActionState;
@@ -55,10 +55,17 @@ impl Plugin for MovementPlugin {
 }
 
 #[cfg(feature = "dev")]
-fn pause_bike(mut q_bike: Query<(&mut BikeMarker, &ActionState<PlayerMovement>)>) {
-    for (mut bike, actions) in q_bike.iter_mut() {
+fn pause_bike(
+    q_players: Query<(&ClientIdMarker, &ActionState<PlayerMovement>), With<PlayerMarker>>,
+    mut q_bike: Query<(&ClientIdMarker, &mut BikeMarker)>,
+) {
+    for (client_id, actions) in q_players.iter() {
         if actions.just_pressed(&PlayerMovement::Pause) {
-            bike.paused = !bike.paused;
+            for (bike_client_id, mut bike) in q_bike.iter_mut() {
+                if bike_client_id == client_id {
+                    bike.paused = !bike.paused;
+                }
+            }
         }
     }
 }
@@ -69,31 +76,33 @@ fn move_bike_system(
     fixed_time: Res<Time<Fixed>>,
     // TODO: add spatial index
     q_zones: Query<(&Zones, &ClientIdMarker)>,
+    // the inputs are stored on the player entity, which outlives the bike
+    q_players: Query<
+        (&ClientIdMarker, &ActionState<PlayerMovement>),
+        (With<PlayerMarker>, Or<(With<Predicted>, With<Replicating>)>),
+    >,
     mut q_bike: Query<
         (
-            // TODO: do we need this?
             &ClientIdMarker,
             &BikeMarker,
             &mut Position,
             &mut Rotation,
             &mut LinearVelocity,
-            Has<Dead>,
-            &ActionState<PlayerMovement>,
         ),
         // apply inputs either on predicted entities on the client, or replicating entities on the server
-        (Or<(With<Predicted>, With<Replicating>)>, Without<Dead>),
+        Or<(With<Predicted>, With<Replicating>)>,
     >,
     // We can't use Parent directly because on the client the Parent is confirmed..
     trails: Query<(&ClientIdMarker, &Trail)>,
 ) {
     let mut zones = q_zones.iter();
-    for (client_id, marker, mut position, mut rotation, mut linear, dead, action_state) in
-        q_bike.iter_mut()
-    {
-        if dead {
-            *linear = LinearVelocity::default();
+    for (client_id, marker, mut position, mut rotation, mut linear) in q_bike.iter_mut() {
+        let Some((_, action_state)) = q_players
+            .iter()
+            .find(|(player_client_id, _)| *player_client_id == client_id)
+        else {
             continue;
-        }
+        };
         #[cfg(feature = "dev")]
         if marker.paused {
             *linear = LinearVelocity::default();
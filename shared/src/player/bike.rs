@@ -1,5 +1,4 @@
 use super::trail::Trail;
-use crate::player::scores::{Score, Stats};
 use crate::player::zone::Zones;
 use avian2d::math::Vector;
 use avian2d::prelude::*;
@@ -44,30 +43,21 @@ impl Default for ClientIdMarker {
     }
 }
 
-#[derive(Reflect, Component, Serialize, Deserialize, PartialEq, Debug, Clone)]
+/// Marker for the bike entity. A new bike is spawned for every life of a player.
+#[derive(Reflect, Component, Serialize, Deserialize, PartialEq, Default, Debug, Clone)]
 pub struct BikeMarker {
-    pub name: String,
     pub spawn_time: Duration,
+    /// Server tick at which the bike spawned, set by the server
+    pub spawn_tick: u16,
     #[cfg(feature = "dev")]
     pub paused: bool,
 }
 
 impl BikeMarker {
-    pub fn new(name: String, spawn_time: Duration) -> Self {
+    pub fn new(spawn_time: Duration) -> Self {
         Self {
-            name,
             spawn_time,
-            #[cfg(feature = "dev")]
-            paused: false,
-        }
-    }
-}
-
-impl Default for BikeMarker {
-    fn default() -> Self {
-        Self {
-            name: "Player".to_string(),
-            spawn_time: Duration::default(),
+            spawn_tick: 0,
             #[cfg(feature = "dev")]
             paused: false,
         }
@@ -81,26 +71,16 @@ pub struct BikeBundle {
     pub position: Position,
     pub rotation: Rotation,
     pub linear_velocity: LinearVelocity,
-    pub color: ColorComponent,
-    pub score: Score,
-    pub stats: Stats,
     pub name: Name,
 }
 
 impl BikeBundle {
-    pub fn new_at(
-        client_id: ClientId,
-        name: String,
-        position: Vec2,
-        color: Color,
-        spawn_time: Duration,
-    ) -> Self {
+    pub fn new_at(client_id: ClientId, position: Vec2, spawn_time: Duration) -> Self {
         // TODO: spawn at a random position on the map
         Self {
-            marker: BikeMarker::new(name, spawn_time),
+            marker: BikeMarker::new(spawn_time),
             client_id: ClientIdMarker(client_id),
             position: Position(position),
-            color: ColorComponent(color),
             linear_velocity: LinearVelocity(Vector::new(0.0, 0.0)),
             name: Name::from("Bike"),
             ..default()
@@ -1,5 +1,7 @@
-use bevy::prelude::{Component, Reflect};
-use lightyear::prelude::{Deserialize, Serialize};
+use crate::player::bike::{ClientIdMarker, ColorComponent};
+use crate::player::scores::{Score, Stats};
+use bevy::prelude::*;
+use lightyear::prelude::*;
 
 pub mod bike;
 
@@ -7,3 +9,50 @@ pub mod death;
 pub mod scores;
 pub mod trail;
 pub mod zone;
+
+/// Marker for the persistent player entity.
+///
+/// There is one player entity per connection; it owns everything that should survive a death
+/// (name, color, score, stats, inputs) while a new bike entity is spawned for every life.
+#[derive(Reflect, Component, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PlayerMarker {
+    pub name: String,
+}
+
+impl Default for PlayerMarker {
+    fn default() -> Self {
+        Self {
+            name: "Player".to_string(),
+        }
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct PlayerBundle {
+    pub marker: PlayerMarker,
+    pub client_id: ClientIdMarker,
+    pub color: ColorComponent,
+    pub score: Score,
+    pub stats: Stats,
+    pub name: Name,
+}
+
+impl PlayerBundle {
+    pub fn new(client_id: ClientId, name: String, color: Color) -> Self {
+        Self {
+            marker: PlayerMarker { name },
+            client_id: ClientIdMarker(client_id),
+            color: ColorComponent(color),
+            name: Name::from("Player"),
+            ..default()
+        }
+    }
+}
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PlayerMarker>();
+    }
+}
@@ -1,16 +1,21 @@
 use crate::physics::util::line_segments_intersect;
-use crate::player::bike::ClientIdMarker;
+use crate::physics::FixedSet;
+use crate::player::bike::{BikeMarker, ClientIdMarker};
+use crate::player::zone::{Zone, Zones};
+use avian2d::prelude::Position;
 use bevy::prelude::*;
-use bevy::utils::Duration;
 use bevy_prototype_lyon::prelude::{GeometryBuilder, Path, PathBuilder};
 use bevy_prototype_lyon::shapes;
-use lightyear::prelude::{ClientId, DeltaCompression};
+use lightyear::client::prediction::rollback::Rollback;
+use lightyear::client::prediction::Predicted;
+use lightyear::prelude::{ClientId, DeltaCompression, Replicating, Tick, TickManager};
 use lightyear::shared::replication::delta::Diffable;
 use serde::{Deserialize, Serialize};
 
 const MIN_POINT_DISTANCE: f32 = 50.0;
 
-pub const ADD_POINT_INTERVAL: Duration = Duration::from_millis(50);
+/// By default, add a point every 3 ticks (~50ms at 64Hz)
+pub const DEFAULT_TICKS_PER_POINT: u16 = 3;
 const MAX_LINE_POINTS: usize = 200;
 
 #[derive(Bundle, Debug)]
@@ -39,11 +44,114 @@ impl Default for TrailBundle {
     }
 }
 
+/// How often points are added to the trails.
+/// The server and the clients must use the same settings, otherwise the predicted trails diverge.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct TrailSettings {
+    pub ticks_per_point: u16,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            ticks_per_point: DEFAULT_TICKS_PER_POINT,
+        }
+    }
+}
+
+/// Number of ticks since the bike spawned, which decides on which ticks a point gets added to its
+/// trail. Counting from the spawn (and not from the global tick, which wraps around) keeps the
+/// interval between two points the same for the whole life of the bike.
+///
+/// The clock is not replicated: the server and the client both derive it from the spawn tick of
+/// the `BikeMarker`, and follow the tick from there
+#[derive(Component, Debug, Default)]
+pub struct TrailClock {
+    last_tick: u16,
+    ticks: Option<i64>,
+}
+
+impl TrailClock {
+    /// Ticks since the bike spawned, at `tick`
+    fn ticks_since_spawn(&mut self, bike: &BikeMarker, tick: Tick) -> i64 {
+        let ticks = match self.ticks {
+            // the difference is signed, since a rollback goes back a few ticks
+            Some(ticks) => ticks + tick.0.wrapping_sub(self.last_tick) as i16 as i64,
+            None => tick.0.wrapping_sub(bike.spawn_tick) as i64,
+        };
+        self.ticks = Some(ticks);
+        self.last_tick = tick.0;
+        ticks
+    }
+
+    /// Whether a point gets added to the trail of the bike at `tick`.
+    ///
+    /// Sampling is driven by the simulation tick (and not by a timer) so that identical inputs give
+    /// identical trails on the server and on the client, including when re-simulating during a
+    /// rollback.
+    pub fn is_trail_tick(
+        &mut self,
+        settings: &TrailSettings,
+        bike: &BikeMarker,
+        tick: Tick,
+    ) -> bool {
+        self.ticks_since_spawn(bike, tick)
+            .rem_euclid(settings.ticks_per_point.max(1) as i64)
+            == 0
+    }
+}
+
 pub struct TrailPlugin;
 
 impl Plugin for TrailPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<Trail>();
+        app.init_resource::<TrailSettings>();
+        app.observe(add_trail_clock);
+        // same schedule as the server's `mark_trail_system`
+        app.add_systems(FixedUpdate, predict_trail.after(FixedSet::Physics));
+    }
+}
+
+fn add_trail_clock(trigger: Trigger<OnAdd, BikeMarker>, mut commands: Commands) {
+    commands
+        .entity(trigger.entity())
+        .insert(TrailClock::default());
+}
+
+/// Predict the trail and the zones of our own bike on the client, with the same logic as the server.
+/// Cutting the zones of other players and killing them is left to the server.
+fn predict_trail(
+    settings: Res<TrailSettings>,
+    tick_manager: Res<TickManager>,
+    rollback: Option<Res<Rollback>>,
+    mut bikes: Query<
+        (&ClientIdMarker, &Position, &BikeMarker, &mut TrailClock),
+        (With<Predicted>, Without<Replicating>),
+    >,
+    // in host-server mode the server entities can also be predicted; the server already updates them
+    mut trails: Query<(&ClientIdMarker, &mut Trail), (With<Predicted>, Without<Replicating>)>,
+    mut zones: Query<(&ClientIdMarker, &mut Zones), (With<Predicted>, Without<Replicating>)>,
+) {
+    let tick = match rollback {
+        Some(rollback) => tick_manager.tick_or_rollback_tick(rollback.as_ref()),
+        None => tick_manager.tick(),
+    };
+    for (client_id, mut trail) in trails.iter_mut() {
+        let Some((_, position, bike, mut clock)) =
+            bikes.iter_mut().find(|(id, ..)| *id == client_id)
+        else {
+            continue;
+        };
+        if !clock.is_trail_tick(&settings, bike, tick) {
+            continue;
+        }
+        if let Some(shape) = trail.try_add_point(position.0) {
+            trail.line.clear();
+            if let Some((_, mut zones)) = zones.iter_mut().find(|(id, _)| *id == client_id) {
+                zones.add_zone(Zone::new(shape));
+            }
+        }
     }
 }
 
@@ -52,7 +160,9 @@ pub struct Trail {
     pub line: Vec<Vec2>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Default, Debug, Clone)]
+/// With the `compact_protocol` feature, the serde impls are in `network::compact`
+#[cfg_attr(not(feature = "compact_protocol"), derive(Serialize, Deserialize))]
+#[derive(PartialEq, Default, Debug, Clone)]
 pub struct TrailDiff {
     pub line_diff: Vec<Vec2>,
     /// If true, the `line_diff` is a new line instead of being an extension
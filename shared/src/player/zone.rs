@@ -1,9 +1,9 @@
 use crate::player::bike::ClientIdMarker;
 use crate::player::trail::Trail;
 use bevy::prelude::*;
-use bevy::utils::HashSet;
+use bevy::utils::{HashMap, HashSet};
 use bevy_prototype_lyon::prelude::*;
-use geo::{area::Area, BooleanOps, Contains, Coord, LineString};
+use geo::{area::Area, BooleanOps, Contains, Coord, LineString, Simplify};
 use geo_types::{MultiPolygon, Polygon};
 use lightyear::{prelude::*, shared::replication::delta::Diffable};
 use serde::{Deserialize, Serialize};
@@ -45,21 +45,33 @@ impl Plugin for ZonePlugin {
     }
 }
 
+/// Id of a zone, unique among the zones of a player
+pub type ZoneId = u32;
+
 #[derive(Reflect, Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct Zone {
+    /// Assigned by [`Zones`] when the zone is added; a zone keeps its id when it is merged with
+    /// another zone or cut, so that the diffs only contain the edited parts of its rings
+    pub id: ZoneId,
     pub exterior: Vec<Vec2>,
     pub interiors: Vec<Vec<Vec2>>,
 }
 
-// TODO: maybe use a hashmap<barycenter, Zone> so that computing the diff is quicker?
 #[derive(Reflect, Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Zones {
+    /// Sorted by id, so that the predicted zones (edited in place) and the confirmed ones (rebuilt
+    /// from the diffs) compare equal when they have the same content
     pub zones: Vec<Zone>,
+    /// Id of the next zone that is created
+    next_id: ZoneId,
 }
 
 impl Default for Zones {
     fn default() -> Self {
-        Zones { zones: Vec::new() }
+        Zones {
+            zones: Vec::new(),
+            next_id: 0,
+        }
     }
 }
 
@@ -72,7 +84,95 @@ impl Zones {
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct ZonesDiff {
     new_zones: Vec<Zone>,
-    removed_zones: Vec<Zone>,
+    removed_zones: Vec<ZoneId>,
+    changed_zones: Vec<ZoneEdit>,
+    next_id: ZoneId,
+}
+
+/// Edits of the rings of a zone that exists before and after the diff
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+struct ZoneEdit {
+    id: ZoneId,
+    /// Number of interior rings after the edit
+    interiors: u32,
+    /// Ring 0 is the exterior, ring `i + 1` is the interior `i`
+    rings: Vec<(u32, RingEdit)>,
+}
+
+/// Replace the points `start..start + removed` of a ring with `inserted`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+struct RingEdit {
+    start: u32,
+    removed: u32,
+    inserted: Vec<Vec2>,
+}
+
+impl RingEdit {
+    /// Only send the points between the common prefix and the common suffix of the two rings,
+    /// which is usually a single segment when a neighbour nibbles at the edge of a zone
+    fn new(old: &[Vec2], new: &[Vec2]) -> Option<Self> {
+        if old == new {
+            return None;
+        }
+        let prefix = old
+            .iter()
+            .zip(new.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        Some(RingEdit {
+            start: prefix as u32,
+            removed: (old.len() - prefix - suffix) as u32,
+            inserted: new[prefix..new.len() - suffix].to_vec(),
+        })
+    }
+
+    fn apply(&self, ring: &mut Vec<Vec2>) {
+        let start = (self.start as usize).min(ring.len());
+        let end = (start + self.removed as usize).min(ring.len());
+        ring.splice(start..end, self.inserted.iter().copied());
+    }
+}
+
+impl ZoneEdit {
+    fn new(old: &Zone, new: &Zone) -> Self {
+        let empty = Vec::new();
+        let mut rings = Vec::new();
+        if let Some(edit) = RingEdit::new(&old.exterior, &new.exterior) {
+            rings.push((0, edit));
+        }
+        for (i, interior) in new.interiors.iter().enumerate() {
+            let old_interior = old.interiors.get(i).unwrap_or(&empty);
+            if let Some(edit) = RingEdit::new(old_interior, interior) {
+                rings.push((i as u32 + 1, edit));
+            }
+        }
+        ZoneEdit {
+            id: new.id,
+            interiors: new.interiors.len() as u32,
+            rings,
+        }
+    }
+
+    fn apply(&self, zone: &mut Zone) {
+        zone.interiors
+            .resize_with(self.interiors as usize, Vec::new);
+        for (ring, edit) in self.rings.iter() {
+            match ring {
+                0 => edit.apply(&mut zone.exterior),
+                i => {
+                    if let Some(interior) = zone.interiors.get_mut(*i as usize - 1) {
+                        edit.apply(interior);
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl Diffable for Zones {
@@ -83,25 +183,47 @@ impl Diffable for Zones {
     }
 
     fn diff(&self, new: &Self) -> Self::Delta {
-        let mut diff = ZonesDiff::default();
+        let mut diff = ZonesDiff {
+            next_id: new.next_id,
+            ..default()
+        };
+        let old_zones: HashMap<ZoneId, &Zone> =
+            self.zones.iter().map(|zone| (zone.id, zone)).collect();
+        let new_ids: HashSet<ZoneId> = new.zones.iter().map(|zone| zone.id).collect();
         for zone in self.zones.iter() {
-            if !new.zones.contains(zone) {
-                diff.removed_zones.push(zone.clone());
+            if !new_ids.contains(&zone.id) {
+                diff.removed_zones.push(zone.id);
             }
         }
         for zone in new.zones.iter() {
-            // TODO: use a hashset?
-            if !self.zones.contains(zone) {
-                diff.new_zones.push(zone.clone());
+            match old_zones.get(&zone.id) {
+                None => diff.new_zones.push(zone.clone()),
+                Some(old_zone) if *old_zone != zone => {
+                    diff.changed_zones.push(ZoneEdit::new(old_zone, zone));
+                }
+                Some(_) => {}
             }
         }
         diff
     }
 
     fn apply_diff(&mut self, delta: &Self::Delta) {
-        self.zones
-            .retain(|zone| !delta.removed_zones.contains(zone));
+        let removed: HashSet<ZoneId> = delta.removed_zones.iter().copied().collect();
+        self.zones.retain(|zone| !removed.contains(&zone.id));
+        let index: HashMap<ZoneId, usize> = self
+            .zones
+            .iter()
+            .enumerate()
+            .map(|(i, zone)| (zone.id, i))
+            .collect();
+        for edit in delta.changed_zones.iter() {
+            if let Some(&i) = index.get(&edit.id) {
+                edit.apply(&mut self.zones[i]);
+            }
+        }
         self.zones.extend(delta.new_zones.iter().cloned());
+        self.sort_by_id();
+        self.next_id = delta.next_id;
     }
 }
 
@@ -163,8 +285,10 @@ impl From<&Zone> for Path {
 }
 
 impl Zone {
+    /// The id is assigned when the zone is added to [`Zones`]
     pub fn new(exterior: Vec<Vec2>) -> Self {
         Zone {
+            id: 0,
             exterior,
             interiors: Vec::new(),
         }
@@ -175,6 +299,25 @@ impl Zone {
         poly.unsigned_area() as f32
     }
 
+    /// Exterior ring with fewer points (Ramer-Douglas-Peucker), where no point moves more than `epsilon`
+    pub fn simplified_exterior(&self, epsilon: f32) -> Vec<Vec2> {
+        let exterior = LineString(
+            self.exterior
+                .iter()
+                .map(|p| Coord {
+                    x: p.x as f64,
+                    y: p.y as f64,
+                })
+                .collect(),
+        );
+        exterior
+            .simplify(&(epsilon as f64))
+            .0
+            .iter()
+            .map(|p| Vec2::new(p.x as f32, p.y as f32))
+            .collect()
+    }
+
     pub fn contains(&self, point: Vec2) -> bool {
         let poly = self.to_geo_polygon();
         poly.contains(&Coord {
@@ -211,7 +354,7 @@ impl Zone {
         Polygon::new(LineString(exterior), interiors)
     }
 
-    fn from_geo_polygon(poly: Polygon) -> Self {
+    fn from_geo_polygon(id: ZoneId, poly: Polygon) -> Self {
         let exterior: Vec<Vec2> = poly
             .exterior()
             .0
@@ -231,6 +374,7 @@ impl Zone {
             .collect();
 
         Zone {
+            id,
             exterior,
             interiors,
         }
@@ -242,38 +386,63 @@ impl Zones {
         self.zones.iter().map(|zone| zone.area()).sum()
     }
 
+    fn sort_by_id(&mut self) {
+        self.zones.sort_by_key(|zone| zone.id);
+    }
+
+    fn allocate_id(&mut self) -> ZoneId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
     pub fn add_zone(&mut self, new_zone: Zone) {
         let mut merged_zone = new_zone;
+        // the merged zone keeps the id of the largest zone it absorbs, so growing a territory
+        // only sends the edited segments of its ring
+        let mut merged_id: Option<(ZoneId, f32)> = None;
         self.zones.retain(|zone| {
             if Self::zones_overlap(zone, &merged_zone) {
                 merged_zone = Self::union_zones(&merged_zone, zone);
+                let area = zone.area();
+                if merged_id.map_or(true, |(_, largest)| area > largest) {
+                    merged_id = Some((zone.id, area));
+                }
                 false
             } else {
                 true
             }
         });
+        merged_zone.id = match merged_id {
+            Some((id, _)) => id,
+            None => self.allocate_id(),
+        };
         trace!(?merged_zone);
         self.zones.push(merged_zone);
+        self.sort_by_id();
     }
 
     pub fn cut_out_zones(&mut self, cut_zone: &Zone) {
         let stencil = cut_zone.to_geo_polygon();
         let mut new_zones = vec![];
-        for zone in self.zones.iter() {
+        for zone in std::mem::take(&mut self.zones) {
             let to_be_cut = zone.to_geo_polygon();
 
             match to_be_cut.difference(&stencil) {
-                MultiPolygon(polys) => {
-                    for poly in polys {
-                        if !poly.exterior().0.is_empty() {
-                            new_zones.push(Zone::from_geo_polygon(poly));
-                        }
+                MultiPolygon(mut polys) => {
+                    polys.retain(|poly| !poly.exterior().0.is_empty());
+                    // the piece with the most points keeps the id of the zone, the others are new zones
+                    polys.sort_by_key(|p| std::cmp::Reverse(p.exterior().0.len()));
+                    for (i, poly) in polys.into_iter().enumerate() {
+                        let id = if i == 0 { zone.id } else { self.allocate_id() };
+                        new_zones.push(Zone::from_geo_polygon(id, poly));
                     }
                 }
             }
         }
         trace!(?new_zones);
         self.zones = new_zones;
+        self.sort_by_id();
     }
 
     fn union_zones(zone1: &Zone, zone2: &Zone) -> Zone {
@@ -283,7 +452,7 @@ impl Zones {
         match poly1.union(&poly2) {
             MultiPolygon(mut polys) if !polys.is_empty() => {
                 polys.sort_by_key(|p| std::cmp::Reverse(p.exterior().0.len()));
-                Zone::from_geo_polygon(polys.remove(0))
+                Zone::from_geo_polygon(zone1.id, polys.remove(0))
             }
             _ => zone1.clone(),
         }
@@ -298,3 +467,104 @@ impl Zones {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(center: Vec2, half_size: f32) -> Zone {
+        Zone::new(vec![
+            center + Vec2::new(-half_size, -half_size),
+            center + Vec2::new(half_size, -half_size),
+            center + Vec2::new(half_size, half_size),
+            center + Vec2::new(-half_size, half_size),
+        ])
+    }
+
+    /// Applying the diff from `old` to `new` on `old` gives `new`, with the zones in the same order
+    fn round_trip(old: &Zones, new: &Zones) -> ZonesDiff {
+        let diff = old.diff(new);
+        let mut applied = old.clone();
+        applied.apply_diff(&diff);
+        assert_eq!(applied, *new);
+        diff
+    }
+
+    #[test]
+    fn new_zones() {
+        let mut zones = Zones::default();
+        zones.add_zone(square(Vec2::ZERO, 10.0));
+        zones.add_zone(square(Vec2::new(100.0, 0.0), 10.0));
+        let diff = round_trip(&Zones::default(), &zones);
+        assert_eq!(diff.new_zones.len(), 2);
+    }
+
+    #[test]
+    fn grown_zone_keeps_its_id() {
+        let mut old = Zones::default();
+        old.add_zone(square(Vec2::ZERO, 10.0));
+        let mut new = old.clone();
+        new.add_zone(square(Vec2::new(10.0, 0.0), 5.0));
+        let diff = round_trip(&old, &new);
+        assert!(diff.new_zones.is_empty());
+        assert!(diff.removed_zones.is_empty());
+        assert_eq!(diff.changed_zones.len(), 1);
+    }
+
+    /// Growing a zone that is not the last one must not reorder the zones, otherwise the predicted
+    /// zones differ from the confirmed ones and trigger a rollback
+    #[test]
+    fn predicted_zones_match_the_confirmed_ones() {
+        let mut old = Zones::default();
+        old.add_zone(square(Vec2::ZERO, 10.0));
+        old.add_zone(square(Vec2::new(100.0, 0.0), 10.0));
+        let mut predicted = old.clone();
+        predicted.add_zone(square(Vec2::new(10.0, 0.0), 5.0));
+        let mut confirmed = old.clone();
+        confirmed.apply_diff(&old.diff(&predicted));
+        assert_eq!(confirmed, predicted);
+        assert_eq!(predicted.zones[0].id, 0);
+    }
+
+    #[test]
+    fn interiors() {
+        let mut old = Zones::default();
+        old.add_zone(square(Vec2::ZERO, 10.0));
+        let mut new = old.clone();
+        new.cut_out_zones(&square(Vec2::ZERO, 2.0));
+        assert_eq!(new.zones[0].interiors.len(), 1);
+        round_trip(&old, &new);
+        // and back, when the hole is filled
+        round_trip(&new, &old);
+    }
+
+    #[test]
+    fn zone_split_by_a_cut() {
+        let mut old = Zones::default();
+        old.add_zone(square(Vec2::ZERO, 10.0));
+        let mut new = old.clone();
+        new.cut_out_zones(&Zone::new(vec![
+            Vec2::new(-1.0, -20.0),
+            Vec2::new(1.0, -20.0),
+            Vec2::new(1.0, 20.0),
+            Vec2::new(-1.0, 20.0),
+        ]));
+        assert_eq!(new.zones.len(), 2);
+        let diff = round_trip(&old, &new);
+        assert_eq!(diff.new_zones.len(), 1);
+        assert_eq!(diff.changed_zones.len(), 1);
+    }
+
+    #[test]
+    fn removed_zones() {
+        let mut old = Zones::default();
+        old.add_zone(square(Vec2::ZERO, 10.0));
+        old.add_zone(square(Vec2::new(100.0, 0.0), 10.0));
+        let mut new = old.clone();
+        new.cut_out_zones(&square(Vec2::ZERO, 20.0));
+        assert_eq!(new.zones.len(), 1);
+        let diff = round_trip(&old, &new);
+        assert_eq!(diff.removed_zones.len(), 1);
+        round_trip(&new, &Zones::default());
+    }
+}
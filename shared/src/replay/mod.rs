@@ -0,0 +1,154 @@
+//! Compact recording of a match.
+//!
+//! The server records one [`ReplayFrame`] per simulation tick, and the client can play the
+//! file back by feeding the recorded state into the usual bike/trail/zones components.
+//!
+//! A file is a [`ReplayHeader`] followed by the frames, one after the other, so that the server
+//! can append the frames while the match is running.
+use crate::network::message::ChatMessage;
+use crate::player::trail::TrailDiff;
+use crate::player::zone::ZonesDiff;
+use bevy::prelude::*;
+use lightyear::prelude::ClientId;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Bump this when the replay format changes, so that old files are rejected instead of misread
+pub const REPLAY_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReplayHeader {
+    version: u32,
+    frame_duration: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub version: u32,
+    /// Duration of a frame in seconds (the fixed timestep of the server)
+    pub frame_duration: f32,
+    pub frames: Vec<ReplayFrame>,
+}
+
+/// State of the match during one tick
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReplayFrame {
+    /// Every bike alive during this tick
+    pub bikes: Vec<BikeFrame>,
+    /// Everything else that happened during this tick
+    pub events: Vec<ReplayEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BikeFrame {
+    pub client_id: ClientId,
+    pub position: Vec2,
+    /// Rotation angle in radians
+    pub rotation: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ReplayEvent {
+    PlayerJoined {
+        client_id: ClientId,
+        name: String,
+        color: Color,
+    },
+    PlayerLeft {
+        client_id: ClientId,
+    },
+    /// The trail changed; stored as a diff from the previous recorded trail
+    Trail {
+        client_id: ClientId,
+        diff: TrailDiff,
+    },
+    /// The zones changed; stored as a diff from the previous recorded zones
+    Zones {
+        client_id: ClientId,
+        diff: ZonesDiff,
+    },
+    Kill {
+        killer: ClientId,
+        killed: ClientId,
+        position: Vec2,
+    },
+    Chat(ChatMessage),
+}
+
+impl Replay {
+    pub fn new(frame_duration: f32) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            frame_duration,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Total duration of the replay in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames.len() as f32 * self.frame_duration
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = ReplayWriter::create(path, self.frame_duration)?;
+        for frame in self.frames.iter() {
+            writer.write_frame(frame)?;
+        }
+        writer.flush()
+    }
+
+    /// A file whose last frame is incomplete (the server stopped while writing it) is loaded
+    /// without that frame
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header: ReplayHeader =
+            bincode::deserialize_from(&mut reader).map_err(io::Error::other)?;
+        if header.version != REPLAY_VERSION {
+            return Err(io::Error::other(format!(
+                "unsupported replay version {} (expected {})",
+                header.version, REPLAY_VERSION
+            )));
+        }
+        let mut replay = Replay::new(header.frame_duration);
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(frame) => replay.frames.push(frame),
+                Err(error) => match *error {
+                    bincode::ErrorKind::Io(error)
+                        if error.kind() == io::ErrorKind::UnexpectedEof =>
+                    {
+                        return Ok(replay);
+                    }
+                    error => return Err(io::Error::other(error)),
+                },
+            }
+        }
+    }
+}
+
+/// Write a replay file frame by frame, without keeping the frames in memory
+pub struct ReplayWriter {
+    writer: BufWriter<File>,
+}
+
+impl ReplayWriter {
+    pub fn create(path: &Path, frame_duration: f32) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = ReplayHeader {
+            version: REPLAY_VERSION,
+            frame_duration,
+        };
+        bincode::serialize_into(&mut writer, &header).map_err(io::Error::other)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_frame(&mut self, frame: &ReplayFrame) -> io::Result<()> {
+        bincode::serialize_into(&mut self.writer, frame).map_err(io::Error::other)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}